@@ -0,0 +1,199 @@
+// Authenticode hash of PE/COFF images, the digest UEFI firmware measures when it loads an EFI
+// application such as shim, grub or a Linux bzImage with its EFI stub.
+use anyhow::*;
+use sha2::{Digest, Sha384};
+use std::result::Result;
+use std::result::Result::Ok;
+
+const PE_SIGNATURE: &[u8; 4] = b"PE\0\0";
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const COFF_HEADER_LEN: usize = 20;
+const SECTION_HEADER_LEN: usize = 40;
+const CERTIFICATE_TABLE_INDEX: usize = 4;
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, anyhow::Error> {
+    match bytes.get(offset..offset + 2) {
+        None => Err(anyhow!(
+            "[authenticode] Truncated PE image at {:#x}",
+            offset
+        )),
+        Some(b) => Ok(u16::from_le_bytes(b.try_into().unwrap())),
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, anyhow::Error> {
+    match bytes.get(offset..offset + 4) {
+        None => Err(anyhow!(
+            "[authenticode] Truncated PE image at {:#x}",
+            offset
+        )),
+        Some(b) => Ok(u32::from_le_bytes(b.try_into().unwrap())),
+    }
+}
+
+fn range(image: &[u8], start: usize, end: usize) -> Result<&[u8], anyhow::Error> {
+    match image.get(start..end) {
+        None => Err(anyhow!(
+            "[authenticode] Range {:#x}..{:#x} is outside the {:#x} byte PE image",
+            start,
+            end,
+            image.len()
+        )),
+        Some(r) => Ok(r),
+    }
+}
+
+/// SHA384 Authenticode digest of a PE/COFF image, as EDK2 measures images it loads: the
+/// headers without the checksum and the certificate table entry, the sections in file order,
+/// then any data after the last section other than the attribute certificates.
+pub fn authenticode_sha384(image: &[u8]) -> Result<[u8; 48], anyhow::Error> {
+    if image.get(0..2) != Some(b"MZ".as_slice()) {
+        return Err(anyhow!("[authenticode] No MZ header, not a PE image"));
+    }
+    let pe = u32_at(image, 0x3c)? as usize;
+    if image.get(pe..pe + 4) != Some(PE_SIGNATURE.as_slice()) {
+        return Err(anyhow!("[authenticode] No PE signature at {:#x}", pe));
+    }
+    let coff = pe + 4;
+    let section_count = u16_at(image, coff + 2)? as usize;
+    let optional_header_len = u16_at(image, coff + 16)? as usize;
+    let optional = coff + COFF_HEADER_LEN;
+    let (rva_count_offset, directories) = match u16_at(image, optional)? {
+        PE32_MAGIC => (optional + 92, optional + 96),
+        PE32_PLUS_MAGIC => (optional + 108, optional + 112),
+        magic => {
+            return Err(anyhow!(
+                "[authenticode] Unknown optional header magic {:#x}",
+                magic
+            ))
+        }
+    };
+    let checksum = optional + 64;
+    let headers_len = u32_at(image, optional + 60)? as usize;
+    let rva_count = u32_at(image, rva_count_offset)? as usize;
+
+    let mut hasher = Sha384::new();
+    hasher.update(range(image, 0, checksum)?);
+    let mut certificates_len = 0;
+    if rva_count > CERTIFICATE_TABLE_INDEX {
+        let certificate_entry = directories + CERTIFICATE_TABLE_INDEX * 8;
+        certificates_len = u32_at(image, certificate_entry + 4)? as usize;
+        hasher.update(range(image, checksum + 4, certificate_entry)?);
+        hasher.update(range(image, certificate_entry + 8, headers_len)?);
+    } else {
+        hasher.update(range(image, checksum + 4, headers_len)?);
+    }
+
+    let section_table = optional + optional_header_len;
+    let mut sections = Vec::new();
+    for i in 0..section_count {
+        let header = section_table + i * SECTION_HEADER_LEN;
+        let raw_size = u32_at(image, header + 16)? as usize;
+        let raw_offset = u32_at(image, header + 20)? as usize;
+        if raw_size != 0 {
+            sections.push((raw_offset, raw_size));
+        }
+    }
+    sections.sort();
+    let mut hashed = headers_len;
+    for (offset, size) in sections {
+        hasher.update(range(image, offset, offset + size)?);
+        hashed += size;
+    }
+
+    if image.len() > hashed + certificates_len {
+        hasher.update(range(image, hashed, image.len() - certificates_len)?);
+    }
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    // PE32+ EFI application: two sections listed out of file order, data after the last section
+    // and a certificate table at the end of the file
+    const FIXTURE: &str = "tests/data/pe32plus.efi";
+    // hashed independently with Python's hashlib, following the Authenticode specification
+    const FIXTURE_DIGEST: &str = "71e5ce9356b99e46d1cff3b341099fb47994ff6eb756cf8b416a960e8af1b06838a5575041cc56870da75e22d3ceb497";
+    const OPTIONAL_HEADER: usize = 0x40 + 4 + COFF_HEADER_LEN;
+    const CERTIFICATE_TABLE: usize = 0x640;
+    const TRAILING_DATA: usize = 0x600;
+
+    fn fixture() -> Vec<u8> {
+        fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join(FIXTURE)).unwrap()
+    }
+
+    #[test]
+    fn known_digest() {
+        assert_eq!(
+            hex::encode(authenticode_sha384(&fixture()).unwrap()),
+            FIXTURE_DIGEST
+        );
+    }
+
+    #[test]
+    fn excluded_fields() {
+        let digest = authenticode_sha384(&fixture()).unwrap();
+
+        let mut image = fixture();
+        image[OPTIONAL_HEADER + 64] ^= 0xff; // checksum
+        image[CERTIFICATE_TABLE + 8] ^= 0xff; // signature
+        assert_eq!(authenticode_sha384(&image).unwrap(), digest);
+
+        // the certificate table entry is excluded, its size decides where hashing stops
+        let mut image = fixture();
+        let entry = OPTIONAL_HEADER + 112 + CERTIFICATE_TABLE_INDEX * 8;
+        image[entry + 4..entry + 8].copy_from_slice(&0x40u32.to_le_bytes());
+        assert_ne!(authenticode_sha384(&image).unwrap(), digest);
+        image[entry + 4..entry + 8].copy_from_slice(&0x48u32.to_le_bytes());
+        image[entry] ^= 0xff;
+        assert_eq!(authenticode_sha384(&image).unwrap(), digest);
+
+        let mut image = fixture();
+        image[TRAILING_DATA] ^= 0xff;
+        assert_ne!(authenticode_sha384(&image).unwrap(), digest);
+    }
+
+    #[test]
+    fn malformed_images() {
+        let image = fixture();
+        assert!(authenticode_sha384(&image[..0x100])
+            .unwrap_err()
+            .to_string()
+            .contains("outside the 0x100 byte PE image"));
+
+        let mut bad = image.clone();
+        bad[0] = b'X';
+        assert!(authenticode_sha384(&bad)
+            .unwrap_err()
+            .to_string()
+            .contains("No MZ header"));
+
+        let mut bad = image.clone();
+        bad[0x40] = b'X';
+        assert!(authenticode_sha384(&bad)
+            .unwrap_err()
+            .to_string()
+            .contains("No PE signature"));
+
+        let mut bad = image.clone();
+        bad[OPTIONAL_HEADER..OPTIONAL_HEADER + 2].copy_from_slice(&0x107u16.to_le_bytes());
+        assert!(authenticode_sha384(&bad)
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown optional header magic 0x107"));
+
+        // the first section header's raw data size running past the end of the file
+        let mut bad = image;
+        let section = OPTIONAL_HEADER + 0xf0;
+        bad[section + 16..section + 20].copy_from_slice(&0x10000u32.to_le_bytes());
+        assert!(authenticode_sha384(&bad)
+            .unwrap_err()
+            .to_string()
+            .contains("Range 0x400..0x10400"));
+    }
+}
@@ -0,0 +1,345 @@
+// A stand-in for the host Quote Generation Service: answers the QGS wire protocol with quotes
+// from a simulated platform, so the quote flow can run end to end without a TDX host.
+use anyhow::*;
+use ioctl::qgs::*;
+use ioctl::qgs_socket::{QgsAddress, QGS_VSOCK_PORT};
+use ioctl::simulator::*;
+use ioctl::verify::load_pem_chain;
+use nix::sys::socket::{
+    accept, bind, listen, socket, AddressFamily, SockFlag, SockType, VsockAddr,
+};
+use p256::ecdsa::SigningKey;
+use p256::pkcs8::DecodePrivateKey;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::result::Result;
+use std::result::Result::Ok;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use x509_cert::der::{pem::LineEnding, EncodePem};
+
+const USAGE: &str = "usage: qgs-emulator [--unix PATH | --tcp ADDR | --vsock PORT]
+    [--attestation-key PEM] [--pck-key PEM --pck-chain PEM] [--root-cert-out PATH]
+    [--qe-svn N] [--pce-svn N] [--fmspc HEX]
+    [--error-code CODE] [--fail-count N] [--delay-ms MS] [--drop-connection]
+
+Listens on vsock port 4050 by default. Keys are PKCS#8 PEM, the PCK chain is PEM with the leaf
+first. Without --pck-key a fresh chain is generated; --root-cert-out writes its root for verifiers.
+--error-code answers requests with that QGS error code, only the first N with --fail-count.
+--delay-ms holds every response, --drop-connection closes the connection instead of answering.";
+
+// QGS reads a whole message before answering, bound what a client can make us allocate
+const MAX_REQUEST_LEN: u32 = 1 << 20;
+const VMADDR_CID_ANY: u32 = u32::MAX;
+
+struct Options {
+    address: QgsAddress,
+    attestation_key: Option<PathBuf>,
+    pck_key: Option<PathBuf>,
+    pck_chain: Option<PathBuf>,
+    root_cert_out: Option<PathBuf>,
+    config: SimulatedTdConfig,
+    faults: Faults,
+}
+
+#[derive(Default)]
+struct Faults {
+    error_code: Option<u32>,
+    fail_count: Option<u64>,
+    failed: AtomicU64,
+    delay: Duration,
+    drop_connection: bool,
+}
+
+impl Faults {
+    // the error to answer this request with, counting it against --fail-count
+    fn injected_error(&self) -> Option<u32> {
+        let code = self.error_code?;
+        let failed = self.failed.fetch_add(1, Ordering::SeqCst);
+        match self.fail_count {
+            Some(count) if failed >= count => None,
+            _ => Some(code),
+        }
+    }
+}
+
+fn parse_u32(value: &str) -> Result<u32, anyhow::Error> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    match parsed {
+        Err(e) => Err(anyhow!("[qgs-emulator] Bad number {}: {:?}", value, e)),
+        Ok(v) => Ok(v),
+    }
+}
+
+fn parse_args() -> Result<Options, anyhow::Error> {
+    let mut options = Options {
+        address: QgsAddress::Vsock {
+            cid: VMADDR_CID_ANY,
+            port: QGS_VSOCK_PORT,
+        },
+        attestation_key: None,
+        pck_key: None,
+        pck_chain: None,
+        root_cert_out: None,
+        config: SimulatedTdConfig::default(),
+        faults: Faults::default(),
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        if arg == "--drop-connection" {
+            options.faults.drop_connection = true;
+            continue;
+        }
+        let value = match args.next() {
+            None => return Err(anyhow!("[qgs-emulator] {} needs a value\n{}", arg, USAGE)),
+            Some(v) => v,
+        };
+        match arg.as_str() {
+            "--unix" => options.address = QgsAddress::Unix(PathBuf::from(value)),
+            "--tcp" => options.address = QgsAddress::Tcp(value),
+            "--vsock" => {
+                options.address = QgsAddress::Vsock {
+                    cid: VMADDR_CID_ANY,
+                    port: parse_u32(&value)?,
+                }
+            }
+            "--attestation-key" => options.attestation_key = Some(PathBuf::from(value)),
+            "--pck-key" => options.pck_key = Some(PathBuf::from(value)),
+            "--pck-chain" => options.pck_chain = Some(PathBuf::from(value)),
+            "--root-cert-out" => options.root_cert_out = Some(PathBuf::from(value)),
+            "--qe-svn" => options.config.qe_svn = parse_u32(&value)? as u16,
+            "--pce-svn" => options.config.pck.pce_svn = parse_u32(&value)? as u16,
+            "--fmspc" => {
+                options.config.pck.fmspc = match hex::decode(&value).map(|v| v.try_into()) {
+                    Ok(Ok(fmspc)) => fmspc,
+                    _ => return Err(anyhow!("[qgs-emulator] Bad FMSPC {}", value)),
+                }
+            }
+            "--error-code" => options.faults.error_code = Some(parse_u32(&value)?),
+            "--fail-count" => options.faults.fail_count = Some(parse_u32(&value)? as u64),
+            "--delay-ms" => options.faults.delay = Duration::from_millis(parse_u32(&value)? as u64),
+            _ => return Err(anyhow!("[qgs-emulator] Unknown option {}\n{}", arg, USAGE)),
+        }
+    }
+    if options.pck_key.is_some() != options.pck_chain.is_some() {
+        return Err(anyhow!(
+            "[qgs-emulator] --pck-key and --pck-chain go together"
+        ));
+    }
+    Ok(options)
+}
+
+fn load_key(path: &PathBuf) -> Result<SigningKey, anyhow::Error> {
+    let pem = match fs::read_to_string(path) {
+        Err(e) => return Err(anyhow!("[qgs-emulator] Fail to read {:?}: {:?}", path, e)),
+        Ok(p) => p,
+    };
+    match SigningKey::from_pkcs8_pem(&pem) {
+        Err(e) => Err(anyhow!("[qgs-emulator] Bad key {:?}: {:?}", path, e)),
+        Ok(k) => Ok(k),
+    }
+}
+
+fn build_platform(options: &Options) -> Result<SimulatedBackend, anyhow::Error> {
+    let attestation_key = match &options.attestation_key {
+        Some(path) => load_key(path)?,
+        None => SigningKey::random(&mut rand_core::OsRng),
+    };
+    let (pck_key, pck_chain) = match (&options.pck_key, &options.pck_chain) {
+        (Some(key), Some(chain)) => {
+            let pem = match fs::read(chain) {
+                Err(e) => return Err(anyhow!("[qgs-emulator] Fail to read {:?}: {:?}", chain, e)),
+                Ok(p) => p,
+            };
+            (load_key(key)?, load_pem_chain(&pem)?)
+        }
+        _ => simulated_pck_chain(&options.config)?,
+    };
+    SimulatedBackend::with_keys(options.config.clone(), attestation_key, pck_key, pck_chain)
+}
+
+struct Emulator {
+    platform: SimulatedBackend,
+    faults: Faults,
+}
+
+// the response of request's type carrying only error_code
+fn error_response(request_type: u32, error_code: u32) -> QgsMessage {
+    match request_type {
+        QGS_MSG_GET_COLLATERAL_REQ => QgsMessage::GetCollateralResponse {
+            error_code,
+            major_version: QGS_COLLATERAL_MAJOR_VER,
+            minor_version: QGS_COLLATERAL_MINOR_VER,
+            collateral: QgsCollateral::default(),
+        },
+        QGS_MSG_GET_PLATFORM_INFO_REQ => QgsMessage::GetPlatformInfoResponse {
+            error_code,
+            tdqe_isvsvn: 0,
+            pce_isvsvn: 0,
+            platform_id: Vec::new(),
+            cpusvn: Vec::new(),
+        },
+        _ => QgsMessage::GetQuoteResponse {
+            error_code,
+            selected_id: Vec::new(),
+            quote: Vec::new(),
+        },
+    }
+}
+
+impl Emulator {
+    fn respond(&self, msg: &[u8]) -> QgsMessage {
+        let request = match QgsMessage::decode(msg) {
+            Err(e) => {
+                eprintln!("[qgs-emulator] Bad request: {}", e);
+                return error_response(QGS_MSG_GET_QUOTE_REQ, QGS_MSG_ERROR_INVALID_PARAMETER);
+            }
+            Ok(r) => r,
+        };
+        if let Some(code) = self.faults.injected_error() {
+            return error_response(request.msg_type(), code);
+        }
+
+        match request {
+            QgsMessage::GetQuoteRequest { report, id_list } => {
+                match self.platform.quote_for_unverified_report(&report) {
+                    Err(e) => {
+                        eprintln!("[qgs-emulator] Bad TDREPORT: {}", e);
+                        error_response(QGS_MSG_GET_QUOTE_REQ, SGX_QL_INVALID_REPORT)
+                    }
+                    Ok(quote) => QgsMessage::GetQuoteResponse {
+                        error_code: QGS_MSG_SUCCESS,
+                        //the only key we have stands in for the first id offered
+                        selected_id: match id_list.len() {
+                            0 | 1 => Vec::new(),
+                            _ => id_list[0].clone(),
+                        },
+                        quote,
+                    },
+                }
+            }
+            QgsMessage::GetPlatformInfoRequest => {
+                let config = self.platform.config();
+                QgsMessage::GetPlatformInfoResponse {
+                    error_code: QGS_MSG_SUCCESS,
+                    tdqe_isvsvn: config.qe_svn,
+                    pce_isvsvn: config.pck.pce_svn,
+                    platform_id: [0; 16].to_vec(),
+                    cpusvn: config.pck.cpusvn.to_vec(),
+                }
+            }
+            //no PCCS behind the emulator
+            QgsMessage::GetCollateralRequest { .. } => {
+                error_response(QGS_MSG_GET_COLLATERAL_REQ, SGX_QL_NO_PLATFORM_CERT_DATA)
+            }
+            request => error_response(request.msg_type(), QGS_MSG_ERROR_INVALID_TYPE),
+        }
+    }
+
+    // length prefixed request/response pairs until the client hangs up
+    fn serve<S: Read + Write>(&self, mut stream: S) -> io::Result<()> {
+        loop {
+            let mut len_bytes = [0; 4];
+            match stream.read_exact(&mut len_bytes) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+                Ok(()) => (),
+            }
+            let len = u32::from_be_bytes(len_bytes);
+            if len > MAX_REQUEST_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("request of {} bytes", len),
+                ));
+            }
+            let mut msg = vec![0; len as usize];
+            stream.read_exact(&mut msg)?;
+
+            thread::sleep(self.faults.delay);
+            if self.faults.drop_connection {
+                return Ok(());
+            }
+            let response = self.respond(&msg).encode();
+            stream.write_all(&(response.len() as u32).to_be_bytes())?;
+            stream.write_all(&response)?;
+        }
+    }
+}
+
+fn spawn<S: Read + Write + Send + 'static>(emulator: &Arc<Emulator>, stream: S) {
+    let emulator = emulator.clone();
+    thread::spawn(move || {
+        if let Err(e) = emulator.serve(stream) {
+            eprintln!("[qgs-emulator] Connection failed: {}", e);
+        }
+    });
+}
+
+fn run(address: &QgsAddress, emulator: Arc<Emulator>) -> Result<(), anyhow::Error> {
+    match address {
+        QgsAddress::Unix(path) => {
+            let _ = fs::remove_file(path);
+            let listener = UnixListener::bind(path)?;
+            for stream in listener.incoming() {
+                spawn(&emulator, stream?);
+            }
+        }
+        QgsAddress::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            for stream in listener.incoming() {
+                spawn(&emulator, stream?);
+            }
+        }
+        QgsAddress::Vsock { cid, port } => {
+            let fd = socket(
+                AddressFamily::Vsock,
+                SockType::Stream,
+                SockFlag::SOCK_CLOEXEC,
+                None,
+            )?;
+            let listener = unsafe { File::from_raw_fd(fd) };
+            bind(listener.as_raw_fd(), &VsockAddr::new(*cid, *port))?;
+            listen(listener.as_raw_fd(), 16)?;
+            loop {
+                let stream = accept(listener.as_raw_fd())?;
+                spawn(&emulator, unsafe { File::from_raw_fd(stream) });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let options = parse_args()?;
+    let platform = build_platform(&options)?;
+    if let Some(path) = &options.root_cert_out {
+        let pem = match platform.root_certificate().to_pem(LineEnding::LF) {
+            Err(e) => return Err(anyhow!("[qgs-emulator] Fail to encode root: {:?}", e)),
+            Ok(p) => p,
+        };
+        fs::write(path, pem)?;
+    }
+
+    eprintln!("[qgs-emulator] Listening on {}", options.address);
+    let emulator = Arc::new(Emulator {
+        platform,
+        faults: options.faults,
+    });
+    run(&options.address, emulator)
+}
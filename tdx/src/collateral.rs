@@ -0,0 +1,1098 @@
+use crate::quote::*;
+use crate::verify::*;
+use anyhow::*;
+use der::asn1::{Any, ObjectIdentifier, OctetString};
+use der::{Decode, Encode, Sequence};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::result::Result;
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::time::SystemTime;
+use x509_cert::Certificate;
+
+// Intel SGX PCK certificate extensions, see "Intel SGX PCK Certificate and CRL Profile"
+pub const SGX_EXTENSIONS_OID: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.2.840.113741.1.13.1");
+const SGX_TCB_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113741.1.13.1.2");
+const SGX_PCESVN_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113741.1.13.1.2.17");
+const SGX_CPUSVN_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113741.1.13.1.2.18");
+const SGX_PCEID_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113741.1.13.1.3");
+const SGX_FMSPC_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113741.1.13.1.4");
+
+// file names used by Collateral::load_dir
+pub const TCB_INFO_FILE: &str = "tcb_info.json";
+pub const QE_IDENTITY_FILE: &str = "qe_identity.json";
+pub const TCB_SIGNING_CHAIN_FILE: &str = "tcb_signing_chain.pem";
+
+#[derive(Sequence)]
+struct SgxExtension {
+    id: ObjectIdentifier,
+    value: Any,
+}
+
+/// Platform identity and TCB carried in the SGX extensions of a PCK leaf certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PckExtensions {
+    pub sgx_tcb_components: [u8; 16],
+    pub pce_svn: u16,
+    pub cpusvn: [u8; 16],
+    pub pce_id: [u8; 2],
+    pub fmspc: [u8; 6],
+}
+
+fn octets<const N: usize>(value: &Any, what: &str) -> Result<[u8; N], anyhow::Error> {
+    let octets = match value.decode_as::<OctetString>() {
+        Err(e) => return Err(anyhow!("[PckExtensions] Bad {}: {:?}", what, e)),
+        Ok(o) => o,
+    };
+    match octets.as_bytes().try_into() {
+        Err(_) => Err(anyhow!(
+            "[PckExtensions] Wrong {} size: {}",
+            what,
+            octets.as_bytes().len()
+        )),
+        Ok(a) => Ok(a),
+    }
+}
+
+fn integer<'a, T: der::DecodeValue<'a> + der::FixedTag>(
+    value: &'a Any,
+    what: &str,
+) -> Result<T, anyhow::Error> {
+    match value.decode_as::<T>() {
+        Err(e) => Err(anyhow!("[PckExtensions] Bad {}: {:?}", what, e)),
+        Ok(v) => Ok(v),
+    }
+}
+
+impl PckExtensions {
+    pub fn from_certificate(cert: &Certificate) -> Result<Self, anyhow::Error> {
+        let ext = match cert
+            .tbs_certificate
+            .extensions
+            .iter()
+            .flatten()
+            .find(|e| e.extn_id == SGX_EXTENSIONS_OID)
+        {
+            None => {
+                return Err(anyhow!(
+                    "[PckExtensions] {} has no SGX extensions",
+                    subject_name(cert)
+                ))
+            }
+            Some(e) => e,
+        };
+        let entries = match Vec::<SgxExtension>::from_der(ext.extn_value.as_bytes()) {
+            Err(e) => return Err(anyhow!("[PckExtensions] Bad SGX extensions: {:?}", e)),
+            Ok(v) => v,
+        };
+
+        let mut pck = PckExtensions {
+            sgx_tcb_components: [0; 16],
+            pce_svn: 0,
+            cpusvn: [0; 16],
+            pce_id: [0; 2],
+            fmspc: [0; 6],
+        };
+        let (mut has_tcb, mut has_pce_id, mut has_fmspc) = (false, false, false);
+        for entry in entries {
+            if entry.id == SGX_TCB_OID {
+                has_tcb = true;
+                let tcb = match entry.value.decode_as::<Vec<SgxExtension>>() {
+                    Err(e) => return Err(anyhow!("[PckExtensions] Bad TCB: {:?}", e)),
+                    Ok(v) => v,
+                };
+                for comp in tcb {
+                    if comp.id == SGX_PCESVN_OID {
+                        pck.pce_svn = integer(&comp.value, "PCESVN")?;
+                    } else if comp.id == SGX_CPUSVN_OID {
+                        pck.cpusvn = octets(&comp.value, "CPUSVN")?;
+                    } else if comp.id.parent() == Some(SGX_TCB_OID) {
+                        //components 1..16 are the last arc of the OID
+                        let index = comp.id.arcs().last().unwrap_or(0) as usize;
+                        if (1..=16).contains(&index) {
+                            pck.sgx_tcb_components[index - 1] =
+                                integer(&comp.value, "TCB component")?;
+                        }
+                    }
+                }
+            } else if entry.id == SGX_PCEID_OID {
+                has_pce_id = true;
+                pck.pce_id = octets(&entry.value, "PCE-ID")?;
+            } else if entry.id == SGX_FMSPC_OID {
+                has_fmspc = true;
+                pck.fmspc = octets(&entry.value, "FMSPC")?;
+            }
+        }
+        if !(has_tcb && has_pce_id && has_fmspc) {
+            return Err(anyhow!(
+                "[PckExtensions] {} lacks TCB, PCE-ID or FMSPC",
+                subject_name(cert)
+            ));
+        }
+        Ok(pck)
+    }
+
+    // DER value of the SGX extension, e.g. to mint a PCK certificate for a simulated platform
+    pub fn to_der(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let mut tcb = Vec::new();
+        for (i, svn) in self.sgx_tcb_components.iter().enumerate() {
+            let id = match SGX_TCB_OID.push_arc(i as u32 + 1) {
+                Err(e) => return Err(anyhow!("[PckExtensions] Bad TCB component OID: {:?}", e)),
+                Ok(id) => id,
+            };
+            tcb.push(SgxExtension::encode(id, svn)?);
+        }
+        tcb.push(SgxExtension::encode(SGX_PCESVN_OID, &self.pce_svn)?);
+        tcb.push(SgxExtension::encode(
+            SGX_CPUSVN_OID,
+            &OctetString::new(self.cpusvn.to_vec())?,
+        )?);
+        let entries = vec![
+            SgxExtension::encode(SGX_TCB_OID, &tcb)?,
+            SgxExtension::encode(SGX_PCEID_OID, &OctetString::new(self.pce_id.to_vec())?)?,
+            SgxExtension::encode(SGX_FMSPC_OID, &OctetString::new(self.fmspc.to_vec())?)?,
+        ];
+        match entries.to_der() {
+            Err(e) => Err(anyhow!("[PckExtensions] Fail to encode: {:?}", e)),
+            Ok(der) => Ok(der),
+        }
+    }
+}
+
+impl SgxExtension {
+    fn encode<T: der::EncodeValue + der::Tagged>(
+        id: ObjectIdentifier,
+        value: &T,
+    ) -> Result<Self, anyhow::Error> {
+        match Any::encode_from(value) {
+            Err(e) => Err(anyhow!("[PckExtensions] Fail to encode {}: {:?}", id, e)),
+            Ok(value) => Ok(SgxExtension { id, value }),
+        }
+    }
+}
+
+// ordered from best to worst so that the overall status is the maximum
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub enum TcbStatus {
+    UpToDate,
+    SWHardeningNeeded,
+    ConfigurationNeeded,
+    ConfigurationAndSWHardeningNeeded,
+    OutOfDate,
+    OutOfDateConfigurationNeeded,
+    Revoked,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TcbComponent {
+    pub svn: u8,
+    pub category: Option<String>,
+    #[serde(rename = "type")]
+    pub component_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Tcb {
+    pub sgxtcbcomponents: Vec<TcbComponent>,
+    pub pcesvn: u16,
+    pub tdxtcbcomponents: Option<Vec<TcbComponent>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TcbLevel {
+    pub tcb: Tcb,
+    pub tcb_date: String,
+    pub tcb_status: TcbStatus,
+    #[serde(rename = "advisoryIDs", default)]
+    pub advisory_ids: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IsvTcb {
+    pub isvsvn: u16,
+}
+
+// TCB level of the TDX module identities and of the QE identity
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IsvTcbLevel {
+    pub tcb: IsvTcb,
+    pub tcb_date: String,
+    pub tcb_status: TcbStatus,
+    #[serde(rename = "advisoryIDs", default)]
+    pub advisory_ids: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TdxModule {
+    pub mrsigner: String,
+    pub attributes: String,
+    pub attributes_mask: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TdxModuleIdentity {
+    pub id: String,
+    pub mrsigner: String,
+    pub attributes: String,
+    pub attributes_mask: String,
+    pub tcb_levels: Vec<IsvTcbLevel>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TcbInfoBody {
+    pub id: String,
+    pub version: u32,
+    pub issue_date: String,
+    pub next_update: String,
+    pub fmspc: String,
+    pub pce_id: String,
+    pub tcb_type: u32,
+    pub tcb_evaluation_data_number: u32,
+    pub tdx_module: Option<TdxModule>,
+    pub tdx_module_identities: Option<Vec<TdxModuleIdentity>>,
+    pub tcb_levels: Vec<TcbLevel>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QeIdentityBody {
+    pub id: String,
+    pub version: u32,
+    pub issue_date: String,
+    pub next_update: String,
+    pub tcb_evaluation_data_number: u32,
+    pub miscselect: String,
+    pub miscselect_mask: String,
+    pub attributes: String,
+    pub attributes_mask: String,
+    pub mrsigner: String,
+    pub isvprodid: u16,
+    pub tcb_levels: Vec<IsvTcbLevel>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignedTcbInfo<'a> {
+    #[serde(borrow)]
+    tcb_info: &'a RawValue,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignedQeIdentity<'a> {
+    #[serde(borrow)]
+    enclave_identity: &'a RawValue,
+    signature: String,
+}
+
+/// TDX TCB Info (v3) as served by the Intel PCS, with the signed JSON kept verbatim.
+#[derive(Clone, Debug)]
+pub struct TcbInfo {
+    pub body: TcbInfoBody,
+    pub signed_json: String,
+    pub signature: Vec<u8>,
+}
+
+/// QE Identity (v2) as served by the Intel PCS, with the signed JSON kept verbatim.
+#[derive(Clone, Debug)]
+pub struct QeIdentity {
+    pub body: QeIdentityBody,
+    pub signed_json: String,
+    pub signature: Vec<u8>,
+}
+
+fn decode_hex(what: &str, s: &str) -> Result<Vec<u8>, anyhow::Error> {
+    match hex::decode(s) {
+        Err(e) => Err(anyhow!("[collateral] {} is not hex encoded: {:?}", what, e)),
+        Ok(v) => Ok(v),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, anyhow::Error> {
+    match fs::read(path) {
+        Err(e) => Err(anyhow!(
+            "[collateral] Fail to read {}: {:?}",
+            path.display(),
+            e
+        )),
+        Ok(v) => Ok(v),
+    }
+}
+
+impl TcbInfo {
+    pub fn from_json(json: &[u8]) -> Result<Self, anyhow::Error> {
+        let signed: SignedTcbInfo = match serde_json::from_slice(json) {
+            Err(e) => return Err(anyhow!("[TcbInfo::from_json] Malformed TCB Info: {:?}", e)),
+            Ok(s) => s,
+        };
+        let body: TcbInfoBody = match serde_json::from_str(signed.tcb_info.get()) {
+            Err(e) => return Err(anyhow!("[TcbInfo::from_json] Malformed tcbInfo: {:?}", e)),
+            Ok(b) => b,
+        };
+        if body.id != "TDX" || body.version != 3 {
+            return Err(anyhow!(
+                "[TcbInfo::from_json] Expected TDX TCB Info v3, got {} v{}",
+                body.id,
+                body.version
+            ));
+        }
+        Ok(TcbInfo {
+            body,
+            signed_json: signed.tcb_info.get().to_string(),
+            signature: decode_hex("TCB Info signature", &signed.signature)?,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        TcbInfo::from_json(&read_file(path)?)
+    }
+}
+
+impl QeIdentity {
+    pub fn from_json(json: &[u8]) -> Result<Self, anyhow::Error> {
+        let signed: SignedQeIdentity = match serde_json::from_slice(json) {
+            Err(e) => {
+                return Err(anyhow!(
+                    "[QeIdentity::from_json] Malformed QE Identity: {:?}",
+                    e
+                ))
+            }
+            Ok(s) => s,
+        };
+        let body: QeIdentityBody = match serde_json::from_str(signed.enclave_identity.get()) {
+            Err(e) => {
+                return Err(anyhow!(
+                    "[QeIdentity::from_json] Malformed enclaveIdentity: {:?}",
+                    e
+                ))
+            }
+            Ok(b) => b,
+        };
+        if body.id != "TD_QE" {
+            return Err(anyhow!(
+                "[QeIdentity::from_json] Expected TD_QE identity, got {}",
+                body.id
+            ));
+        }
+        Ok(QeIdentity {
+            body,
+            signed_json: signed.enclave_identity.get().to_string(),
+            signature: decode_hex("QE Identity signature", &signed.signature)?,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        QeIdentity::from_json(&read_file(path)?)
+    }
+}
+
+/// Locally cached verification collateral for an air-gapped verifier.
+#[derive(Clone, Debug)]
+pub struct Collateral {
+    pub tcb_info: TcbInfo,
+    pub qe_identity: QeIdentity,
+    pub tcb_signing_chain: Vec<Certificate>, // TCB signing certificate first
+}
+
+fn is_expired(next_update: &str, now: SystemTime) -> Result<bool, anyhow::Error> {
+    match der::DateTime::from_str(next_update) {
+        Err(e) => Err(anyhow!(
+            "[collateral] Bad nextUpdate {}: {:?}",
+            next_update,
+            e
+        )),
+        Ok(t) => Ok(now > t.to_system_time()),
+    }
+}
+
+impl Collateral {
+    pub fn load(
+        tcb_info: &Path,
+        qe_identity: &Path,
+        tcb_signing_chain: &Path,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Collateral {
+            tcb_info: TcbInfo::load(tcb_info)?,
+            qe_identity: QeIdentity::load(qe_identity)?,
+            tcb_signing_chain: load_pem_chain(&read_file(tcb_signing_chain)?)?,
+        })
+    }
+
+    // load tcb_info.json, qe_identity.json and tcb_signing_chain.pem from one directory
+    pub fn load_dir(dir: &Path) -> Result<Self, anyhow::Error> {
+        Collateral::load(
+            &dir.join(TCB_INFO_FILE),
+            &dir.join(QE_IDENTITY_FILE),
+            &dir.join(TCB_SIGNING_CHAIN_FILE),
+        )
+    }
+
+    // check the TCB signing chain up to the trusted root and the signatures
+    // over TCB Info and QE Identity
+    pub fn verify(
+        self,
+        trusted_root: &Certificate,
+        now: SystemTime,
+    ) -> Result<VerifiedCollateral, VerificationFailure> {
+        verify_cert_chain(&self.tcb_signing_chain, trusted_root, now)?;
+        let key = match verifying_key(&self.tcb_signing_chain[0]) {
+            Err(e) => return Err(VerificationFailure::MalformedCertificate(e.to_string())),
+            Ok(k) => k,
+        };
+        for (what, json, signature) in [
+            (
+                "TCB Info",
+                &self.tcb_info.signed_json,
+                &self.tcb_info.signature,
+            ),
+            (
+                "QE Identity",
+                &self.qe_identity.signed_json,
+                &self.qe_identity.signature,
+            ),
+        ] {
+            let signature: &[u8; 64] = match signature.as_slice().try_into() {
+                Err(_) => {
+                    return Err(VerificationFailure::CollateralInvalid(format!(
+                        "{} signature has {} bytes",
+                        what,
+                        signature.len()
+                    )))
+                }
+                Ok(s) => s,
+            };
+            if let Err(e) = verify_raw_signature(&key, json.as_bytes(), signature) {
+                return Err(VerificationFailure::CollateralInvalid(format!(
+                    "{} signature: {}",
+                    what, e
+                )));
+            }
+        }
+        Ok(VerifiedCollateral(self))
+    }
+}
+
+/// Collateral whose signing chain and signatures `Collateral::verify` has checked, the only
+/// collateral `evaluate_tcb` accepts.
+#[derive(Clone, Debug)]
+pub struct VerifiedCollateral(Collateral);
+
+impl VerifiedCollateral {
+    pub fn collateral(&self) -> &Collateral {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Collateral {
+        self.0
+    }
+}
+
+/// TCB status of a quote's platform, TDX module and QE against the collateral.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TcbEvaluation {
+    pub status: TcbStatus, // worst of the platform, TDX module and QE status
+    pub platform_status: TcbStatus,
+    pub tdx_module_status: Option<TcbStatus>,
+    pub qe_status: TcbStatus,
+    pub tcb_date: String,
+    pub advisory_ids: Vec<String>,
+    pub collateral_expired: bool,
+}
+
+fn collateral_hex(what: &str, s: &str) -> Result<Vec<u8>, VerificationFailure> {
+    decode_hex(what, s).map_err(|e| VerificationFailure::CollateralInvalid(e.to_string()))
+}
+
+fn masked_eq(value: &[u8], expected: &[u8], mask: &[u8]) -> bool {
+    value.len() == expected.len()
+        && value.len() == mask.len()
+        && value
+            .iter()
+            .zip(expected)
+            .zip(mask)
+            .all(|((v, e), m)| v & m == e & m)
+}
+
+fn match_isv_level(levels: &[IsvTcbLevel], isvsvn: u16) -> Option<&IsvTcbLevel> {
+    levels.iter().find(|l| isvsvn >= l.tcb.isvsvn)
+}
+
+fn evaluate_tdx_module<'a>(
+    body: &TdQuoteBody,
+    tcb_info: &'a TcbInfoBody,
+) -> Result<Option<&'a IsvTcbLevel>, VerificationFailure> {
+    if let Some(module) = &tcb_info.tdx_module {
+        if body.mrsignerseam[..] != collateral_hex("tdxModule.mrsigner", &module.mrsigner)?[..] {
+            return Err(VerificationFailure::TdxModuleMismatch(
+                "MRSIGNERSEAM".to_string(),
+            ));
+        }
+        if !masked_eq(
+            &body.seam_attributes,
+            &collateral_hex("tdxModule.attributes", &module.attributes)?,
+            &collateral_hex("tdxModule.attributesMask", &module.attributes_mask)?,
+        ) {
+            return Err(VerificationFailure::TdxModuleMismatch(
+                "SEAM attributes".to_string(),
+            ));
+        }
+    }
+
+    //TEE_TCB_SVN[1] is the TDX module major version, 0 for modules without identities
+    let major = body.tee_tcb_svn[1];
+    if major == 0 {
+        return Ok(None);
+    }
+    let id = format!("TDX_{:02X}", major);
+    let identity = match tcb_info
+        .tdx_module_identities
+        .iter()
+        .flatten()
+        .find(|i| i.id == id)
+    {
+        None => {
+            return Err(VerificationFailure::TdxModuleMismatch(format!(
+                "no module identity {}",
+                id
+            )))
+        }
+        Some(i) => i,
+    };
+    if body.mrsignerseam[..] != collateral_hex("mrsigner", &identity.mrsigner)?[..] {
+        return Err(VerificationFailure::TdxModuleMismatch(format!(
+            "MRSIGNERSEAM for {}",
+            id
+        )));
+    }
+    match match_isv_level(&identity.tcb_levels, body.tee_tcb_svn[0] as u16) {
+        None => Err(VerificationFailure::TcbLevelNotFound),
+        Some(level) => Ok(Some(level)),
+    }
+}
+
+fn evaluate_qe<'a>(
+    qe: &QeReportCertificationData,
+    identity: &'a QeIdentityBody,
+) -> Result<&'a IsvTcbLevel, VerificationFailure> {
+    let report = &qe.qe_report;
+    if report.mrsigner[..] != collateral_hex("mrsigner", &identity.mrsigner)?[..] {
+        return Err(VerificationFailure::QeIdentityMismatch(
+            "MRSIGNER".to_string(),
+        ));
+    }
+    if report.isv_prod_id != identity.isvprodid {
+        return Err(VerificationFailure::QeIdentityMismatch(format!(
+            "ISVPRODID {} != {}",
+            report.isv_prod_id, identity.isvprodid
+        )));
+    }
+    //the identity writes MISCSELECT as a hex number, most significant byte first
+    if !masked_eq(
+        &report.miscselect.to_be_bytes(),
+        &collateral_hex("miscselect", &identity.miscselect)?,
+        &collateral_hex("miscselectMask", &identity.miscselect_mask)?,
+    ) {
+        return Err(VerificationFailure::QeIdentityMismatch(
+            "MISCSELECT".to_string(),
+        ));
+    }
+    if !masked_eq(
+        &report.attributes,
+        &collateral_hex("attributes", &identity.attributes)?,
+        &collateral_hex("attributesMask", &identity.attributes_mask)?,
+    ) {
+        return Err(VerificationFailure::QeIdentityMismatch(
+            "ATTRIBUTES".to_string(),
+        ));
+    }
+    match match_isv_level(&identity.tcb_levels, report.isv_svn) {
+        None => Err(VerificationFailure::TcbLevelNotFound),
+        Some(level) => Ok(level),
+    }
+}
+
+/// Match the quote's TEE_TCB_SVN and the PCK certificate's SGX TCB against the collateral.
+pub fn evaluate_tcb(
+    quote: &Quote,
+    pck_leaf: &Certificate,
+    collateral: &VerifiedCollateral,
+    now: SystemTime,
+) -> Result<TcbEvaluation, VerificationFailure> {
+    let collateral = collateral.collateral();
+    let tcb_info = &collateral.tcb_info.body;
+    let pck = PckExtensions::from_certificate(pck_leaf)
+        .map_err(|e| VerificationFailure::MalformedCertificate(e.to_string()))?;
+
+    if pck.fmspc[..] != collateral_hex("fmspc", &tcb_info.fmspc)?[..] {
+        return Err(VerificationFailure::FmspcMismatch {
+            pck: hex::encode(pck.fmspc),
+            collateral: tcb_info.fmspc.clone(),
+        });
+    }
+    if pck.pce_id[..] != collateral_hex("pceId", &tcb_info.pce_id)?[..] {
+        return Err(VerificationFailure::CollateralInvalid(format!(
+            "PCE-ID {} does not match collateral {}",
+            hex::encode(pck.pce_id),
+            tcb_info.pce_id
+        )));
+    }
+
+    let module_level = evaluate_tdx_module(&quote.body, tcb_info)?;
+    //with a module identity the first two TEE_TCB_SVN bytes are judged by that identity instead
+    let tdx_start = if module_level.is_some() { 2 } else { 0 };
+
+    //levels are ordered from newest to oldest, the first one the platform meets wins
+    let platform_level = tcb_info.tcb_levels.iter().find(|level| {
+        let sgx_ok = level.tcb.sgxtcbcomponents.len() == 16
+            && level
+                .tcb
+                .sgxtcbcomponents
+                .iter()
+                .zip(pck.sgx_tcb_components)
+                .all(|(c, svn)| svn >= c.svn)
+            && pck.pce_svn >= level.tcb.pcesvn;
+        let tdx_ok = match &level.tcb.tdxtcbcomponents {
+            Some(comps) if comps.len() == 16 => comps
+                .iter()
+                .zip(quote.body.tee_tcb_svn)
+                .skip(tdx_start)
+                .all(|(c, svn)| svn >= c.svn),
+            _ => false,
+        };
+        sgx_ok && tdx_ok
+    });
+    let platform_level = match platform_level {
+        None => return Err(VerificationFailure::TcbLevelNotFound),
+        Some(l) => l,
+    };
+
+    let qe = match quote.signature_data.qe_report_data() {
+        None => return Err(VerificationFailure::MissingCertificationData),
+        Some(q) => q,
+    };
+    let qe_level = evaluate_qe(qe, &collateral.qe_identity.body)?;
+
+    let mut advisory_ids = platform_level.advisory_ids.clone();
+    for id in module_level
+        .iter()
+        .flat_map(|l| l.advisory_ids.iter())
+        .chain(qe_level.advisory_ids.iter())
+    {
+        if !advisory_ids.contains(id) {
+            advisory_ids.push(id.clone());
+        }
+    }
+    let tdx_module_status = module_level.map(|l| l.tcb_status);
+    let status = platform_level
+        .tcb_status
+        .max(tdx_module_status.unwrap_or(TcbStatus::UpToDate))
+        .max(qe_level.tcb_status);
+    let collateral_expired = is_expired(&tcb_info.next_update, now)
+        .and_then(|t| Ok(t || is_expired(&collateral.qe_identity.body.next_update, now)?))
+        .map_err(|e| VerificationFailure::CollateralInvalid(e.to_string()))?;
+
+    Ok(TcbEvaluation {
+        status,
+        platform_status: platform_level.tcb_status,
+        tdx_module_status,
+        qe_status: qe_level.tcb_status,
+        tcb_date: platform_level.tcb_date.clone(),
+        advisory_ids,
+        collateral_expired,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+    use rand_core::OsRng;
+    use serde_json::{json, Value};
+
+    const NEXT_UPDATE: &str = "2099-01-01T00:00:00Z";
+    const QE_MRSIGNER: [u8; 32] = [0xab; 32];
+
+    // signs the JSON text of body and wraps it as the PCS does
+    fn signed(key: &SigningKey, field: &str, body: &Value) -> Vec<u8> {
+        let text = body.to_string();
+        let signature: Signature = key.sign(text.as_bytes());
+        format!(
+            "{{\"{}\":{},\"signature\":\"{}\"}}",
+            field,
+            text,
+            hex::encode(signature.to_bytes())
+        )
+        .into_bytes()
+    }
+
+    fn components(first: u8, rest: u8) -> Value {
+        let mut comps = vec![json!({ "svn": first })];
+        comps.extend((1..16).map(|_| json!({ "svn": rest })));
+        Value::Array(comps)
+    }
+
+    // a level met by SGX TCB components of sgx_svn, PCESVN pcesvn and TEE_TCB_SVN[0] tdx_svn
+    fn level(sgx_svn: u8, pcesvn: u16, tdx_svn: u8, status: &str, advisories: &[&str]) -> Value {
+        json!({
+            "tcb": {
+                "sgxtcbcomponents": components(sgx_svn, sgx_svn),
+                "pcesvn": pcesvn,
+                "tdxtcbcomponents": components(tdx_svn, 0),
+            },
+            "tcbDate": "2024-03-13T00:00:00Z",
+            "tcbStatus": status,
+            "advisoryIDs": advisories,
+        })
+    }
+
+    fn isv_level(isvsvn: u16, status: &str, advisories: &[&str]) -> Value {
+        json!({
+            "tcb": { "isvsvn": isvsvn },
+            "tcbDate": "2024-03-13T00:00:00Z",
+            "tcbStatus": status,
+            "advisoryIDs": advisories,
+        })
+    }
+
+    fn tcb_info(next_update: &str, module_identities: Value) -> Value {
+        json!({
+            "id": "TDX",
+            "version": 3,
+            "issueDate": "2024-06-01T00:00:00Z",
+            "nextUpdate": next_update,
+            "fmspc": "00906ea10000",
+            "pceId": "0000",
+            "tcbType": 0,
+            "tcbEvaluationDataNumber": 17,
+            "tdxModule": {
+                "mrsigner": hex::encode([0; 48]),
+                "attributes": "0000000000000000",
+                "attributesMask": "ffffffffffffffff",
+            },
+            "tdxModuleIdentities": module_identities,
+            "tcbLevels": [
+                level(6, 10, 3, "UpToDate", &[]),
+                level(5, 10, 4, "SWHardeningNeeded", &[]),
+                level(5, 10, 3, "SWHardeningNeeded", &["INTEL-SA-00001"]),
+                level(1, 10, 1, "OutOfDate", &["INTEL-SA-00002"]),
+                level(0, 1, 0, "Revoked", &["INTEL-SA-00003"]),
+            ],
+        })
+    }
+
+    fn qe_identity(next_update: &str, miscselect: &str) -> Value {
+        json!({
+            "id": "TD_QE",
+            "version": 2,
+            "issueDate": "2024-06-01T00:00:00Z",
+            "nextUpdate": next_update,
+            "tcbEvaluationDataNumber": 17,
+            "miscselect": miscselect,
+            "miscselectMask": "ffffffff",
+            "attributes": "11000000000000000000000000000000",
+            "attributesMask": "fbffffffffffffff0000000000000000",
+            "mrsigner": hex::encode(QE_MRSIGNER),
+            "isvprodid": 2,
+            "tcbLevels": [
+                isv_level(8, "UpToDate", &[]),
+                isv_level(0, "OutOfDate", &["INTEL-SA-00004"]),
+            ],
+        })
+    }
+
+    // the PCK platform CA stands in for the TCB signing certificate
+    fn collateral(
+        pki: &SimulatedPki,
+        tcb_info_body: &Value,
+        qe_identity_body: &Value,
+    ) -> Collateral {
+        Collateral {
+            tcb_info: TcbInfo::from_json(&signed(&pki.ca_key, "tcbInfo", tcb_info_body)).unwrap(),
+            qe_identity: QeIdentity::from_json(&signed(
+                &pki.ca_key,
+                "enclaveIdentity",
+                qe_identity_body,
+            ))
+            .unwrap(),
+            tcb_signing_chain: pki.chain[1..].to_vec(),
+        }
+    }
+
+    fn platform(sgx_svn: u8, pce_svn: u16, tee_tcb_svn: [u8; 2], qe_svn: u16) -> SimulatedTdConfig {
+        let mut config = SimulatedTdConfig::default();
+        config.pck.sgx_tcb_components = [sgx_svn; 16];
+        config.pck.pce_svn = pce_svn;
+        config.pck.fmspc = [0x00, 0x90, 0x6e, 0xa1, 0x00, 0x00];
+        config.tee_tcb_svn[..2].copy_from_slice(&tee_tcb_svn);
+        config.qe_svn = qe_svn;
+        config.qe_mrsigner = QE_MRSIGNER;
+        config
+    }
+
+    // a quote of config with its PCK hierarchy
+    fn quote(config: &SimulatedTdConfig) -> (SimulatedPki, Vec<u8>) {
+        let pki = SimulatedPki::new(config).unwrap();
+        let backend = SimulatedBackend::with_keys(
+            config.clone(),
+            SigningKey::random(&mut OsRng),
+            pki.pck_key.clone(),
+            pki.chain.clone(),
+        )
+        .unwrap();
+        let report = backend.build_report(&crate::tee_tdx_lib::ReportData([0; 64]));
+        let quote = backend.quote_for_report(&report).unwrap();
+        (pki, quote)
+    }
+
+    fn evaluate(
+        config: &SimulatedTdConfig,
+        tcb_info_body: &Value,
+        qe_identity_body: &Value,
+    ) -> Result<TcbEvaluation, VerificationFailure> {
+        let (pki, raw) = quote(config);
+        let verified = collateral(&pki, tcb_info_body, qe_identity_body)
+            .verify(pki.root_certificate(), SystemTime::now())
+            .unwrap();
+        evaluate_tcb(
+            &Quote::from_bytes(&raw).unwrap(),
+            &pki.chain[0],
+            &verified,
+            SystemTime::now(),
+        )
+    }
+
+    #[test]
+    fn tcb_status() {
+        let cases = [
+            (
+                platform(6, 10, [3, 0], 8),
+                TcbStatus::UpToDate,
+                TcbStatus::UpToDate,
+                vec![],
+            ),
+            (
+                platform(5, 10, [3, 0], 8),
+                TcbStatus::SWHardeningNeeded,
+                TcbStatus::SWHardeningNeeded,
+                vec!["INTEL-SA-00001"],
+            ),
+            (
+                platform(5, 10, [2, 0], 8),
+                TcbStatus::OutOfDate,
+                TcbStatus::OutOfDate,
+                vec!["INTEL-SA-00002"],
+            ),
+            (
+                platform(0, 10, [0, 0], 8),
+                TcbStatus::Revoked,
+                TcbStatus::Revoked,
+                vec!["INTEL-SA-00003"],
+            ),
+            //an up to date platform with an old QE
+            (
+                platform(6, 10, [3, 0], 7),
+                TcbStatus::UpToDate,
+                TcbStatus::OutOfDate,
+                vec!["INTEL-SA-00004"],
+            ),
+            //advisories of the platform and the QE are merged
+            (
+                platform(5, 10, [3, 0], 0),
+                TcbStatus::SWHardeningNeeded,
+                TcbStatus::OutOfDate,
+                vec!["INTEL-SA-00001", "INTEL-SA-00004"],
+            ),
+        ];
+        let tcb_info_body = tcb_info(NEXT_UPDATE, json!([]));
+        let qe_identity_body = qe_identity(NEXT_UPDATE, "00000000");
+        for (config, platform_status, status, advisory_ids) in cases {
+            let evaluation = evaluate(&config, &tcb_info_body, &qe_identity_body).unwrap();
+            assert_eq!(
+                evaluation.platform_status, platform_status,
+                "{:?}",
+                config.pck
+            );
+            assert_eq!(evaluation.status, status, "{:?}", config.pck);
+            assert_eq!(evaluation.advisory_ids, advisory_ids);
+            assert_eq!(evaluation.tdx_module_status, None);
+            assert!(!evaluation.collateral_expired);
+        }
+    }
+
+    #[test]
+    fn no_tcb_level() {
+        //PCESVN 0 is below every level
+        let result = evaluate(
+            &platform(6, 0, [3, 0], 8),
+            &tcb_info(NEXT_UPDATE, json!([])),
+            &qe_identity(NEXT_UPDATE, "00000000"),
+        );
+        assert!(matches!(result, Err(VerificationFailure::TcbLevelNotFound)));
+    }
+
+    #[test]
+    fn fmspc_mismatch() {
+        let mut config = platform(6, 10, [3, 0], 8);
+        config.pck.fmspc = [0x00, 0x80, 0x6f, 0x05, 0x00, 0x00];
+        let result = evaluate(
+            &config,
+            &tcb_info(NEXT_UPDATE, json!([])),
+            &qe_identity(NEXT_UPDATE, "00000000"),
+        );
+        assert!(matches!(
+            result,
+            Err(VerificationFailure::FmspcMismatch { pck, .. }) if pck == "00806f050000"
+        ));
+    }
+
+    #[test]
+    fn expired_collateral() {
+        let evaluation = evaluate(
+            &platform(6, 10, [3, 0], 8),
+            &tcb_info(NEXT_UPDATE, json!([])),
+            &qe_identity("2020-01-01T00:00:00Z", "00000000"),
+        )
+        .unwrap();
+        assert!(evaluation.collateral_expired);
+    }
+
+    #[test]
+    fn tdx_module_identity() {
+        let identities = json!([{
+            "id": "TDX_0A",
+            "mrsigner": hex::encode([0; 48]),
+            "attributes": "0000000000000000",
+            "attributesMask": "ffffffffffffffff",
+            "tcbLevels": [
+                isv_level(3, "UpToDate", &[]),
+                isv_level(0, "OutOfDate", &["INTEL-SA-00005"]),
+            ],
+        }]);
+        let tcb_info_body = tcb_info(NEXT_UPDATE, identities);
+        let qe_identity_body = qe_identity(NEXT_UPDATE, "00000000");
+
+        //module major version 10, TEE_TCB_SVN[0] is its minor SVN
+        let evaluation = evaluate(
+            &platform(6, 10, [3, 10], 8),
+            &tcb_info_body,
+            &qe_identity_body,
+        )
+        .unwrap();
+        assert_eq!(evaluation.tdx_module_status, Some(TcbStatus::UpToDate));
+        assert_eq!(evaluation.status, TcbStatus::UpToDate);
+
+        let evaluation = evaluate(
+            &platform(6, 10, [2, 10], 8),
+            &tcb_info_body,
+            &qe_identity_body,
+        )
+        .unwrap();
+        assert_eq!(evaluation.tdx_module_status, Some(TcbStatus::OutOfDate));
+        assert_eq!(evaluation.status, TcbStatus::OutOfDate);
+        assert_eq!(evaluation.advisory_ids, ["INTEL-SA-00005"]);
+
+        let result = evaluate(
+            &platform(6, 10, [3, 11], 8),
+            &tcb_info_body,
+            &qe_identity_body,
+        );
+        assert!(matches!(
+            result,
+            Err(VerificationFailure::TdxModuleMismatch(s)) if s == "no module identity TDX_0B"
+        ));
+    }
+
+    #[test]
+    fn qe_miscselect_byte_order() {
+        let config = platform(6, 10, [3, 0], 8);
+        let (pki, mut raw) = quote(&config);
+        //MISCSELECT follows the 16 byte CPUSVN of the QE report, stored little-endian
+        let qe_report = Quote::from_bytes(&raw)
+            .unwrap()
+            .signature_data
+            .qe_report_data()
+            .unwrap()
+            .qe_report
+            .raw
+            .clone();
+        let offset = raw
+            .windows(qe_report.len())
+            .position(|w| w == qe_report)
+            .unwrap();
+        raw[offset + 16] = 0x01;
+        let quote = Quote::from_bytes(&raw).unwrap();
+        assert_eq!(
+            quote
+                .signature_data
+                .qe_report_data()
+                .unwrap()
+                .qe_report
+                .miscselect,
+            1
+        );
+
+        let tcb_info_body = tcb_info(NEXT_UPDATE, json!([]));
+        let check = |miscselect: &str| {
+            let verified = collateral(&pki, &tcb_info_body, &qe_identity(NEXT_UPDATE, miscselect))
+                .verify(pki.root_certificate(), SystemTime::now())
+                .unwrap();
+            evaluate_tcb(&quote, &pki.chain[0], &verified, SystemTime::now())
+        };
+        assert!(check("00000001").is_ok());
+        assert!(matches!(
+            check("01000000"),
+            Err(VerificationFailure::QeIdentityMismatch(s)) if s == "MISCSELECT"
+        ));
+    }
+
+    #[test]
+    fn bad_signature() {
+        let config = platform(6, 10, [3, 0], 8);
+        let pki = SimulatedPki::new(&config).unwrap();
+        let tcb_info_body = tcb_info(NEXT_UPDATE, json!([]));
+        let qe_identity_body = qe_identity(NEXT_UPDATE, "00000000");
+
+        let mut tampered = collateral(&pki, &tcb_info_body, &qe_identity_body);
+        tampered.tcb_info.signature[0] ^= 1;
+        assert!(matches!(
+            tampered.verify(pki.root_certificate(), SystemTime::now()),
+            Err(VerificationFailure::CollateralInvalid(s)) if s.starts_with("TCB Info signature")
+        ));
+
+        let mut tampered = collateral(&pki, &tcb_info_body, &qe_identity_body);
+        tampered.qe_identity.signed_json = tampered
+            .qe_identity
+            .signed_json
+            .replace("\"isvprodid\":2", "\"isvprodid\":3");
+        assert!(matches!(
+            tampered.verify(pki.root_certificate(), SystemTime::now()),
+            Err(VerificationFailure::CollateralInvalid(s)) if s.starts_with("QE Identity signature")
+        ));
+
+        //signed by a key outside the trusted hierarchy
+        let other = SimulatedPki::new(&config).unwrap();
+        let mut foreign = collateral(&pki, &tcb_info_body, &qe_identity_body);
+        foreign.tcb_info =
+            TcbInfo::from_json(&signed(&other.ca_key, "tcbInfo", &tcb_info_body)).unwrap();
+        assert!(matches!(
+            foreign.verify(pki.root_certificate(), SystemTime::now()),
+            Err(VerificationFailure::CollateralInvalid(_))
+        ));
+        assert!(collateral(&pki, &tcb_info_body, &qe_identity_body)
+            .verify(other.root_certificate(), SystemTime::now())
+            .is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::verify::*;
+use anyhow::*;
+use der::{Decode, Encode};
+use std::fs;
+use std::path::Path;
+use std::result::Result;
+use std::result::Result::Ok;
+use std::time::SystemTime;
+use x509_cert::crl::CertificateList;
+use x509_cert::Certificate;
+
+const CRL_PEM_LABEL: &str = "X509 CRL";
+
+/// A DER or PEM encoded CRL, e.g. the Intel SGX Root CA CRL or the PCK Platform/Processor CRL.
+#[derive(Clone, Debug)]
+pub struct Crl {
+    pub crl: CertificateList,
+}
+
+impl Crl {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let der = if bytes.starts_with(b"-----BEGIN") {
+            match der::pem::decode_vec(bytes) {
+                Err(e) => return Err(anyhow!("[Crl::from_bytes] Bad PEM CRL: {:?}", e)),
+                Ok((label, _)) if label != CRL_PEM_LABEL => {
+                    return Err(anyhow!(
+                        "[Crl::from_bytes] Expected PEM label {}, got {}",
+                        CRL_PEM_LABEL,
+                        label
+                    ))
+                }
+                Ok((_, der)) => der,
+            }
+        } else {
+            bytes.to_vec()
+        };
+        match CertificateList::from_der(&der) {
+            Err(e) => Err(anyhow!("[Crl::from_bytes] Fail to parse CRL: {:?}", e)),
+            Ok(crl) => Ok(Crl { crl }),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        match fs::read(path) {
+            Err(e) => Err(anyhow!(
+                "[Crl::load] Fail to read {}: {:?}",
+                path.display(),
+                e
+            )),
+            Ok(bytes) => Crl::from_bytes(&bytes),
+        }
+    }
+
+    pub fn issuer_name(&self) -> String {
+        self.crl.tbs_cert_list.issuer.to_string()
+    }
+
+    pub fn is_issued_by(&self, cert: &Certificate) -> bool {
+        self.crl.tbs_cert_list.issuer == cert.tbs_certificate.subject
+    }
+
+    // signature by the issuing CA and thisUpdate <= now <= nextUpdate
+    pub fn verify(&self, issuer: &Certificate, now: SystemTime) -> CheckResult {
+        let tbs = &self.crl.tbs_cert_list;
+        let signed = match tbs.to_der() {
+            Err(e) => return Err(VerificationFailure::CrlInvalid(format!("{:?}", e))),
+            Ok(t) => t,
+        };
+        if let Err(e) = verify_der_signature(
+            issuer,
+            &self.crl.signature_algorithm.oid.to_string(),
+            &signed,
+            self.crl.signature.raw_bytes(),
+        ) {
+            return Err(VerificationFailure::CrlInvalid(format!(
+                "CRL of {}: {}",
+                self.issuer_name(),
+                e
+            )));
+        }
+        if now < tbs.this_update.to_system_time() {
+            return Err(VerificationFailure::CrlInvalid(format!(
+                "CRL of {} is not yet valid",
+                self.issuer_name()
+            )));
+        }
+        if let Some(next_update) = tbs.next_update {
+            if now > next_update.to_system_time() {
+                return Err(VerificationFailure::CrlExpired {
+                    issuer: self.issuer_name(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_revoked(&self, cert: &Certificate) -> bool {
+        self.crl.tbs_cert_list.issuer == cert.tbs_certificate.issuer
+            && self
+                .crl
+                .tbs_cert_list
+                .revoked_certificates
+                .iter()
+                .flatten()
+                .any(|r| r.serial_number == cert.tbs_certificate.serial_number)
+    }
+}
+
+// every certificate below the trusted root needs a valid CRL from its issuer that does not list it
+pub fn check_revocation(
+    chain: &[Certificate],
+    trusted_root: &Certificate,
+    crls: &[Crl],
+    now: SystemTime,
+) -> CheckResult {
+    for (i, cert) in chain.iter().enumerate() {
+        if cert == trusted_root {
+            continue;
+        }
+        let issuer = chain.get(i + 1).unwrap_or(trusted_root);
+        let issuer_crls: Vec<&Crl> = crls.iter().filter(|c| c.is_issued_by(issuer)).collect();
+        if issuer_crls.is_empty() {
+            return Err(VerificationFailure::CrlMissing {
+                issuer: subject_name(issuer),
+            });
+        }
+        for crl in issuer_crls {
+            crl.verify(issuer, now)?;
+            if crl.is_revoked(cert) {
+                return Err(VerificationFailure::Revoked {
+                    subject: subject_name(cert),
+                    serial: hex::encode(cert.tbs_certificate.serial_number.as_bytes()),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quote::Quote;
+    use crate::simulator::*;
+    use crate::tee_tdx_lib::ReportData;
+    use der::asn1::BitString;
+    use der::oid::ObjectIdentifier;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{DerSignature, SigningKey};
+    use rand_core::OsRng;
+    use std::time::Duration;
+    use x509_cert::crl::{RevokedCert, TbsCertList};
+    use x509_cert::spki::AlgorithmIdentifierOwned;
+    use x509_cert::time::Time;
+    use x509_cert::Version;
+
+    const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+
+    // a CRL of issuer valid for the next hour, listing revoked
+    fn signed_crl(issuer: &Certificate, key: &SigningKey, revoked: &[&Certificate]) -> Crl {
+        let now = SystemTime::now();
+        let algorithm = AlgorithmIdentifierOwned {
+            oid: ECDSA_WITH_SHA256,
+            parameters: None,
+        };
+        let revoked_certificates = revoked
+            .iter()
+            .map(|cert| RevokedCert {
+                serial_number: cert.tbs_certificate.serial_number.clone(),
+                revocation_date: Time::try_from(now).unwrap(),
+                crl_entry_extensions: None,
+            })
+            .collect::<Vec<_>>();
+        let tbs_cert_list = TbsCertList {
+            version: Version::V2,
+            signature: algorithm.clone(),
+            issuer: issuer.tbs_certificate.subject.clone(),
+            this_update: Time::try_from(now - Duration::from_secs(60)).unwrap(),
+            next_update: Some(Time::try_from(now + Duration::from_secs(3600)).unwrap()),
+            revoked_certificates: (!revoked.is_empty()).then_some(revoked_certificates),
+            crl_extensions: None,
+        };
+        let signature: DerSignature = key.sign(&tbs_cert_list.to_der().unwrap());
+        let der = CertificateList {
+            tbs_cert_list,
+            signature_algorithm: algorithm,
+            signature: BitString::from_bytes(signature.as_bytes()).unwrap(),
+        }
+        .to_der()
+        .unwrap();
+        Crl::from_bytes(&der).unwrap()
+    }
+
+    fn simulated_quote(pki: &SimulatedPki) -> Quote {
+        let backend = SimulatedBackend::with_keys(
+            SimulatedTdConfig::default(),
+            SigningKey::random(&mut OsRng),
+            pki.pck_key.clone(),
+            pki.chain.clone(),
+        )
+        .unwrap();
+        let report = backend.build_report(&ReportData([7; 64]));
+        Quote::from_bytes(&backend.quote_for_report(&report).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn not_revoked() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let (pck, ca, root) = (&pki.chain[0], &pki.chain[1], &pki.chain[2]);
+        let crls = [
+            signed_crl(ca, &pki.ca_key, &[]),
+            signed_crl(root, &pki.root_key, &[]),
+        ];
+        assert!(!crls[0].is_revoked(pck));
+        let verdict =
+            verify_quote_with_crls(&simulated_quote(&pki), root, &crls, SystemTime::now());
+        assert!(verdict.is_valid(), "{:?}", verdict.failures());
+    }
+
+    #[test]
+    fn revoked_pck() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let (pck, ca, root) = (&pki.chain[0], &pki.chain[1], &pki.chain[2]);
+        let crls = [
+            signed_crl(ca, &pki.ca_key, &[pck]),
+            signed_crl(root, &pki.root_key, &[]),
+        ];
+        let verdict =
+            verify_quote_with_crls(&simulated_quote(&pki), root, &crls, SystemTime::now());
+        assert!(matches!(
+            verdict.revocation,
+            Some(Err(VerificationFailure::Revoked { ref subject, .. })) if *subject == subject_name(pck)
+        ));
+        assert_eq!(verdict.failures().len(), 1);
+    }
+
+    #[test]
+    fn missing_crl() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let (ca, root) = (&pki.chain[1], &pki.chain[2]);
+        let crls = [signed_crl(ca, &pki.ca_key, &[])];
+        let result = check_revocation(&pki.chain, root, &crls, SystemTime::now());
+        assert!(matches!(
+            result,
+            Err(VerificationFailure::CrlMissing { issuer }) if issuer == subject_name(root)
+        ));
+    }
+
+    #[test]
+    fn crl_signature_and_validity() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let (ca, root) = (&pki.chain[1], &pki.chain[2]);
+        let crl = signed_crl(ca, &pki.root_key, &[]);
+        assert!(matches!(
+            crl.verify(ca, SystemTime::now()),
+            Err(VerificationFailure::CrlInvalid(_))
+        ));
+        assert!(signed_crl(ca, &pki.ca_key, &[])
+            .verify(ca, SystemTime::now())
+            .is_ok());
+        assert!(matches!(
+            signed_crl(root, &pki.root_key, &[])
+                .verify(root, SystemTime::now() + Duration::from_secs(7200)),
+            Err(VerificationFailure::CrlExpired { .. })
+        ));
+    }
+}
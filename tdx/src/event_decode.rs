@@ -0,0 +1,963 @@
+use crate::eventlog::*;
+use sha2::{Digest, Sha384};
+use std::convert::TryInto;
+use std::fmt;
+
+// well-known GUIDs found in TD event logs
+const EFI_GLOBAL_VARIABLE_GUID: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+const EFI_IMAGE_SECURITY_DATABASE_GUID: &str = "d719b2cb-3d3a-4596-a3bc-dad00e67656f";
+const EFI_CERT_X509_GUID: &str = "a5c059a1-94e4-4aa7-87b5-ab155c2bf072";
+const EFI_CERT_SHA256_GUID: &str = "c1c41626-504c-4092-aca9-41f936934328";
+
+// HOB types, see UEFI PI specification volume 3
+const EFI_HOB_TYPE_HANDOFF: u16 = 0x0001;
+const EFI_HOB_TYPE_MEMORY_ALLOCATION: u16 = 0x0002;
+const EFI_HOB_TYPE_RESOURCE_DESCRIPTOR: u16 = 0x0003;
+const EFI_HOB_TYPE_GUID_EXTENSION: u16 = 0x0004;
+const EFI_HOB_TYPE_FV: u16 = 0x0005;
+const EFI_HOB_TYPE_END_OF_HOB_LIST: u16 = 0xffff;
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+// EFI_GUID: u32, u16, u16 little-endian followed by 8 bytes in order
+pub fn format_guid(bytes: &[u8]) -> Option<String> {
+    let b = bytes.get(0..16)?;
+    Some(format!(
+        "{:08x}-{:04x}-{:04x}-{}-{}",
+        u32_at(b, 0)?,
+        u16_at(b, 4)?,
+        u16_at(b, 6)?,
+        hex::encode(&b[8..10]),
+        hex::encode(&b[10..16])
+    ))
+}
+
+fn utf16(data: &[u8]) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|u| *u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+// EV_IPL text is ASCII from grub, UTF-16 from some other loaders
+fn event_text(data: &[u8]) -> String {
+    let looks_utf16 = data.len() >= 4
+        && data.len().is_multiple_of(2)
+        && data.iter().skip(1).step_by(2).all(|b| *b == 0);
+    if looks_utf16 {
+        utf16(data)
+    } else {
+        String::from_utf8_lossy(data)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+}
+
+// EFI device path nodes rendered in the UEFI text form where it is commonly known
+pub fn format_device_path(mut data: &[u8]) -> String {
+    let mut nodes = Vec::new();
+    while data.len() >= 4 {
+        let (node_type, sub_type) = (data[0], data[1]);
+        let len = u16_at(data, 2).unwrap_or(0) as usize;
+        if len < 4 || len > data.len() {
+            nodes.push("<malformed>".to_string());
+            break;
+        }
+        let body = &data[4..len];
+        let node = match (node_type, sub_type) {
+            (0x7f, 0xff) => break,
+            (0x7f, _) => "/".to_string(),
+            (0x01, 0x01) if body.len() >= 2 => format!("Pci({:#x},{:#x})", body[1], body[0]),
+            (0x02, 0x01) if body.len() >= 8 => match u32_at(body, 0) {
+                Some(0x0a0341d0) | Some(0x0a0841d0) => {
+                    format!("PciRoot({:#x})", u32_at(body, 4).unwrap_or(0))
+                }
+                hid => format!(
+                    "Acpi({:#x},{:#x})",
+                    hid.unwrap_or(0),
+                    u32_at(body, 4).unwrap_or(0)
+                ),
+            },
+            (0x03, 0x01) if body.len() >= 2 => format!("Ata({},{})", body[0], body[1]),
+            (0x03, 0x02) if body.len() >= 4 => format!(
+                "Scsi({},{})",
+                u16_at(body, 0).unwrap_or(0),
+                u16_at(body, 2).unwrap_or(0)
+            ),
+            (0x03, 0x05) if body.len() >= 2 => format!("USB({},{})", body[0], body[1]),
+            (0x03, 0x12) if body.len() >= 6 => format!(
+                "Sata({},{},{})",
+                u16_at(body, 0).unwrap_or(0),
+                u16_at(body, 2).unwrap_or(0),
+                u16_at(body, 4).unwrap_or(0)
+            ),
+            (0x03, 0x17) if body.len() >= 4 => format!("NVMe({:#x})", u32_at(body, 0).unwrap_or(0)),
+            (0x03, 0x0b) => "MAC()".to_string(),
+            (0x03, 0x0c) => "IPv4()".to_string(),
+            (0x03, 0x0d) => "IPv6()".to_string(),
+            (0x03, 0x18) => format!("Uri({})", String::from_utf8_lossy(body)),
+            (0x04, 0x01) if body.len() >= 38 => format!(
+                "HD({},{},{},{:#x},{:#x})",
+                u32_at(body, 0).unwrap_or(0),
+                match body[37] {
+                    1 => "MBR",
+                    2 => "GPT",
+                    _ => "?",
+                },
+                format_guid(&body[20..36]).unwrap_or_default(),
+                u64_at(body, 4).unwrap_or(0),
+                u64_at(body, 12).unwrap_or(0)
+            ),
+            (0x04, 0x02) => "CDROM()".to_string(),
+            (0x04, 0x03) => format!("VenMedia({})", format_guid(body).unwrap_or_default()),
+            (0x04, 0x04) => utf16(body),
+            (0x04, 0x06) => format!("FvFile({})", format_guid(body).unwrap_or_default()),
+            (0x04, 0x07) => format!("Fv({})", format_guid(body).unwrap_or_default()),
+            (0x04, 0x08) if body.len() >= 16 => format!(
+                "Offset({:#x},{:#x})",
+                u64_at(body, 4).unwrap_or(0),
+                u64_at(body, 12).unwrap_or(0)
+            ),
+            (t, s) => format!("Path({},{},{})", t, s, hex::encode(body)),
+        };
+        nodes.push(node);
+        data = &data[len..];
+    }
+    nodes.join("/").replace("//", "/")
+}
+
+// summary of EFI_SIGNATURE_LIST entries in PK, KEK, db and dbx
+fn signature_lists_summary(mut data: &[u8]) -> String {
+    let mut lists = Vec::new();
+    while data.len() >= 28 {
+        let sig_type = format_guid(data).unwrap_or_default();
+        let list_size = u32_at(data, 16).unwrap_or(0) as usize;
+        let header_size = u32_at(data, 20).unwrap_or(0) as usize;
+        let sig_size = u32_at(data, 24).unwrap_or(0) as usize;
+        if list_size < 28 + header_size || list_size > data.len() || sig_size == 0 {
+            lists.push("<malformed signature list>".to_string());
+            break;
+        }
+        let count = (list_size - 28 - header_size) / sig_size;
+        let kind = match sig_type.as_str() {
+            EFI_CERT_X509_GUID => "X509".to_string(),
+            EFI_CERT_SHA256_GUID => "SHA256".to_string(),
+            other => other.to_string(),
+        };
+        lists.push(format!("{} x {}", count, kind));
+        data = &data[list_size..];
+    }
+    if lists.is_empty() {
+        "empty".to_string()
+    } else {
+        lists.join(", ")
+    }
+}
+
+fn variable_summary(guid: &str, name: &str, data: &[u8]) -> String {
+    match (guid, name) {
+        (EFI_GLOBAL_VARIABLE_GUID, "SecureBoot") => match data.first() {
+            Some(1) => "enabled".to_string(),
+            Some(0) => "disabled".to_string(),
+            _ => format!("{} bytes", data.len()),
+        },
+        (EFI_GLOBAL_VARIABLE_GUID, "PK")
+        | (EFI_GLOBAL_VARIABLE_GUID, "KEK")
+        | (EFI_IMAGE_SECURITY_DATABASE_GUID, "db")
+        | (EFI_IMAGE_SECURITY_DATABASE_GUID, "dbx") => signature_lists_summary(data),
+        (EFI_GLOBAL_VARIABLE_GUID, "BootOrder") => data
+            .chunks_exact(2)
+            .map(|c| format!("Boot{:04X}", u16::from_le_bytes([c[0], c[1]])))
+            .collect::<Vec<_>>()
+            .join(","),
+        (EFI_GLOBAL_VARIABLE_GUID, n) if n.starts_with("Boot") && data.len() >= 6 => {
+            //EFI_LOAD_OPTION: attributes, file path list length, description, file path list
+            let path_len = u16_at(data, 4).unwrap_or(0) as usize;
+            let description = utf16(&data[6..]);
+            let path_start = 6 + (description.encode_utf16().count() + 1) * 2;
+            let path = data
+                .get(path_start..path_start + path_len)
+                .map(format_device_path)
+                .unwrap_or_default();
+            format!("{} {}", description, path)
+        }
+        _ => format!("{} bytes", data.len()),
+    }
+}
+
+// HOB list as measured by TDVF, one line per HOB
+pub fn decode_hob_list(mut data: &[u8]) -> Option<Vec<String>> {
+    if u16_at(data, 0)? != EFI_HOB_TYPE_HANDOFF {
+        return None;
+    }
+    let mut hobs = Vec::new();
+    while data.len() >= 8 {
+        let hob_type = u16_at(data, 0)?;
+        let len = u16_at(data, 2)? as usize;
+        if len < 8 || len > data.len() {
+            return None;
+        }
+        let hob = &data[0..len];
+        hobs.push(match hob_type {
+            EFI_HOB_TYPE_HANDOFF => "Handoff".to_string(),
+            EFI_HOB_TYPE_MEMORY_ALLOCATION => format!(
+                "MemoryAllocation(base {:#x}, length {:#x})",
+                u64_at(hob, 24).unwrap_or(0),
+                u64_at(hob, 32).unwrap_or(0)
+            ),
+            EFI_HOB_TYPE_RESOURCE_DESCRIPTOR => format!(
+                "ResourceDescriptor(type {}, attributes {:#x}, start {:#x}, length {:#x})",
+                u32_at(hob, 24).unwrap_or(0),
+                u32_at(hob, 28).unwrap_or(0),
+                u64_at(hob, 32).unwrap_or(0),
+                u64_at(hob, 40).unwrap_or(0)
+            ),
+            EFI_HOB_TYPE_GUID_EXTENSION => format!(
+                "GuidExtension({})",
+                format_guid(&hob[8..]).unwrap_or_default()
+            ),
+            EFI_HOB_TYPE_FV => format!(
+                "FirmwareVolume(base {:#x}, length {:#x})",
+                u64_at(hob, 8).unwrap_or(0),
+                u64_at(hob, 16).unwrap_or(0)
+            ),
+            EFI_HOB_TYPE_END_OF_HOB_LIST => {
+                hobs.push("EndOfHobList".to_string());
+                break;
+            }
+            t => format!("Hob(type {:#x}, {} bytes)", t, len),
+        });
+        data = &data[len..];
+    }
+    Some(hobs)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodedEvent {
+    EfiVariable {
+        guid: String,
+        name: String,
+        summary: String,
+    },
+    ImageLoad {
+        location: u64,
+        length: u64,
+        link_time_address: u64,
+        device_path: String,
+    },
+    FirmwareBlob {
+        description: Option<String>,
+        base: u64,
+        length: u64,
+    },
+    HandoffTables {
+        description: Option<String>,
+        tables: Vec<(String, u64)>, // vendor GUID and table address
+    },
+    TaggedEvent {
+        id: u32,
+        size: usize,
+        hobs: Option<Vec<String>>,
+    },
+    Text(String),
+    Raw(usize),
+}
+
+impl fmt::Display for DecodedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodedEvent::EfiVariable {
+                guid,
+                name,
+                summary,
+            } => write!(f, "{} ({}): {}", name, guid, summary),
+            DecodedEvent::ImageLoad {
+                location,
+                length,
+                device_path,
+                ..
+            } => write!(
+                f,
+                "image at {:#x} ({:#x} bytes) {}",
+                location, length, device_path
+            ),
+            DecodedEvent::FirmwareBlob {
+                description,
+                base,
+                length,
+            } => write!(
+                f,
+                "{} at {:#x} ({:#x} bytes)",
+                description.as_deref().unwrap_or("blob"),
+                base,
+                length
+            ),
+            DecodedEvent::HandoffTables {
+                description,
+                tables,
+            } => {
+                write!(f, "{}:", description.as_deref().unwrap_or("tables"))?;
+                for (guid, address) in tables {
+                    write!(f, " {}@{:#x}", guid, address)?;
+                }
+                std::result::Result::Ok(())
+            }
+            DecodedEvent::TaggedEvent { id, size, hobs } => {
+                write!(f, "tag {:#x} ({} bytes)", id, size)?;
+                if let Some(hobs) = hobs {
+                    write!(f, " HOB list: {}", hobs.join(", "))?;
+                }
+                std::result::Result::Ok(())
+            }
+            DecodedEvent::Text(s) => write!(f, "{:?}", s),
+            DecodedEvent::Raw(size) => write!(f, "{} bytes", size),
+        }
+    }
+}
+
+fn decode_variable(data: &[u8]) -> Option<DecodedEvent> {
+    //UEFI_VARIABLE_DATA: GUID, name length (chars), data length, name, data
+    let guid = format_guid(data)?;
+    //the lengths come from the log, a malformed entry must not overflow
+    let name_end = usize::try_from(u64_at(data, 16)?)
+        .ok()?
+        .checked_mul(2)?
+        .checked_add(32)?;
+    let data_end = name_end.checked_add(usize::try_from(u64_at(data, 24)?).ok()?)?;
+    let name = utf16(data.get(32..name_end)?);
+    let value = data.get(name_end..data_end)?;
+    let summary = variable_summary(&guid, &name, value);
+    Some(DecodedEvent::EfiVariable {
+        guid,
+        name,
+        summary,
+    })
+}
+
+fn decode_image_load(data: &[u8]) -> Option<DecodedEvent> {
+    let path_end = usize::try_from(u64_at(data, 24)?).ok()?.checked_add(32)?;
+    Some(DecodedEvent::ImageLoad {
+        location: u64_at(data, 0)?,
+        length: u64_at(data, 8)?,
+        link_time_address: u64_at(data, 16)?,
+        device_path: format_device_path(data.get(32..path_end)?),
+    })
+}
+
+fn decode_blob(data: &[u8]) -> Option<DecodedEvent> {
+    Some(DecodedEvent::FirmwareBlob {
+        description: None,
+        base: u64_at(data, 0)?,
+        length: u64_at(data, 8)?,
+    })
+}
+
+fn decode_blob2(data: &[u8]) -> Option<DecodedEvent> {
+    let len = *data.first()? as usize;
+    Some(DecodedEvent::FirmwareBlob {
+        description: Some(event_text(data.get(1..1 + len)?)),
+        base: u64_at(data, 1 + len)?,
+        length: u64_at(data, 9 + len)?,
+    })
+}
+
+fn decode_handoff_tables(data: &[u8], with_description: bool) -> Option<DecodedEvent> {
+    let (description, offset) = if with_description {
+        let len = *data.first()? as usize;
+        (Some(event_text(data.get(1..1 + len)?)), 1 + len)
+    } else {
+        (None, 0)
+    };
+    let count = u64_at(data, offset)? as usize;
+    let mut tables = Vec::new();
+    for i in 0..count {
+        let entry = offset + 8 + i * 24;
+        tables.push((format_guid(data.get(entry..)?)?, u64_at(data, entry + 16)?));
+    }
+    Some(DecodedEvent::HandoffTables {
+        description,
+        tables,
+    })
+}
+
+fn decode_tagged_event(data: &[u8]) -> Option<DecodedEvent> {
+    let size = u32_at(data, 4)? as usize;
+    let tagged = data.get(8..size.checked_add(8)?)?;
+    Some(DecodedEvent::TaggedEvent {
+        id: u32_at(data, 0)?,
+        size,
+        hobs: decode_hob_list(tagged),
+    })
+}
+
+impl EventLogEntry {
+    pub fn decode(&self) -> DecodedEvent {
+        let data = &self.event;
+        let decoded = match self.event_type {
+            EV_EFI_VARIABLE_DRIVER_CONFIG
+            | EV_EFI_VARIABLE_BOOT
+            | EV_EFI_VARIABLE_BOOT2
+            | EV_EFI_VARIABLE_AUTHORITY => decode_variable(data),
+            EV_EFI_BOOT_SERVICES_APPLICATION
+            | EV_EFI_BOOT_SERVICES_DRIVER
+            | EV_EFI_RUNTIME_SERVICES_DRIVER => decode_image_load(data),
+            EV_EFI_PLATFORM_FIRMWARE_BLOB => decode_blob(data),
+            EV_EFI_PLATFORM_FIRMWARE_BLOB2 => decode_blob2(data),
+            EV_EFI_HANDOFF_TABLES => decode_handoff_tables(data, false),
+            EV_EFI_HANDOFF_TABLES2 => decode_handoff_tables(data, true),
+            EV_EVENT_TAG => decode_tagged_event(data),
+            EV_IPL
+            | EV_EFI_ACTION
+            | EV_ACTION
+            | EV_S_CRTM_VERSION
+            | EV_POST_CODE
+            | EV_OMIT_BOOT_DEVICE_EVENTS => Some(DecodedEvent::Text(event_text(data))),
+            EV_SEPARATOR if data.len() == 4 => Some(DecodedEvent::Text(format!(
+                "separator {:#x}",
+                u32_at(data, 0).unwrap_or(0)
+            ))),
+            _ => None,
+        };
+        decoded.unwrap_or(DecodedEvent::Raw(data.len()))
+    }
+}
+
+/// Replayed value of one RTMR next to the value reported by a TD report or quote.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtmrDiff {
+    pub index: usize,
+    pub replayed: [u8; 48],
+    pub reported: [u8; 48],
+    pub events: Vec<String>, // decoded events measured into this RTMR with the running value
+}
+
+impl RtmrDiff {
+    pub fn matches(&self) -> bool {
+        self.replayed == self.reported
+    }
+}
+
+impl fmt::Display for RtmrDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.matches() {
+            return writeln!(
+                f,
+                "RTMR{}: match {}",
+                self.index,
+                hex::encode(self.reported)
+            );
+        }
+        writeln!(f, "RTMR{}: MISMATCH", self.index)?;
+        writeln!(f, "  reported: {}", hex::encode(self.reported))?;
+        writeln!(f, "  replayed: {}", hex::encode(self.replayed))?;
+        for event in &self.events {
+            writeln!(f, "    {}", event)?;
+        }
+        std::result::Result::Ok(())
+    }
+}
+
+// compare the replayed event log against the RTMRs of a TD report or quote
+pub fn diff_rtmrs(log: &EventLog, reported: &[[u8; 48]; 4]) -> Vec<RtmrDiff> {
+    diff_entries(&log.entries, reported)
+}
+
+// same for a list of events, such as the expected ones of a boot
+pub fn diff_entries(entries: &[EventLogEntry], reported: &[[u8; 48]; 4]) -> Vec<RtmrDiff> {
+    let mut diffs: Vec<RtmrDiff> = (0..4)
+        .map(|index| RtmrDiff {
+            index,
+            replayed: [0; 48],
+            reported: reported[index],
+            events: Vec::new(),
+        })
+        .collect();
+    for entry in entries {
+        let index = match entry.rtmr_index() {
+            Some(i) if entry.event_type != EV_NO_ACTION => i,
+            _ => continue,
+        };
+        let diff = &mut diffs[index];
+        let digest = match entry.digest(TPM_ALG_SHA384) {
+            None => {
+                diff.events.push(format!(
+                    "{} without SHA384 digest: {}",
+                    event_type_name(entry.event_type),
+                    entry.decode()
+                ));
+                continue;
+            }
+            Some(d) => d,
+        };
+        let mut hasher = Sha384::new();
+        hasher.update(diff.replayed);
+        hasher.update(digest);
+        diff.replayed = hasher.finalize().into();
+        diff.events.push(format!(
+            "{} {} -> {}: {}",
+            event_type_name(entry.event_type),
+            hex::encode(&digest[0..8]),
+            hex::encode(&diff.replayed[0..8]),
+            entry.decode()
+        ));
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // inverse of format_guid
+    fn guid(s: &str) -> Vec<u8> {
+        let parts: Vec<&str> = s.split('-').collect();
+        let mut bytes = Vec::new();
+        bytes.extend(u32::from_str_radix(parts[0], 16).unwrap().to_le_bytes());
+        bytes.extend(u16::from_str_radix(parts[1], 16).unwrap().to_le_bytes());
+        bytes.extend(u16::from_str_radix(parts[2], 16).unwrap().to_le_bytes());
+        bytes.extend(hex::decode(parts[3]).unwrap());
+        bytes.extend(hex::decode(parts[4]).unwrap());
+        bytes
+    }
+
+    fn utf16_bytes(s: &str, terminated: bool) -> Vec<u8> {
+        let mut bytes: Vec<u8> = s.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        if terminated {
+            bytes.extend([0, 0]);
+        }
+        bytes
+    }
+
+    fn entry(event_type: u32, event: Vec<u8>) -> EventLogEntry {
+        EventLogEntry {
+            mr_index: 1,
+            event_type,
+            digests: vec![(TPM_ALG_SHA384, vec![0; 48])],
+            event,
+        }
+    }
+
+    fn variable(vendor: &str, name: &str, value: &[u8]) -> Vec<u8> {
+        let mut data = guid(vendor);
+        data.extend((name.encode_utf16().count() as u64).to_le_bytes());
+        data.extend((value.len() as u64).to_le_bytes());
+        data.extend(utf16_bytes(name, false));
+        data.extend(value);
+        data
+    }
+
+    fn node(node_type: u8, sub_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![node_type, sub_type];
+        data.extend((body.len() as u16 + 4).to_le_bytes());
+        data.extend(body);
+        data
+    }
+
+    // PciRoot(0x0)/Pci(0x1,0x3)/HD(1,GPT,...)/\EFI\BOOT\BOOTX64.EFI
+    fn device_path() -> Vec<u8> {
+        let mut hd = Vec::new();
+        hd.extend(1u32.to_le_bytes());
+        hd.extend(0x800u64.to_le_bytes());
+        hd.extend(0x100000u64.to_le_bytes());
+        hd.extend(guid("01020304-0506-0708-090a-0b0c0d0e0f10"));
+        hd.extend([2, 2]);
+        let mut path = node(0x02, 0x01, &[0xd0, 0x41, 0x03, 0x0a, 0, 0, 0, 0]);
+        path.extend(node(0x01, 0x01, &[0x01, 0x03])); // function, device
+        path.extend(node(0x04, 0x01, &hd));
+        path.extend(node(
+            0x04,
+            0x04,
+            &utf16_bytes("\\EFI\\BOOT\\BOOTX64.EFI", true),
+        ));
+        path.extend(node(0x7f, 0xff, &[]));
+        path
+    }
+
+    const DEVICE_PATH_TEXT: &str = "PciRoot(0x0)/Pci(0x3,0x1)/HD(1,GPT,01020304-0506-0708-090a-0b0c0d0e0f10,0x800,0x100000)/\\EFI\\BOOT\\BOOTX64.EFI";
+
+    fn signature_list(sig_type: &str, sig_size: usize, count: usize) -> Vec<u8> {
+        let mut data = guid(sig_type);
+        data.extend(((28 + sig_size * count) as u32).to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend((sig_size as u32).to_le_bytes());
+        data.extend(vec![0xaa; sig_size * count]);
+        data
+    }
+
+    fn image_load() -> Vec<u8> {
+        let path = device_path();
+        let mut data = Vec::new();
+        data.extend(0x7e000000u64.to_le_bytes());
+        data.extend(0x2000u64.to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+        data.extend((path.len() as u64).to_le_bytes());
+        data.extend(path);
+        data
+    }
+
+    fn blob2() -> Vec<u8> {
+        let mut data = vec![8];
+        data.extend(b"Fv(XXXX)");
+        data.extend(0xffc00000u64.to_le_bytes());
+        data.extend(0x84000u64.to_le_bytes());
+        data
+    }
+
+    fn handoff_tables(description: Option<&str>) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Some(d) = description {
+            data.push(d.len() as u8);
+            data.extend(d.as_bytes());
+        }
+        data.extend(2u64.to_le_bytes());
+        data.extend(guid("eb9d2d31-2d88-11d3-9a16-0090273fc14d"));
+        data.extend(0x7f000000u64.to_le_bytes());
+        data.extend(guid("8868e871-e4f1-11d3-bc22-0080c73c8881"));
+        data.extend(0x7f100000u64.to_le_bytes());
+        data
+    }
+
+    fn hob(hob_type: u16, body: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(hob_type.to_le_bytes());
+        data.extend((body.len() as u16 + 8).to_le_bytes());
+        data.extend([0; 4]);
+        data.extend(body);
+        data
+    }
+
+    fn tagged_hob_list() -> Vec<u8> {
+        let mut resource = vec![0; 16];
+        resource.extend(7u32.to_le_bytes());
+        resource.extend(0x3cu32.to_le_bytes());
+        resource.extend(0x100000u64.to_le_bytes());
+        resource.extend(0x7ff00000u64.to_le_bytes());
+        let mut fv = Vec::new();
+        fv.extend(0x820000u64.to_le_bytes());
+        fv.extend(0x5e0000u64.to_le_bytes());
+        let mut hobs = hob(EFI_HOB_TYPE_HANDOFF, &[0; 48]);
+        hobs.extend(hob(EFI_HOB_TYPE_RESOURCE_DESCRIPTOR, &resource));
+        hobs.extend(hob(
+            EFI_HOB_TYPE_GUID_EXTENSION,
+            &guid(EFI_GLOBAL_VARIABLE_GUID),
+        ));
+        hobs.extend(hob(EFI_HOB_TYPE_FV, &fv));
+        hobs.extend(hob(EFI_HOB_TYPE_END_OF_HOB_LIST, &[]));
+        let mut data = Vec::new();
+        data.extend(0x494e4954u32.to_le_bytes());
+        data.extend((hobs.len() as u32).to_le_bytes());
+        data.extend(hobs);
+        data
+    }
+
+    #[test]
+    fn efi_variables() {
+        let secure_boot = variable(EFI_GLOBAL_VARIABLE_GUID, "SecureBoot", &[1]);
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_DRIVER_CONFIG, secure_boot).decode(),
+            DecodedEvent::EfiVariable {
+                guid: EFI_GLOBAL_VARIABLE_GUID.to_string(),
+                name: "SecureBoot".to_string(),
+                summary: "enabled".to_string(),
+            }
+        );
+
+        let mut lists = signature_list(EFI_CERT_X509_GUID, 16, 2);
+        lists.extend(signature_list(EFI_CERT_SHA256_GUID, 48, 3));
+        let db = variable(EFI_IMAGE_SECURITY_DATABASE_GUID, "db", &lists);
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_DRIVER_CONFIG, db)
+                .decode()
+                .to_string(),
+            format!(
+                "db ({}): 2 x X509, 3 x SHA256",
+                EFI_IMAGE_SECURITY_DATABASE_GUID
+            )
+        );
+
+        let boot_order = variable(EFI_GLOBAL_VARIABLE_GUID, "BootOrder", &[1, 0, 0x0a, 0]);
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_BOOT, boot_order).decode().to_string(),
+            format!(
+                "BootOrder ({}): Boot0001,Boot000A",
+                EFI_GLOBAL_VARIABLE_GUID
+            )
+        );
+
+        let path = device_path();
+        let mut option = Vec::new();
+        option.extend(1u32.to_le_bytes());
+        option.extend((path.len() as u16).to_le_bytes());
+        option.extend(utf16_bytes("UEFI Disk", true));
+        option.extend(path);
+        let boot = variable(EFI_GLOBAL_VARIABLE_GUID, "Boot0001", &option);
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_BOOT2, boot).decode().to_string(),
+            format!(
+                "Boot0001 ({}): UEFI Disk {}",
+                EFI_GLOBAL_VARIABLE_GUID, DEVICE_PATH_TEXT
+            )
+        );
+
+        let other = variable(EFI_CERT_X509_GUID, "Vendor", &[0; 5]);
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_AUTHORITY, other).decode().to_string(),
+            format!("Vendor ({}): 5 bytes", EFI_CERT_X509_GUID)
+        );
+    }
+
+    #[test]
+    fn image_loads() {
+        assert_eq!(
+            entry(EV_EFI_BOOT_SERVICES_APPLICATION, image_load()).decode(),
+            DecodedEvent::ImageLoad {
+                location: 0x7e000000,
+                length: 0x2000,
+                link_time_address: 0,
+                device_path: DEVICE_PATH_TEXT.to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn device_paths() {
+        let mut path = node(0x03, 0x17, &[1, 0, 0, 0, 0, 0, 0, 0]);
+        path.extend(node(0x04, 0x07, &guid(EFI_GLOBAL_VARIABLE_GUID)));
+        path.extend(node(0x09, 0x01, &[0xab]));
+        assert_eq!(
+            format_device_path(&path),
+            format!("NVMe(0x1)/Fv({})/Path(9,1,ab)", EFI_GLOBAL_VARIABLE_GUID)
+        );
+        // a node length running past the end of the data
+        let mut bad = node(0x01, 0x01, &[0, 0]);
+        bad[2] = 0x40;
+        assert_eq!(format_device_path(&bad), "<malformed>");
+    }
+
+    #[test]
+    fn firmware_blobs() {
+        let mut blob = Vec::new();
+        blob.extend(0xffc00000u64.to_le_bytes());
+        blob.extend(0x400000u64.to_le_bytes());
+        assert_eq!(
+            entry(EV_EFI_PLATFORM_FIRMWARE_BLOB, blob).decode(),
+            DecodedEvent::FirmwareBlob {
+                description: None,
+                base: 0xffc00000,
+                length: 0x400000,
+            }
+        );
+        assert_eq!(
+            entry(EV_EFI_PLATFORM_FIRMWARE_BLOB2, blob2()).decode(),
+            DecodedEvent::FirmwareBlob {
+                description: Some("Fv(XXXX)".to_string()),
+                base: 0xffc00000,
+                length: 0x84000,
+            }
+        );
+    }
+
+    #[test]
+    fn handoff_table_events() {
+        let tables = vec![
+            (
+                "eb9d2d31-2d88-11d3-9a16-0090273fc14d".to_string(),
+                0x7f000000,
+            ),
+            (
+                "8868e871-e4f1-11d3-bc22-0080c73c8881".to_string(),
+                0x7f100000,
+            ),
+        ];
+        assert_eq!(
+            entry(EV_EFI_HANDOFF_TABLES, handoff_tables(None)).decode(),
+            DecodedEvent::HandoffTables {
+                description: None,
+                tables: tables.clone(),
+            }
+        );
+        assert_eq!(
+            entry(EV_EFI_HANDOFF_TABLES2, handoff_tables(Some("ACPI DATA"))).decode(),
+            DecodedEvent::HandoffTables {
+                description: Some("ACPI DATA".to_string()),
+                tables,
+            }
+        );
+    }
+
+    #[test]
+    fn tagged_hob_list_event() {
+        let data = tagged_hob_list();
+        let size = data.len() - 8;
+        assert_eq!(
+            entry(EV_EVENT_TAG, data).decode(),
+            DecodedEvent::TaggedEvent {
+                id: 0x494e4954,
+                size,
+                hobs: Some(vec![
+                    "Handoff".to_string(),
+                    "ResourceDescriptor(type 7, attributes 0x3c, start 0x100000, length 0x7ff00000)"
+                        .to_string(),
+                    format!("GuidExtension({})", EFI_GLOBAL_VARIABLE_GUID),
+                    "FirmwareVolume(base 0x820000, length 0x5e0000)".to_string(),
+                    "EndOfHobList".to_string(),
+                ]),
+            }
+        );
+        // tagged data that is not a HOB list
+        let mut other = vec![1, 0, 0, 0, 2, 0, 0, 0];
+        other.extend([0xde, 0xad]);
+        assert_eq!(
+            entry(EV_EVENT_TAG, other).decode(),
+            DecodedEvent::TaggedEvent {
+                id: 1,
+                size: 2,
+                hobs: None,
+            }
+        );
+    }
+
+    #[test]
+    fn text_events() {
+        assert_eq!(
+            entry(EV_IPL, b"grub_cmd: linux /vmlinuz\0".to_vec()).decode(),
+            DecodedEvent::Text("grub_cmd: linux /vmlinuz".to_string())
+        );
+        assert_eq!(
+            entry(EV_IPL, utf16_bytes("initrd=initrd.img", true)).decode(),
+            DecodedEvent::Text("initrd=initrd.img".to_string())
+        );
+        assert_eq!(
+            entry(
+                EV_EFI_ACTION,
+                b"Calling EFI Application from Boot Option".to_vec()
+            )
+            .decode(),
+            DecodedEvent::Text("Calling EFI Application from Boot Option".to_string())
+        );
+        assert_eq!(
+            entry(EV_SEPARATOR, vec![0; 4]).decode(),
+            DecodedEvent::Text("separator 0x0".to_string())
+        );
+        assert_eq!(
+            entry(EV_SEPARATOR, vec![0; 3]).decode(),
+            DecodedEvent::Raw(3)
+        );
+        assert_eq!(
+            entry(EV_COMPACT_HASH, vec![0; 7]).decode(),
+            DecodedEvent::Raw(7)
+        );
+    }
+
+    #[test]
+    fn truncated_events() {
+        let path = device_path();
+        let mut option = Vec::new();
+        option.extend(1u32.to_le_bytes());
+        option.extend((path.len() as u16).to_le_bytes());
+        option.extend(utf16_bytes("UEFI Disk", true));
+        option.extend(path);
+        let events = vec![
+            (
+                EV_EFI_VARIABLE_BOOT,
+                variable(EFI_GLOBAL_VARIABLE_GUID, "Boot0001", &option),
+            ),
+            (EV_EFI_BOOT_SERVICES_DRIVER, image_load()),
+            (EV_EFI_PLATFORM_FIRMWARE_BLOB, vec![0; 16]),
+            (EV_EFI_PLATFORM_FIRMWARE_BLOB2, blob2()),
+            (EV_EFI_HANDOFF_TABLES, handoff_tables(None)),
+            (EV_EFI_HANDOFF_TABLES2, handoff_tables(Some("ACPI DATA"))),
+            (EV_EVENT_TAG, tagged_hob_list()),
+        ];
+        for (event_type, data) in events {
+            for len in 0..data.len() {
+                assert_eq!(
+                    entry(event_type, data[..len].to_vec()).decode(),
+                    DecodedEvent::Raw(len),
+                    "{} truncated to {} bytes",
+                    event_type_name(event_type),
+                    len
+                );
+            }
+        }
+        // the variable data itself may be cut short, the summary must not read past it
+        let mut lists = signature_list(EFI_CERT_X509_GUID, 16, 2);
+        lists.truncate(40);
+        let db = variable(EFI_IMAGE_SECURITY_DATABASE_GUID, "db", &lists);
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_DRIVER_CONFIG, db)
+                .decode()
+                .to_string(),
+            format!(
+                "db ({}): <malformed signature list>",
+                EFI_IMAGE_SECURITY_DATABASE_GUID
+            )
+        );
+        option.truncate(option.len() - 10);
+        let boot = variable(EFI_GLOBAL_VARIABLE_GUID, "Boot0001", &option);
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_BOOT, boot).decode().to_string(),
+            format!("Boot0001 ({}): UEFI Disk ", EFI_GLOBAL_VARIABLE_GUID)
+        );
+    }
+
+    #[test]
+    fn oversized_lengths() {
+        // name and data lengths chosen to overflow the end offsets
+        let mut data = guid(EFI_GLOBAL_VARIABLE_GUID);
+        data.extend(u64::MAX.to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_BOOT, data.clone()).decode(),
+            DecodedEvent::Raw(32)
+        );
+        data[16..24].copy_from_slice(&0u64.to_le_bytes());
+        data[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            entry(EV_EFI_VARIABLE_BOOT, data).decode(),
+            DecodedEvent::Raw(32)
+        );
+
+        let mut image = vec![0; 24];
+        image.extend(u64::MAX.to_le_bytes());
+        assert_eq!(
+            entry(EV_EFI_BOOT_SERVICES_APPLICATION, image).decode(),
+            DecodedEvent::Raw(32)
+        );
+
+        let mut tables = u64::MAX.to_le_bytes().to_vec();
+        tables.extend(guid(EFI_GLOBAL_VARIABLE_GUID));
+        tables.extend(0u64.to_le_bytes());
+        assert_eq!(
+            entry(EV_EFI_HANDOFF_TABLES, tables).decode(),
+            DecodedEvent::Raw(32)
+        );
+
+        let mut tagged = 0u32.to_le_bytes().to_vec();
+        tagged.extend(u32::MAX.to_le_bytes());
+        assert_eq!(entry(EV_EVENT_TAG, tagged).decode(), DecodedEvent::Raw(8));
+
+        // a HOB length running past the end of the list
+        let mut hobs = hob(EFI_HOB_TYPE_HANDOFF, &[0; 48]);
+        hobs[2] = 0xff;
+        assert_eq!(decode_hob_list(&hobs), None);
+    }
+}
@@ -0,0 +1,581 @@
+use crate::evidence::hex_bytes;
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha384};
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::result::Result;
+use std::result::Result::Ok;
+
+// CCEL ACPI table and its log area, see the ACPI specification "CC Event Log ACPI Table"
+pub const CCEL_TABLE_PATH: &str = "/sys/firmware/acpi/tables/CCEL";
+pub const CCEL_DATA_PATH: &str = "/sys/firmware/acpi/tables/data/CCEL";
+const ACPI_HEADER_LEN: usize = 36;
+const CCEL_TABLE_LEN: usize = ACPI_HEADER_LEN + 4 + 8 + 8; // header, CC type/subtype/reserved, LAML, LASA
+
+// TCG algorithm ids, see TCG Algorithm Registry
+pub const TPM_ALG_SHA1: u16 = 0x0004;
+pub const TPM_ALG_SHA256: u16 = 0x000b;
+pub const TPM_ALG_SHA384: u16 = 0x000c;
+pub const TPM_ALG_SHA512: u16 = 0x000d;
+
+// TCG PC Client event types
+pub const EV_PREBOOT_CERT: u32 = 0x0;
+pub const EV_POST_CODE: u32 = 0x1;
+pub const EV_NO_ACTION: u32 = 0x3;
+pub const EV_SEPARATOR: u32 = 0x4;
+pub const EV_ACTION: u32 = 0x5;
+pub const EV_EVENT_TAG: u32 = 0x6;
+pub const EV_S_CRTM_CONTENTS: u32 = 0x7;
+pub const EV_S_CRTM_VERSION: u32 = 0x8;
+pub const EV_CPU_MICROCODE: u32 = 0x9;
+pub const EV_PLATFORM_CONFIG_FLAGS: u32 = 0xa;
+pub const EV_TABLE_OF_DEVICES: u32 = 0xb;
+pub const EV_COMPACT_HASH: u32 = 0xc;
+pub const EV_IPL: u32 = 0xd;
+pub const EV_IPL_PARTITION_DATA: u32 = 0xe;
+pub const EV_NONHOST_CODE: u32 = 0xf;
+pub const EV_NONHOST_CONFIG: u32 = 0x10;
+pub const EV_NONHOST_INFO: u32 = 0x11;
+pub const EV_OMIT_BOOT_DEVICE_EVENTS: u32 = 0x12;
+pub const EV_EFI_VARIABLE_DRIVER_CONFIG: u32 = 0x80000001;
+pub const EV_EFI_VARIABLE_BOOT: u32 = 0x80000002;
+pub const EV_EFI_BOOT_SERVICES_APPLICATION: u32 = 0x80000003;
+pub const EV_EFI_BOOT_SERVICES_DRIVER: u32 = 0x80000004;
+pub const EV_EFI_RUNTIME_SERVICES_DRIVER: u32 = 0x80000005;
+pub const EV_EFI_GPT_EVENT: u32 = 0x80000006;
+pub const EV_EFI_ACTION: u32 = 0x80000007;
+pub const EV_EFI_PLATFORM_FIRMWARE_BLOB: u32 = 0x80000008;
+pub const EV_EFI_HANDOFF_TABLES: u32 = 0x80000009;
+pub const EV_EFI_PLATFORM_FIRMWARE_BLOB2: u32 = 0x8000000a;
+pub const EV_EFI_HANDOFF_TABLES2: u32 = 0x8000000b;
+pub const EV_EFI_VARIABLE_BOOT2: u32 = 0x8000000c;
+pub const EV_EFI_HCRTM_EVENT: u32 = 0x80000010;
+pub const EV_EFI_VARIABLE_AUTHORITY: u32 = 0x800000e0;
+pub const EV_EFI_SPDM_FIRMWARE_BLOB: u32 = 0x800000e1;
+pub const EV_EFI_SPDM_FIRMWARE_CONFIG: u32 = 0x800000e2;
+
+const SPEC_ID_SIGNATURE: &[u8; 16] = b"Spec ID Event03\0";
+
+pub fn event_type_name(event_type: u32) -> &'static str {
+    match event_type {
+        EV_PREBOOT_CERT => "EV_PREBOOT_CERT",
+        EV_POST_CODE => "EV_POST_CODE",
+        EV_NO_ACTION => "EV_NO_ACTION",
+        EV_SEPARATOR => "EV_SEPARATOR",
+        EV_ACTION => "EV_ACTION",
+        EV_EVENT_TAG => "EV_EVENT_TAG",
+        EV_S_CRTM_CONTENTS => "EV_S_CRTM_CONTENTS",
+        EV_S_CRTM_VERSION => "EV_S_CRTM_VERSION",
+        EV_CPU_MICROCODE => "EV_CPU_MICROCODE",
+        EV_PLATFORM_CONFIG_FLAGS => "EV_PLATFORM_CONFIG_FLAGS",
+        EV_TABLE_OF_DEVICES => "EV_TABLE_OF_DEVICES",
+        EV_COMPACT_HASH => "EV_COMPACT_HASH",
+        EV_IPL => "EV_IPL",
+        EV_IPL_PARTITION_DATA => "EV_IPL_PARTITION_DATA",
+        EV_NONHOST_CODE => "EV_NONHOST_CODE",
+        EV_NONHOST_CONFIG => "EV_NONHOST_CONFIG",
+        EV_NONHOST_INFO => "EV_NONHOST_INFO",
+        EV_OMIT_BOOT_DEVICE_EVENTS => "EV_OMIT_BOOT_DEVICE_EVENTS",
+        EV_EFI_VARIABLE_DRIVER_CONFIG => "EV_EFI_VARIABLE_DRIVER_CONFIG",
+        EV_EFI_VARIABLE_BOOT => "EV_EFI_VARIABLE_BOOT",
+        EV_EFI_BOOT_SERVICES_APPLICATION => "EV_EFI_BOOT_SERVICES_APPLICATION",
+        EV_EFI_BOOT_SERVICES_DRIVER => "EV_EFI_BOOT_SERVICES_DRIVER",
+        EV_EFI_RUNTIME_SERVICES_DRIVER => "EV_EFI_RUNTIME_SERVICES_DRIVER",
+        EV_EFI_GPT_EVENT => "EV_EFI_GPT_EVENT",
+        EV_EFI_ACTION => "EV_EFI_ACTION",
+        EV_EFI_PLATFORM_FIRMWARE_BLOB => "EV_EFI_PLATFORM_FIRMWARE_BLOB",
+        EV_EFI_HANDOFF_TABLES => "EV_EFI_HANDOFF_TABLES",
+        EV_EFI_PLATFORM_FIRMWARE_BLOB2 => "EV_EFI_PLATFORM_FIRMWARE_BLOB2",
+        EV_EFI_HANDOFF_TABLES2 => "EV_EFI_HANDOFF_TABLES2",
+        EV_EFI_VARIABLE_BOOT2 => "EV_EFI_VARIABLE_BOOT2",
+        EV_EFI_HCRTM_EVENT => "EV_EFI_HCRTM_EVENT",
+        EV_EFI_VARIABLE_AUTHORITY => "EV_EFI_VARIABLE_AUTHORITY",
+        EV_EFI_SPDM_FIRMWARE_BLOB => "EV_EFI_SPDM_FIRMWARE_BLOB",
+        EV_EFI_SPDM_FIRMWARE_CONFIG => "EV_EFI_SPDM_FIRMWARE_CONFIG",
+        _ => "UNKNOWN",
+    }
+}
+
+// little-endian cursor over the event log, every read is bounds checked
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, what: &str) -> Result<&'a [u8], anyhow::Error> {
+        if self.bytes.len() - self.offset < len {
+            return Err(anyhow!(
+                "[EventLog::from_bytes] Truncated event log reading {} at offset {}",
+                what,
+                self.offset
+            ));
+        }
+        let slice = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self, what: &str) -> Result<u8, anyhow::Error> {
+        Ok(self.take(1, what)?[0])
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, anyhow::Error> {
+        Ok(u16::from_le_bytes(self.take(2, what)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32, anyhow::Error> {
+        Ok(u32::from_le_bytes(self.take(4, what)?.try_into().unwrap()))
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+}
+
+/// TCG_EfiSpecIDEvent carried by the first (SHA1 format) event of a crypto-agile log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecIdEvent {
+    pub platform_class: u32,
+    pub spec_version_minor: u8,
+    pub spec_version_major: u8,
+    pub spec_errata: u8,
+    pub uintn_size: u8,
+    #[serde(with = "algorithm_sizes")]
+    pub algorithms: Vec<(u16, u16)>, // algorithm id and digest size
+    #[serde(with = "hex_bytes")]
+    pub vendor_info: Vec<u8>,
+}
+
+impl SpecIdEvent {
+    fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = Reader {
+            bytes: data,
+            offset: 0,
+        };
+        if reader.take(16, "spec id signature")? != SPEC_ID_SIGNATURE {
+            return Err(anyhow!(
+                "[EventLog::from_bytes] First event is not a crypto-agile Spec ID event"
+            ));
+        }
+        let platform_class = reader.u32("platform class")?;
+        let spec_version_minor = reader.u8("spec version minor")?;
+        let spec_version_major = reader.u8("spec version major")?;
+        let spec_errata = reader.u8("spec errata")?;
+        let uintn_size = reader.u8("uintn size")?;
+        let count = reader.u32("number of algorithms")?;
+        let mut algorithms = Vec::new();
+        for _ in 0..count {
+            let alg = reader.u16("algorithm id")?;
+            let size = reader.u16("digest size")?;
+            algorithms.push((alg, size));
+        }
+        let vendor_info_size = reader.u8("vendor info size")? as usize;
+        let vendor_info = reader.take(vendor_info_size, "vendor info")?.to_vec();
+
+        Ok(SpecIdEvent {
+            platform_class,
+            spec_version_minor,
+            spec_version_major,
+            spec_errata,
+            uintn_size,
+            algorithms,
+            vendor_info,
+        })
+    }
+
+    fn digest_size(&self, alg: u16) -> Option<usize> {
+        self.algorithms
+            .iter()
+            .find(|(id, _)| *id == alg)
+            .map(|(_, size)| *size as usize)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventLogEntry {
+    pub mr_index: u32, // 0 = MRTD, 1..4 = RTMR0..3
+    pub event_type: u32,
+    #[serde(with = "algorithm_digests")]
+    pub digests: Vec<(u16, Vec<u8>)>, // algorithm id and digest
+    #[serde(with = "hex_bytes")]
+    pub event: Vec<u8>,
+}
+
+impl EventLogEntry {
+    pub fn digest(&self, alg: u16) -> Option<&[u8]> {
+        self.digests
+            .iter()
+            .find(|(id, _)| *id == alg)
+            .map(|(_, d)| d.as_slice())
+    }
+
+    // RTMR register the entry was extended into
+    pub fn rtmr_index(&self) -> Option<usize> {
+        match self.mr_index {
+            1..=4 => Some(self.mr_index as usize - 1),
+            _ => None,
+        }
+    }
+}
+
+// (algorithm id, digest) pairs as {"algorithm", "digest"} objects
+mod algorithm_digests {
+    use crate::evidence::hex_bytes;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::result::Result;
+
+    #[derive(Serialize, Deserialize)]
+    struct AlgorithmDigest {
+        algorithm: u16,
+        #[serde(with = "hex_bytes")]
+        digest: Vec<u8>,
+    }
+
+    pub fn serialize<S: Serializer>(digests: &[(u16, Vec<u8>)], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(digests.iter().map(|(algorithm, digest)| AlgorithmDigest {
+            algorithm: *algorithm,
+            digest: digest.clone(),
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<(u16, Vec<u8>)>, D::Error> {
+        let digests = Vec::<AlgorithmDigest>::deserialize(d)?;
+        Ok(digests
+            .into_iter()
+            .map(|d| (d.algorithm, d.digest))
+            .collect())
+    }
+}
+
+// (algorithm id, digest size) pairs as {"algorithm", "digest_size"} objects
+mod algorithm_sizes {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::result::Result;
+
+    #[derive(Serialize, Deserialize)]
+    struct AlgorithmSize {
+        algorithm: u16,
+        digest_size: u16,
+    }
+
+    pub fn serialize<S: Serializer>(algorithms: &[(u16, u16)], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(
+            algorithms
+                .iter()
+                .map(|(algorithm, digest_size)| AlgorithmSize {
+                    algorithm: *algorithm,
+                    digest_size: *digest_size,
+                }),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<(u16, u16)>, D::Error> {
+        let algorithms = Vec::<AlgorithmSize>::deserialize(d)?;
+        Ok(algorithms
+            .into_iter()
+            .map(|a| (a.algorithm, a.digest_size))
+            .collect())
+    }
+}
+
+/// TCG PC Client crypto-agile (TCG2) event log, as exposed by the CCEL ACPI table.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventLog {
+    pub spec_id: SpecIdEvent,
+    pub entries: Vec<EventLogEntry>,
+}
+
+impl EventLog {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        //the first event uses the TCG_PCClientPCREvent (SHA1) format
+        reader.u32("mr index")?;
+        let event_type = reader.u32("event type")?;
+        reader.take(20, "sha1 digest")?;
+        let event_size = reader.u32("event size")? as usize;
+        let event = reader.take(event_size, "event")?;
+        if event_type != EV_NO_ACTION {
+            return Err(anyhow!(
+                "[EventLog::from_bytes] First event has type {:#x}, expected EV_NO_ACTION",
+                event_type
+            ));
+        }
+        let spec_id = SpecIdEvent::parse(event)?;
+
+        let mut entries = Vec::new();
+        loop {
+            //the unused part of the log area is 0xff (or zero) filled
+            let rest = reader.remaining();
+            if rest.len() < 8
+                || rest[0..4] == [0xff; 4]
+                || rest[0..8] == [0; 8]
+                || rest.iter().all(|b| *b == 0xff)
+            {
+                break;
+            }
+
+            let mr_index = reader.u32("mr index")?;
+            let event_type = reader.u32("event type")?;
+            let count = reader.u32("digest count")?;
+            let mut digests = Vec::new();
+            for _ in 0..count {
+                let alg = reader.u16("algorithm id")?;
+                let size = match spec_id.digest_size(alg) {
+                    None => {
+                        return Err(anyhow!(
+                        "[EventLog::from_bytes] Algorithm {:#x} not declared in the Spec ID event",
+                        alg
+                    ))
+                    }
+                    Some(s) => s,
+                };
+                digests.push((alg, reader.take(size, "digest")?.to_vec()));
+            }
+            let event_size = reader.u32("event size")? as usize;
+            let event = reader.take(event_size, "event")?.to_vec();
+
+            entries.push(EventLogEntry {
+                mr_index,
+                event_type,
+                digests,
+                event,
+            });
+        }
+
+        Ok(EventLog { spec_id, entries })
+    }
+
+    // raw event log, e.g. a captured copy of the CCEL log area
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        match fs::read(path) {
+            Err(e) => Err(anyhow!(
+                "[EventLog::load] Fail to read {}: {:?}",
+                path.display(),
+                e
+            )),
+            Ok(bytes) => EventLog::from_bytes(&bytes),
+        }
+    }
+
+    // the CCEL table gives the log area minimum length, the data file holds the log area itself
+    pub fn load_ccel(table: &Path, data: &Path) -> Result<Self, anyhow::Error> {
+        let table_bytes = match fs::read(table) {
+            Err(e) => {
+                return Err(anyhow!(
+                    "[EventLog::load_ccel] Fail to read {}: {:?}",
+                    table.display(),
+                    e
+                ))
+            }
+            Ok(t) => t,
+        };
+        if table_bytes.len() < CCEL_TABLE_LEN || &table_bytes[0..4] != b"CCEL" {
+            return Err(anyhow!(
+                "[EventLog::load_ccel] {} is not a CCEL table",
+                table.display()
+            ));
+        }
+        let laml = u64::from_le_bytes(
+            table_bytes[ACPI_HEADER_LEN + 4..ACPI_HEADER_LEN + 12]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+
+        let log = match fs::read(data) {
+            Err(e) => {
+                return Err(anyhow!(
+                    "[EventLog::load_ccel] Fail to read {}: {:?}",
+                    data.display(),
+                    e
+                ))
+            }
+            Ok(l) => l,
+        };
+        EventLog::from_bytes(&log[0..laml.min(log.len())])
+    }
+
+    pub fn read_ccel() -> Result<Self, anyhow::Error> {
+        EventLog::load_ccel(Path::new(CCEL_TABLE_PATH), Path::new(CCEL_DATA_PATH))
+    }
+
+    // RTMR[i] = SHA384(RTMR[i] || digest) for every measured SHA384 digest, starting from zero
+    pub fn replay_rtmrs(&self) -> Result<[[u8; 48]; 4], anyhow::Error> {
+        let mut rtmrs = [[0u8; 48]; 4];
+        for entry in &self.entries {
+            if entry.event_type == EV_NO_ACTION {
+                continue;
+            }
+            let index = match entry.rtmr_index() {
+                None => continue,
+                Some(i) => i,
+            };
+            let digest = match entry.digest(TPM_ALG_SHA384) {
+                None => {
+                    return Err(anyhow!(
+                        "[EventLog::replay_rtmrs] {} event for RTMR{} has no SHA384 digest",
+                        event_type_name(entry.event_type),
+                        index
+                    ))
+                }
+                Some(d) => d,
+            };
+            let mut hasher = Sha384::new();
+            hasher.update(rtmrs[index]);
+            hasher.update(digest);
+            rtmrs[index] = hasher.finalize().into();
+        }
+        Ok(rtmrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Sha256;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    // synthetic TCG2 log: Spec ID event declaring SHA1, SHA256 and SHA384, six measured events
+    // for RTMR0..2 carrying all three digests, an EV_NO_ACTION event, then 0xff padding
+    const FIXTURE: &str = "tests/data/tcg2_eventlog.bin";
+    const FIXTURE_LOG_LEN: usize = 1103 - 64;
+    const FIRST_ENTRY_OFFSET: usize = 32 + 41; // SHA1 format header and Spec ID event
+
+    // RTMR values replayed independently with Python's hashlib when the fixture was generated
+    const FIXTURE_RTMRS: [&str; 3] = [
+        "6469be3561f5cc756aff80211e706fbe875d0ef7a1f216c6c65fae0bfb59a54bc2d5038200e43b534a6ba4852f836bbf",
+        "72ba939e3acde8777a96721938fc6ea9b3be93dbf5a783956cb61faac836f308863e10b1f42c528214a099669961be1b",
+        "e17dbab208d8f445ec22b657f8edb198f979935fa45bd5b7fd977d75679dc0f9be7a2ebf6e3aa1321265b08d3bc5afc0",
+    ];
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(FIXTURE)
+    }
+
+    #[test]
+    fn spec_id_header() {
+        let log = EventLog::load(&fixture()).unwrap();
+        assert_eq!(log.spec_id.platform_class, 0);
+        assert_eq!(
+            (
+                log.spec_id.spec_version_major,
+                log.spec_id.spec_version_minor
+            ),
+            (2, 0)
+        );
+        assert_eq!(log.spec_id.uintn_size, 2);
+        assert_eq!(
+            log.spec_id.algorithms,
+            [
+                (TPM_ALG_SHA1, 20),
+                (TPM_ALG_SHA256, 32),
+                (TPM_ALG_SHA384, 48)
+            ]
+        );
+        assert!(log.spec_id.vendor_info.is_empty());
+    }
+
+    #[test]
+    fn multi_algorithm_entries() {
+        let log = EventLog::load(&fixture()).unwrap();
+        assert_eq!(log.entries.len(), 7);
+        let types: Vec<u32> = log.entries.iter().map(|e| e.event_type).collect();
+        assert_eq!(
+            types,
+            [
+                EV_EFI_PLATFORM_FIRMWARE_BLOB,
+                EV_EFI_VARIABLE_DRIVER_CONFIG,
+                EV_SEPARATOR,
+                EV_EFI_ACTION,
+                EV_EFI_BOOT_SERVICES_APPLICATION,
+                EV_IPL,
+                EV_NO_ACTION
+            ]
+        );
+
+        //every digest is read with the size the Spec ID event declares for its algorithm
+        let app = &log.entries[4];
+        assert_eq!(app.rtmr_index(), Some(1));
+        assert!(app.event.is_empty());
+        assert_eq!(app.digest(TPM_ALG_SHA1).unwrap().len(), 20);
+        assert_eq!(
+            app.digest(TPM_ALG_SHA256).unwrap(),
+            &Sha256::digest(b"kernel image")[..]
+        );
+        assert_eq!(
+            app.digest(TPM_ALG_SHA384).unwrap(),
+            &Sha384::digest(b"kernel image")[..]
+        );
+        assert_eq!(log.entries[5].event, b"grub_cmd: linux /vmlinuz\0");
+    }
+
+    #[test]
+    fn replay_known_rtmrs() {
+        let rtmrs = EventLog::load(&fixture()).unwrap().replay_rtmrs().unwrap();
+        for (rtmr, expected) in rtmrs.iter().zip(FIXTURE_RTMRS) {
+            assert_eq!(hex::encode(rtmr), expected);
+        }
+        assert_eq!(rtmrs[3], [0; 48]);
+    }
+
+    #[test]
+    fn truncated_log() {
+        let bytes = fs::read(fixture()).unwrap();
+        //any prefix either parses the events it holds completely or fails, never panics
+        for len in 0..FIXTURE_LOG_LEN {
+            if let Ok(log) = EventLog::from_bytes(&bytes[..len]) {
+                assert!(log.entries.len() < 7);
+            }
+        }
+        assert!(EventLog::from_bytes(&bytes[..10]).is_err());
+        //cut inside the SHA384 digest of the first entry
+        let result = EventLog::from_bytes(&bytes[..FIRST_ENTRY_OFFSET + 100]);
+        assert!(result.unwrap_err().to_string().contains("Truncated"));
+    }
+
+    #[test]
+    fn undeclared_algorithm() {
+        let mut bytes = fs::read(fixture()).unwrap();
+        //first digest of the first entry claims SHA512
+        bytes[FIRST_ENTRY_OFFSET + 12..FIRST_ENTRY_OFFSET + 14]
+            .copy_from_slice(&TPM_ALG_SHA512.to_le_bytes());
+        let result = EventLog::from_bytes(&bytes);
+        assert!(result.unwrap_err().to_string().contains("not declared"));
+    }
+
+    #[test]
+    fn replay_needs_sha384() {
+        let mut log = EventLog::load(&fixture()).unwrap();
+        log.entries[0]
+            .digests
+            .retain(|(alg, _)| *alg != TPM_ALG_SHA384);
+        assert!(log.replay_rtmrs().is_err());
+    }
+
+    #[test]
+    fn ccel_log_area() {
+        let dir = env::temp_dir().join(format!("ccel-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        //the log area is larger than the log, LAML bounds what is parsed
+        let mut data = fs::read(fixture()).unwrap();
+        data.extend([0xff; 4096]);
+        let mut table = b"CCEL".to_vec();
+        table.resize(ACPI_HEADER_LEN + 4, 0);
+        table.extend((FIXTURE_LOG_LEN as u64 + 64).to_le_bytes());
+        table.extend(0u64.to_le_bytes());
+        fs::write(dir.join("table"), &table).unwrap();
+        fs::write(dir.join("data"), &data).unwrap();
+
+        let log = EventLog::load_ccel(&dir.join("table"), &dir.join("data")).unwrap();
+        assert_eq!(log, EventLog::load(&fixture()).unwrap());
+
+        fs::write(dir.join("table"), &table[..CCEL_TABLE_LEN - 1]).unwrap();
+        assert!(EventLog::load_ccel(&dir.join("table"), &dir.join("data")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+// JSON and CBOR encodings of TDREPORTs, quotes and event logs, so evidence captured by one
+// tool can be handed to another. Byte fields are hex strings in JSON and byte strings in CBOR.
+use anyhow::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::result::Result;
+use std::result::Result::Ok;
+
+pub fn to_json<T: Serialize>(value: &T) -> Result<String, anyhow::Error> {
+    match serde_json::to_string_pretty(value) {
+        Err(e) => Err(anyhow!("[evidence] Fail to encode JSON: {:?}", e)),
+        Ok(json) => Ok(json),
+    }
+}
+
+pub fn from_json<T: DeserializeOwned>(json: &[u8]) -> Result<T, anyhow::Error> {
+    match serde_json::from_slice(json) {
+        Err(e) => Err(anyhow!("[evidence] Bad JSON: {}", e)),
+        Ok(value) => Ok(value),
+    }
+}
+
+pub fn to_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, anyhow::Error> {
+    let mut cbor = Vec::new();
+    match ciborium::ser::into_writer(value, &mut cbor) {
+        Err(e) => Err(anyhow!("[evidence] Fail to encode CBOR: {:?}", e)),
+        Ok(_) => Ok(cbor),
+    }
+}
+
+pub fn from_cbor<T: DeserializeOwned>(cbor: &[u8]) -> Result<T, anyhow::Error> {
+    match ciborium::de::from_reader(cbor) {
+        Err(e) => Err(anyhow!("[evidence] Bad CBOR: {}", e)),
+        Ok(value) => Ok(value),
+    }
+}
+
+// #[serde(with = "hex_bytes")] for [u8; N] and Vec<u8> fields
+pub(crate) mod hex_bytes {
+    use serde::de::{Error, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+    use std::result::Result;
+    use std::result::Result::Ok;
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(bytes: &T, s: S) -> Result<S::Ok, S::Error> {
+        if s.is_human_readable() {
+            s.serialize_str(&hex::encode(bytes))
+        } else {
+            s.serialize_bytes(bytes.as_ref())
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a hex string or a byte string")
+        }
+
+        fn visit_str<E: Error>(self, v: &str) -> Result<Vec<u8>, E> {
+            hex::decode(v).map_err(|e| E::custom(format!("bad hex: {}", e)))
+        }
+
+        fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::new();
+            while let Some(b) = seq.next_element()? {
+                bytes.push(b);
+            }
+            Ok(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<T, D::Error> {
+        let bytes = if d.is_human_readable() {
+            d.deserialize_str(BytesVisitor)?
+        } else {
+            d.deserialize_byte_buf(BytesVisitor)?
+        };
+        let len = bytes.len();
+        T::try_from(bytes).map_err(|_| D::Error::custom(format!("wrong length {}", len)))
+    }
+
+    // same for Option<[u8; N]>, absent or null for None
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use std::result::Result;
+        use std::result::Result::Ok;
+
+        #[derive(Serialize, Deserialize)]
+        #[serde(transparent)]
+        struct Bytes(#[serde(with = "super")] Vec<u8>);
+
+        pub fn serialize<T: AsRef<[u8]>, S: Serializer>(
+            bytes: &Option<T>,
+            s: S,
+        ) -> Result<S::Ok, S::Error> {
+            bytes
+                .as_ref()
+                .map(|b| Bytes(b.as_ref().to_vec()))
+                .serialize(s)
+        }
+
+        pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(
+            d: D,
+        ) -> Result<Option<T>, D::Error> {
+            match Option::<Bytes>::deserialize(d)? {
+                None => Ok(None),
+                Some(Bytes(bytes)) => {
+                    let len = bytes.len();
+                    match T::try_from(bytes) {
+                        Err(_) => Err(serde::de::Error::custom(format!("wrong length {}", len))),
+                        Ok(b) => Ok(Some(b)),
+                    }
+                }
+            }
+        }
+    }
+
+    // same for lists of byte arrays such as the four RTMRs
+    pub mod list {
+        use serde::ser::SerializeSeq;
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use std::result::Result;
+        use std::result::Result::Ok;
+
+        #[derive(Serialize, Deserialize)]
+        #[serde(transparent)]
+        struct Bytes(#[serde(with = "super")] Vec<u8>);
+
+        pub fn serialize<T: AsRef<[u8]>, S: Serializer>(
+            list: &[T],
+            s: S,
+        ) -> Result<S::Ok, S::Error> {
+            let mut seq = s.serialize_seq(Some(list.len()))?;
+            for bytes in list {
+                seq.serialize_element(&Bytes(bytes.as_ref().to_vec()))?;
+            }
+            seq.end()
+        }
+
+        pub fn deserialize<'de, T, C, D>(d: D) -> Result<C, D::Error>
+        where
+            T: TryFrom<Vec<u8>>,
+            C: TryFrom<Vec<T>>,
+            D: Deserializer<'de>,
+        {
+            let mut list = Vec::new();
+            for Bytes(bytes) in Vec::<Bytes>::deserialize(d)? {
+                let len = bytes.len();
+                match T::try_from(bytes) {
+                    Err(_) => {
+                        return Err(serde::de::Error::custom(format!("wrong length {}", len)))
+                    }
+                    Ok(b) => list.push(b),
+                }
+            }
+            let count = list.len();
+            C::try_from(list)
+                .map_err(|_| serde::de::Error::custom(format!("wrong count {}", count)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eventlog::EventLog;
+    use crate::quote::Quote;
+    use crate::simulator::{SimulatedBackend, SimulatedTdConfig};
+    use crate::tdreport::TdReport;
+    use crate::tee_tdx_lib::ReportData;
+    use serde::Deserialize;
+    use serde_json::Value;
+    use std::path::Path;
+
+    // a TDREPORT and its quote with distinct RTMRs, so a reordered list shows up
+    fn evidence() -> (TdReport, Quote) {
+        let mut config = SimulatedTdConfig {
+            mrtd: [0x11; 48],
+            ..SimulatedTdConfig::default()
+        };
+        for (i, rtmr) in config.rtmrs.iter_mut().enumerate() {
+            *rtmr = [i as u8 + 1; 48];
+        }
+        let backend = SimulatedBackend::new(config).unwrap();
+        let report = backend.build_report(&ReportData([5; 64]));
+        let quote = backend.quote_for_report(&report).unwrap();
+        (
+            TdReport::from_bytes(&report).unwrap(),
+            Quote::from_bytes(&quote).unwrap(),
+        )
+    }
+
+    fn event_log() -> EventLog {
+        EventLog::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/tcg2_eventlog.bin"))
+            .unwrap()
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let (report, quote) = evidence();
+        assert_eq!(
+            from_json::<TdReport>(to_json(&report).unwrap().as_bytes()).unwrap(),
+            report
+        );
+        assert_eq!(
+            from_json::<Quote>(to_json(&quote).unwrap().as_bytes()).unwrap(),
+            quote
+        );
+        let log = event_log();
+        assert_eq!(
+            from_json::<EventLog>(to_json(&log).unwrap().as_bytes()).unwrap(),
+            log
+        );
+    }
+
+    #[test]
+    fn cbor_round_trip() {
+        let (report, quote) = evidence();
+        assert_eq!(
+            from_cbor::<TdReport>(&to_cbor(&report).unwrap()).unwrap(),
+            report
+        );
+        assert_eq!(
+            from_cbor::<Quote>(&to_cbor(&quote).unwrap()).unwrap(),
+            quote
+        );
+        let log = event_log();
+        assert_eq!(from_cbor::<EventLog>(&to_cbor(&log).unwrap()).unwrap(), log);
+    }
+
+    #[test]
+    fn byte_fields_encoding() {
+        let (_, quote) = evidence();
+        let json: Value = serde_json::from_str(&to_json(&quote).unwrap()).unwrap();
+        assert_eq!(json["body"]["mrtd"], hex::encode([0x11; 48]));
+        let rtmrs: Vec<String> = (1..=4).map(|i| hex::encode([i as u8; 48])).collect();
+        assert_eq!(json["body"]["rtmrs"], serde_json::json!(rtmrs));
+
+        let cbor: ciborium::Value = from_cbor(&to_cbor(&quote).unwrap()).unwrap();
+        let body = cbor
+            .as_map()
+            .unwrap()
+            .iter()
+            .find(|(k, _)| k.as_text() == Some("body"))
+            .unwrap()
+            .1
+            .as_map()
+            .unwrap();
+        let field = |name: &str| {
+            &body
+                .iter()
+                .find(|(k, _)| k.as_text() == Some(name))
+                .unwrap()
+                .1
+        };
+        assert_eq!(field("mrtd").as_bytes().unwrap(), &[0x11; 48]);
+        let rtmrs = field("rtmrs").as_array().unwrap();
+        assert_eq!(rtmrs.len(), 4);
+        assert_eq!(rtmrs[3].as_bytes().unwrap(), &[4; 48]);
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Fixed {
+        #[serde(with = "hex_bytes")]
+        digest: [u8; 4],
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Registers {
+        #[serde(with = "hex_bytes::list")]
+        rtmrs: [[u8; 2]; 4],
+    }
+
+    #[test]
+    fn wrong_lengths() {
+        assert_eq!(
+            from_json::<Fixed>(br#"{"digest": "00010203"}"#)
+                .unwrap()
+                .digest,
+            [0, 1, 2, 3]
+        );
+        let e = from_json::<Fixed>(br#"{"digest": "000102"}"#).unwrap_err();
+        assert!(e.to_string().contains("wrong length 3"), "{}", e);
+        assert!(from_json::<Fixed>(br#"{"digest": "0001020z"}"#).is_err());
+
+        assert_eq!(
+            from_json::<Registers>(br#"{"rtmrs": ["0000", "0101", "0202", "0303"]}"#)
+                .unwrap()
+                .rtmrs,
+            [[0, 0], [1, 1], [2, 2], [3, 3]]
+        );
+        let e = from_json::<Registers>(br#"{"rtmrs": ["0000", "0101", "0202"]}"#).unwrap_err();
+        assert!(e.to_string().contains("wrong count 3"), "{}", e);
+        let e =
+            from_json::<Registers>(br#"{"rtmrs": ["0000", "0101", "0202", "03"]}"#).unwrap_err();
+        assert!(e.to_string().contains("wrong length 1"), "{}", e);
+
+        let (_, quote) = evidence();
+        let mut json: Value = serde_json::from_str(&to_json(&quote).unwrap()).unwrap();
+        json["body"]["rtmrs"][0] = Value::String("00".repeat(47));
+        assert!(from_json::<Quote>(json.to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn decoded_fields_must_match_raw() {
+        let (report, quote) = evidence();
+        let mut json: Value = serde_json::from_str(&to_json(&quote).unwrap()).unwrap();
+        json["body"]["mrtd"] = Value::String(hex::encode([0x22; 48]));
+        let e = from_json::<Quote>(json.to_string().as_bytes()).unwrap_err();
+        assert!(e.to_string().contains("do not match"), "{}", e);
+
+        let mut json: Value = serde_json::from_str(&to_json(&report).unwrap()).unwrap();
+        json["td_info"]["rtmrs"][2] = Value::String(hex::encode([0; 48]));
+        let e = from_json::<TdReport>(json.to_string().as_bytes()).unwrap_err();
+        assert!(e.to_string().contains("do not match"), "{}", e);
+    }
+}
@@ -0,0 +1,17 @@
+pub mod authenticode;
+pub mod collateral;
+pub mod crl;
+pub mod event_decode;
+pub mod eventlog;
+pub mod evidence;
+pub mod policy;
+pub mod qgs;
+pub mod qgs_socket;
+pub mod quote;
+pub mod rtmr_predict;
+pub mod simulator;
+pub mod tdreport;
+pub mod tdvf;
+pub mod tee_tdx_lib;
+pub mod tsm;
+pub mod verify;
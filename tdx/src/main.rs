@@ -1,16 +1,801 @@
-pub mod tee_tdx_lib;
-use tee_tdx_lib::*;
-
-fn main() {
-    let hash = [
-        250, 88, 93, 137, 200, 81, 221, 51, 138, 112, 220, 245, 53, 170, 42, 146, 254, 231, 131,
-        109, 214, 175, 241, 34, 101, 131, 232, 142, 9, 150, 41, 63, 22, 188, 0, 156, 101, 40, 38,
-        224, 252, 92, 112, 102, 149, 160, 60, 221, 206, 55, 47, 19, 158, 255, 77, 19, 149, 157,
-        166, 241, 245, 211, 234, 190,
-    ];
-    let q = match get_tdx_quote(base64::encode(hash)) {
-        Err(e) => panic!("Fail to get TDX quote: {:?}", e),
-        Ok(q) => q,
-    };
-    println!("quote size = {}", q.len());
-}
\ No newline at end of file
+// Command line front end to the library: TDREPORTs, quotes, RTMRs and the event log from
+// inside a TD, and offline inspection and verification of saved reports and quotes.
+use anyhow::*;
+use ioctl::collateral::*;
+use ioctl::crl::Crl;
+use ioctl::event_decode::diff_rtmrs;
+use ioctl::eventlog::*;
+use ioctl::evidence::*;
+use ioctl::policy::Policy;
+use ioctl::qgs_socket::{QgsAddress, QgsSocketBackend};
+use ioctl::quote::Quote;
+use ioctl::rtmr_predict::{GrubPaths, LinuxBoot};
+use ioctl::tdreport::TdReport;
+use ioctl::tdvf::TdvfMetadata;
+use ioctl::tee_tdx_lib::*;
+use ioctl::verify::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::result::Result;
+use std::result::Result::Ok;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const USAGE: &str = "usage: ioctl [--device PATH] [--qgs ADDR] COMMAND ...
+
+commands:
+    report [DATA] [--out PATH] [--format raw|hex|base64|json|cbor]
+    quote [DATA] [--timeout SECS] [--out PATH] [--format raw|hex|base64|json|cbor]
+    inspect FILE [--out PATH --format raw|hex|base64|json|cbor]
+    verify QUOTE --root CERT [--collateral DIR] [--crl CRL]... [--policy FILE]
+    rtmr extend INDEX (--digest HEX | --event FILE)
+    rtmr predict --kernel BZIMAGE [--initrd FILE] [--cmdline STR] [--boot-loader PE]...
+        [--grub PATH [--grub-initrd PATH]] [--compare REPORT_OR_QUOTE] [--out PATH --format json|cbor]
+    eventlog [--file PATH] [--replay] [--compare REPORT_OR_QUOTE] [--out PATH --format json|cbor]
+    mrtd FIRMWARE [--sections]
+
+DATA is the report data, zero padded to 64 bytes: --hex HEX, --base64 B64, --file PATH or
+--stdin. Without it the report data is all zeroes. Output goes to stdout unless --out is given.
+--device uses that TDX guest device node, --qgs takes quotes from QGS at vsock:CID:PORT,
+unix:PATH or tcp:HOST:PORT instead of the GetQuote ioctl.
+Reports, quotes and event logs are read raw or as JSON or CBOR documents written by --format.
+inspect detects whether FILE holds a TDREPORT or a quote, --format converts it instead of
+printing it. verify checks the quote signatures against the trusted root CERT (PEM or DER),
+and with --collateral its TCB status against the tcb_info.json, qe_identity.json and
+tcb_signing_chain.pem in DIR, with --policy its measurements against the reference values in
+a TOML or .json policy file.
+rtmr extend --event extends SHA384 of the file contents. rtmr predict prints the RTMR1 and
+RTMR2 events and values of a Linux boot through TDVF: directly, or through the --boot-loader
+images (shim, grub) in load order with --grub giving the kernel and initrd paths of its linux
+and initrd commands. --compare checks them against a report or quote.
+eventlog reads the CCEL log unless --file is given, --replay prints the RTMRs it yields,
+--compare diffs them against a report or quote, --format converts the log instead of dumping
+it.
+mrtd prints the MRTD a TD booted from the TDVF/OVMF image FIRMWARE gets, --sections lists the
+TDVF sections first.";
+
+#[derive(Clone, Copy)]
+enum OutputFormat {
+    Raw,
+    Hex,
+    Base64,
+    Json,
+    Cbor,
+}
+
+fn parse_format(value: &str) -> Result<OutputFormat, anyhow::Error> {
+    match value {
+        "raw" => Ok(OutputFormat::Raw),
+        "hex" => Ok(OutputFormat::Hex),
+        "base64" => Ok(OutputFormat::Base64),
+        "json" => Ok(OutputFormat::Json),
+        "cbor" => Ok(OutputFormat::Cbor),
+        _ => Err(anyhow!("[ioctl] Unknown format {}", value)),
+    }
+}
+
+struct Output {
+    path: Option<PathBuf>,
+    format: OutputFormat,
+}
+
+impl Output {
+    // raw evidence as is or hex or base64 encoded, JSON and CBOR encode its decoded form
+    fn write<T: Serialize>(
+        &self,
+        raw: &[u8],
+        decode: impl FnOnce(&[u8]) -> Result<T, anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let encoded = match self.format {
+            OutputFormat::Raw => raw.to_vec(),
+            OutputFormat::Hex => format!("{}\n", hex::encode(raw)).into_bytes(),
+            OutputFormat::Base64 => format!("{}\n", base64::encode(raw)).into_bytes(),
+            OutputFormat::Json => format!("{}\n", to_json(&decode(raw)?)?).into_bytes(),
+            OutputFormat::Cbor => to_cbor(&decode(raw)?)?,
+        };
+        self.write_bytes(&encoded)
+    }
+
+    fn write_bytes(&self, encoded: &[u8]) -> Result<(), anyhow::Error> {
+        let written = match &self.path {
+            Some(path) => fs::write(path, encoded),
+            None => io::stdout().write_all(encoded),
+        };
+        match written {
+            Err(e) => Err(anyhow!("[ioctl] Fail to write output: {:?}", e)),
+            Ok(_) => Ok(()),
+        }
+    }
+}
+
+// command line arguments with the global options already taken off the front
+struct Args {
+    args: Vec<String>,
+    next: usize,
+}
+
+impl Args {
+    fn next(&mut self) -> Option<String> {
+        let arg = self.args.get(self.next).cloned();
+        self.next += 1;
+        arg
+    }
+
+    fn value(&mut self, option: &str) -> Result<String, anyhow::Error> {
+        match self.next() {
+            None => Err(anyhow!("[ioctl] {} needs a value\n{}", option, USAGE)),
+            Some(v) => Ok(v),
+        }
+    }
+}
+
+fn unknown(arg: &str) -> anyhow::Error {
+    anyhow!("[ioctl] Unexpected argument {}\n{}", arg, USAGE)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, anyhow::Error> {
+    match fs::read(path) {
+        Err(e) => Err(anyhow!("[ioctl] Fail to read {}: {:?}", path.display(), e)),
+        Ok(bytes) => Ok(bytes),
+    }
+}
+
+fn decode_hex(what: &str, value: &str) -> Result<Vec<u8>, anyhow::Error> {
+    match hex::decode(value.trim()) {
+        Err(e) => Err(anyhow!("[ioctl] Bad hex {}: {:?}", what, e)),
+        Ok(bytes) => Ok(bytes),
+    }
+}
+
+// parses the report data and output options shared by report and quote,
+// handing anything else to extra
+fn parse_report_args(
+    args: &mut Args,
+    mut extra: impl FnMut(&str, &mut Args) -> Result<bool, anyhow::Error>,
+) -> Result<(ReportData, Output), anyhow::Error> {
+    let mut data = None;
+    let mut output = Output {
+        path: None,
+        format: OutputFormat::Raw,
+    };
+    while let Some(arg) = args.next() {
+        let bytes = match arg.as_str() {
+            "--hex" => decode_hex("report data", &args.value(&arg)?)?,
+            "--base64" => match base64::decode(args.value(&arg)?.trim()) {
+                Err(e) => return Err(anyhow!("[ioctl] Bad base64 report data: {:?}", e)),
+                Ok(bytes) => bytes,
+            },
+            "--file" => read_file(Path::new(&args.value(&arg)?))?,
+            "--stdin" => {
+                let mut bytes = Vec::new();
+                if let Err(e) = io::stdin().read_to_end(&mut bytes) {
+                    return Err(anyhow!("[ioctl] Fail to read stdin: {:?}", e));
+                }
+                bytes
+            }
+            "--out" | "-o" => {
+                output.path = Some(PathBuf::from(args.value(&arg)?));
+                continue;
+            }
+            "--format" => {
+                output.format = parse_format(&args.value(&arg)?)?;
+                continue;
+            }
+            _ => {
+                if extra(&arg, args)? {
+                    continue;
+                }
+                return Err(unknown(&arg));
+            }
+        };
+        if data.is_some() {
+            return Err(anyhow!("[ioctl] Report data given twice"));
+        }
+        data = Some(bytes);
+    }
+    let report_data = match data {
+        None => ReportData::new([0; REPORT_DATA_LEN as usize]),
+        Some(bytes) => match ReportData::from_slice_zero_padded(&bytes) {
+            Err(e) => return Err(anyhow!("[ioctl] {}", e)),
+            Ok(r) => r,
+        },
+    };
+    Ok((report_data, output))
+}
+
+fn cmd_report(args: &mut Args) -> Result<(), anyhow::Error> {
+    let (report_data, output) = parse_report_args(args, |_, _| Ok(false))?;
+    match tdx_backend().get_report(&report_data) {
+        Err(e) => Err(anyhow!("[ioctl] Fail to get TDX report: {}", e)),
+        Ok(report) => output.write(&report, TdReport::from_bytes),
+    }
+}
+
+fn cmd_quote(args: &mut Args) -> Result<(), anyhow::Error> {
+    let mut options = QuoteOptions::default();
+    let (report_data, output) = parse_report_args(args, |arg, args| match arg {
+        "--timeout" => match args.value(arg)?.parse() {
+            Err(e) => Err(anyhow!("[ioctl] Bad timeout: {:?}", e)),
+            Ok(secs) => {
+                options.timeout = Duration::from_secs(secs);
+                Ok(true)
+            }
+        },
+        _ => Ok(false),
+    })?;
+    match tdx_backend().get_quote(&report_data, &options) {
+        Err(e) => Err(anyhow!("[ioctl] Fail to get TDX quote: {}", e)),
+        Ok(quote) => output.write(&quote, Quote::from_bytes),
+    }
+}
+
+fn print_field(name: &str, value: &[u8]) {
+    println!("  {:<18}{}", name, hex::encode(value));
+}
+
+fn print_report(report: &TdReport) {
+    let mac = &report.report_mac;
+    let tcb = &report.tee_tcb_info;
+    let td = &report.td_info;
+    println!("TDREPORT ({:?})", report.version());
+    println!(
+        "  {:<18}type {:#x} subtype {} version {}",
+        "report_type", mac.report_type.tee_type, mac.report_type.subtype, mac.report_type.version
+    );
+    print_field("cpusvn", &mac.cpusvn);
+    print_field("tee_tcb_info_hash", &mac.tee_tcb_info_hash);
+    print_field("tee_info_hash", &mac.tee_info_hash);
+    print_field("report_data", &mac.report_data);
+    print_field("mac", &mac.mac);
+    print_field("tee_tcb_svn", &tcb.tee_tcb_svn);
+    print_field("mrseam", &tcb.mrseam);
+    print_field("mrsignerseam", &tcb.mrsignerseam);
+    print_field("seam_attributes", &tcb.attributes);
+    if let Some(svn2) = report.tee_tcb_svn2() {
+        print_field("tee_tcb_svn2", svn2);
+    }
+    print_field("td_attributes", &td.attributes);
+    print_field("xfam", &td.xfam);
+    print_field("mrtd", &td.mrtd);
+    print_field("mrconfigid", &td.mrconfigid);
+    print_field("mrowner", &td.mrowner);
+    print_field("mrownerconfig", &td.mrownerconfig);
+    for (i, rtmr) in td.rtmrs.iter().enumerate() {
+        print_field(&format!("rtmr{}", i), rtmr);
+    }
+    if let Some(servtd_hash) = report.servtd_hash() {
+        print_field("servtd_hash", servtd_hash);
+    }
+}
+
+fn print_quote(quote: &Quote) {
+    let header = &quote.header;
+    let body = &quote.body;
+    println!(
+        "Quote v{} (body type {}, attestation key type {})",
+        header.version, quote.body_type, header.att_key_type
+    );
+    println!("  {:<18}{}", "qe_svn", header.qe_svn);
+    println!("  {:<18}{}", "pce_svn", header.pce_svn);
+    print_field("qe_vendor_id", &header.qe_vendor_id);
+    print_field("user_data", &header.user_data);
+    print_field("tee_tcb_svn", &body.tee_tcb_svn);
+    print_field("mrseam", &body.mrseam);
+    print_field("mrsignerseam", &body.mrsignerseam);
+    print_field("seam_attributes", &body.seam_attributes);
+    if let Some(svn2) = &body.tee_tcb_svn2 {
+        print_field("tee_tcb_svn2", svn2);
+    }
+    print_field("td_attributes", &body.td_attributes);
+    print_field("xfam", &body.xfam);
+    print_field("mrtd", &body.mrtd);
+    print_field("mrconfigid", &body.mrconfigid);
+    print_field("mrowner", &body.mrowner);
+    print_field("mrownerconfig", &body.mrownerconfig);
+    for (i, rtmr) in body.rtmrs.iter().enumerate() {
+        print_field(&format!("rtmr{}", i), rtmr);
+    }
+    if let Some(mrservicetd) = &body.mrservicetd {
+        print_field("mrservicetd", mrservicetd);
+    }
+    print_field("report_data", &body.report_data);
+    match quote.pck_cert_chain().map(load_pem_chain) {
+        None => println!("  no PCK certificate chain"),
+        Some(Err(e)) => println!("  bad PCK certificate chain: {}", e),
+        Some(Ok(chain)) => {
+            println!("  PCK certificate chain:");
+            for cert in &chain {
+                println!("    {}", subject_name(cert));
+            }
+        }
+    }
+}
+
+// a JSON object or a CBOR map as written by --format json|cbor, None for raw evidence
+// (a TDREPORT starts with its report type 0x81, a quote with its version, an event log with 0)
+fn decode_document<T: DeserializeOwned>(bytes: &[u8]) -> Option<Result<T, anyhow::Error>> {
+    match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'{') => Some(from_json(bytes)),
+        Some(b) if b & 0xe0 == 0xa0 => Some(from_cbor(bytes)),
+        _ => None,
+    }
+}
+
+// a TDREPORT is exactly TDX_REPORT_LEN bytes and starts with the TDX report type,
+// anything else is taken for a quote
+fn load_report_or_quote(path: &Path) -> Result<Result<TdReport, Quote>, anyhow::Error> {
+    let bytes = read_file(path)?;
+    if let Some(report) = decode_document::<TdReport>(&bytes) {
+        return match (report, decode_document::<Quote>(&bytes).unwrap()) {
+            (Ok(report), _) => Ok(Ok(report)),
+            (_, Ok(quote)) => Ok(Err(quote)),
+            (Err(report_err), Err(quote_err)) => Err(anyhow!(
+                "[ioctl] {} holds neither a TDREPORT ({}) nor a quote ({})",
+                path.display(),
+                report_err,
+                quote_err
+            )),
+        };
+    }
+    if bytes.len() == TDX_REPORT_LEN as usize {
+        if let Ok(report) = TdReport::from_bytes(&bytes) {
+            return Ok(Ok(report));
+        }
+    }
+    Ok(Err(Quote::from_bytes(&bytes)?))
+}
+
+fn cmd_inspect(args: &mut Args) -> Result<(), anyhow::Error> {
+    let path = match args.next() {
+        None => return Err(anyhow!("[ioctl] inspect needs a file\n{}", USAGE)),
+        Some(p) => PathBuf::from(p),
+    };
+    let mut output = Output {
+        path: None,
+        format: OutputFormat::Raw,
+    };
+    let mut convert = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" | "-o" => output.path = Some(PathBuf::from(args.value(&arg)?)),
+            "--format" => {
+                output.format = parse_format(&args.value(&arg)?)?;
+                convert = true;
+            }
+            _ => return Err(unknown(&arg)),
+        }
+    }
+    match (load_report_or_quote(&path)?, convert) {
+        (Ok(report), false) => print_report(&report),
+        (Err(quote), false) => print_quote(&quote),
+        (Ok(report), true) => output.write(report.as_bytes(), |_| Ok(report.clone()))?,
+        (Err(quote), true) => output.write(quote.as_bytes(), |_| Ok(quote.clone()))?,
+    }
+    Ok(())
+}
+
+fn print_check(name: &str, result: &CheckResult) {
+    match result {
+        Ok(_) => println!("  {:<20}ok", name),
+        Err(e) => println!("  {:<20}FAILED: {}", name, e),
+    }
+}
+
+fn cmd_verify(args: &mut Args) -> Result<(), anyhow::Error> {
+    let mut quote_path = None;
+    let mut root = None;
+    let mut collateral_dir = None;
+    let mut crls = Vec::new();
+    let mut policy = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => root = Some(PathBuf::from(args.value(&arg)?)),
+            "--collateral" => collateral_dir = Some(PathBuf::from(args.value(&arg)?)),
+            "--crl" => crls.push(Crl::load(Path::new(&args.value(&arg)?))?),
+            "--policy" => policy = Some(Policy::load(Path::new(&args.value(&arg)?))?),
+            _ if quote_path.is_none() && !arg.starts_with('-') => {
+                quote_path = Some(PathBuf::from(arg))
+            }
+            _ => return Err(unknown(&arg)),
+        }
+    }
+    let (quote_path, root) = match (quote_path, root) {
+        (Some(q), Some(r)) => (q, r),
+        _ => {
+            return Err(anyhow!(
+                "[ioctl] verify needs a quote and --root\n{}",
+                USAGE
+            ))
+        }
+    };
+    let quote = match load_report_or_quote(&quote_path)? {
+        Ok(_) => return Err(anyhow!("[ioctl] {} is a TDREPORT", quote_path.display())),
+        Err(quote) => quote,
+    };
+    let root = load_certificate(&read_file(&root)?)?;
+    let now = SystemTime::now();
+
+    let verdict = if crls.is_empty() {
+        verify_quote_at(&quote, &root, now)
+    } else {
+        verify_quote_with_crls(&quote, &root, &crls, now)
+    };
+    println!("Quote {}", quote_path.display());
+    print_check("quote format", &verdict.quote_format);
+    print_check("PCK chain", &verdict.pck_chain);
+    print_check("QE report signature", &verdict.qe_report_signature);
+    print_check("QE report binding", &verdict.qe_report_binding);
+    print_check("quote signature", &verdict.quote_signature);
+    if let Some(revocation) = &verdict.revocation {
+        print_check("revocation", revocation);
+    }
+    let mut valid = verdict.is_valid();
+
+    if let Some(dir) = collateral_dir {
+        let checked = Collateral::load_dir(&dir)?.verify(&root, now);
+        print_check(
+            "collateral",
+            &checked.as_ref().map(|_| ()).map_err(|e| e.clone()),
+        );
+        let evaluation = match (checked, verdict.pck_leaf()) {
+            (Ok(collateral), Some(pck_leaf)) => {
+                Some(evaluate_tcb(&quote, pck_leaf, &collateral, now))
+            }
+            _ => None,
+        };
+        match evaluation {
+            None => {
+                println!("  {:<20}not checked", "TCB status");
+                valid = false;
+            }
+            Some(Err(e)) => {
+                println!("  {:<20}FAILED: {}", "TCB status", e);
+                valid = false;
+            }
+            Some(Ok(tcb)) => {
+                println!("  {:<20}{:?}", "TCB status", tcb.status);
+                println!("    {:<18}{:?}", "platform", tcb.platform_status);
+                if let Some(status) = tcb.tdx_module_status {
+                    println!("    {:<18}{:?}", "TDX module", status);
+                }
+                println!("    {:<18}{:?}", "QE", tcb.qe_status);
+                println!("    {:<18}{}", "TCB date", tcb.tcb_date);
+                if !tcb.advisory_ids.is_empty() {
+                    println!("    {:<18}{}", "advisories", tcb.advisory_ids.join(", "));
+                }
+                if tcb.collateral_expired {
+                    println!("    collateral is past its nextUpdate");
+                }
+                valid &= tcb.status == TcbStatus::UpToDate && !tcb.collateral_expired;
+            }
+        }
+    }
+
+    if let Some(policy) = policy {
+        let report = policy.evaluate_quote(&quote);
+        println!("Policy");
+        for claim in &report.claims {
+            println!("  {}", claim);
+        }
+        valid &= report.is_pass();
+    }
+
+    if !valid {
+        return Err(anyhow!("[ioctl] Quote verification failed"));
+    }
+    println!("Quote is valid");
+    Ok(())
+}
+
+fn cmd_rtmr(args: &mut Args) -> Result<(), anyhow::Error> {
+    match args.next().as_deref() {
+        Some("extend") => cmd_rtmr_extend(args),
+        Some("predict") => cmd_rtmr_predict(args),
+        Some(arg) => Err(unknown(arg)),
+        None => Err(anyhow!("[ioctl] rtmr needs a subcommand\n{}", USAGE)),
+    }
+}
+
+fn cmd_rtmr_extend(args: &mut Args) -> Result<(), anyhow::Error> {
+    let index: u8 = match args.next().map(|i| i.parse()) {
+        Some(Ok(index)) => index,
+        _ => {
+            return Err(anyhow!(
+                "[ioctl] rtmr extend needs an RTMR index\n{}",
+                USAGE
+            ))
+        }
+    };
+    let extended = match (args.next().as_deref(), args.next()) {
+        (Some("--digest"), Some(value)) => {
+            let bytes = decode_hex("digest", &value)?;
+            let digest: [u8; TDX_EXTEND_RTMR_DATA_LEN] = match bytes.as_slice().try_into() {
+                Err(_) => {
+                    return Err(anyhow!(
+                        "[ioctl] Digest is {} bytes, expected {}",
+                        bytes.len(),
+                        TDX_EXTEND_RTMR_DATA_LEN
+                    ))
+                }
+                Ok(digest) => digest,
+            };
+            extend_rtmr(index, digest).map(|_| digest)
+        }
+        (Some("--event"), Some(path)) => {
+            extend_rtmr_with_event(index, &read_file(Path::new(&path))?)
+        }
+        _ => {
+            return Err(anyhow!(
+                "[ioctl] rtmr extend needs --digest or --event\n{}",
+                USAGE
+            ))
+        }
+    };
+    if let Some(arg) = args.next() {
+        return Err(unknown(&arg));
+    }
+    match extended {
+        Err(e) => Err(anyhow!("[ioctl] Fail to extend RTMR{}: {}", index, e)),
+        Ok(digest) => {
+            println!("extended RTMR{} with {}", index, hex::encode(digest));
+            Ok(())
+        }
+    }
+}
+
+fn cmd_rtmr_predict(args: &mut Args) -> Result<(), anyhow::Error> {
+    let mut kernel = None;
+    let mut initrd = None;
+    let mut cmdline = String::new();
+    let mut boot_loaders = Vec::new();
+    let mut grub_kernel = None;
+    let mut grub_initrd = None;
+    let mut compare = None;
+    let mut output = Output {
+        path: None,
+        format: OutputFormat::Raw,
+    };
+    let mut convert = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kernel" => kernel = Some(PathBuf::from(args.value(&arg)?)),
+            "--initrd" => initrd = Some(PathBuf::from(args.value(&arg)?)),
+            "--cmdline" => cmdline = args.value(&arg)?,
+            "--boot-loader" => boot_loaders.push(PathBuf::from(args.value(&arg)?)),
+            "--grub" => grub_kernel = Some(args.value(&arg)?),
+            "--grub-initrd" => grub_initrd = Some(args.value(&arg)?),
+            "--compare" => compare = Some(PathBuf::from(args.value(&arg)?)),
+            "--out" | "-o" => output.path = Some(PathBuf::from(args.value(&arg)?)),
+            "--format" => {
+                output.format = parse_format(&args.value(&arg)?)?;
+                convert = true;
+            }
+            _ => return Err(unknown(&arg)),
+        }
+    }
+    let kernel = match kernel {
+        None => return Err(anyhow!("[ioctl] rtmr predict needs --kernel\n{}", USAGE)),
+        Some(k) => k,
+    };
+
+    let mut boot = LinuxBoot::new(read_file(&kernel)?).cmdline(&cmdline);
+    if let Some(path) = &initrd {
+        boot = boot.initrd(read_file(path)?);
+    }
+    for path in &boot_loaders {
+        boot = boot.boot_loader(read_file(path)?);
+    }
+    match (grub_kernel, grub_initrd) {
+        (Some(kernel), initrd) => boot = boot.grub(GrubPaths { kernel, initrd }),
+        (None, Some(_)) => return Err(anyhow!("[ioctl] --grub-initrd needs --grub\n{}", USAGE)),
+        (None, None) => {}
+    }
+    let expected = boot.expected_rtmrs()?;
+
+    if convert {
+        return match output.format {
+            OutputFormat::Json => {
+                output.write_bytes(format!("{}\n", to_json(&expected)?).as_bytes())
+            }
+            OutputFormat::Cbor => output.write_bytes(&to_cbor(&expected)?),
+            _ => Err(anyhow!(
+                "[ioctl] Expected RTMRs are written as json or cbor"
+            )),
+        };
+    }
+
+    if let Some(path) = compare {
+        let reported = match load_report_or_quote(&path)? {
+            Ok(report) => report.td_info.rtmrs,
+            Err(quote) => quote.body.rtmrs,
+        };
+        let diffs = expected.diff(&reported);
+        for diff in &diffs {
+            println!("{}", diff);
+        }
+        if !diffs.iter().all(|d| d.matches()) {
+            return Err(anyhow!(
+                "[ioctl] Expected RTMRs do not match {}",
+                path.display()
+            ));
+        }
+        return Ok(());
+    }
+
+    print_entries(&expected.events);
+    println!("rtmr1 {}", hex::encode(expected.rtmr1));
+    println!("rtmr2 {}", hex::encode(expected.rtmr2));
+    Ok(())
+}
+
+fn print_entries(entries: &[EventLogEntry]) {
+    for (i, entry) in entries.iter().enumerate() {
+        let digest = entry
+            .digests
+            .first()
+            .map(|(_, d)| hex::encode(d))
+            .unwrap_or_default();
+        println!(
+            "{:4} MR{} {} ({:#x})",
+            i,
+            entry.mr_index,
+            event_type_name(entry.event_type),
+            entry.event_type
+        );
+        println!("     digest {}", digest);
+        println!("     {}", entry.decode());
+    }
+}
+
+fn cmd_eventlog(args: &mut Args) -> Result<(), anyhow::Error> {
+    let mut file = None;
+    let mut replay = false;
+    let mut compare = None;
+    let mut output = Output {
+        path: None,
+        format: OutputFormat::Raw,
+    };
+    let mut convert = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--file" => file = Some(PathBuf::from(args.value(&arg)?)),
+            "--replay" => replay = true,
+            "--compare" => compare = Some(PathBuf::from(args.value(&arg)?)),
+            "--out" | "-o" => output.path = Some(PathBuf::from(args.value(&arg)?)),
+            "--format" => {
+                output.format = parse_format(&args.value(&arg)?)?;
+                convert = true;
+            }
+            _ => return Err(unknown(&arg)),
+        }
+    }
+    let log = match &file {
+        Some(path) => {
+            let bytes = read_file(path)?;
+            match decode_document(&bytes) {
+                Some(log) => log?,
+                None => EventLog::from_bytes(&bytes)?,
+            }
+        }
+        None => EventLog::read_ccel()?,
+    };
+
+    if convert {
+        return match output.format {
+            OutputFormat::Json => output.write_bytes(format!("{}\n", to_json(&log)?).as_bytes()),
+            OutputFormat::Cbor => output.write_bytes(&to_cbor(&log)?),
+            _ => Err(anyhow!("[ioctl] Event logs are written as json or cbor")),
+        };
+    }
+
+    if !replay && compare.is_none() {
+        print_entries(&log.entries);
+        return Ok(());
+    }
+
+    if replay {
+        for (i, rtmr) in log.replay_rtmrs()?.iter().enumerate() {
+            println!("rtmr{} {}", i, hex::encode(rtmr));
+        }
+    }
+    if let Some(path) = compare {
+        let reported = match load_report_or_quote(&path)? {
+            Ok(report) => report.td_info.rtmrs,
+            Err(quote) => quote.body.rtmrs,
+        };
+        let diffs = diff_rtmrs(&log, &reported);
+        for diff in &diffs {
+            println!("{}", diff);
+        }
+        if !diffs.iter().all(|d| d.matches()) {
+            return Err(anyhow!(
+                "[ioctl] Event log does not match {}",
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn cmd_mrtd(args: &mut Args) -> Result<(), anyhow::Error> {
+    let mut firmware = None;
+    let mut sections = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sections" => sections = true,
+            _ if firmware.is_none() && !arg.starts_with('-') => firmware = Some(PathBuf::from(arg)),
+            _ => return Err(unknown(&arg)),
+        }
+    }
+    let firmware = match firmware {
+        None => return Err(anyhow!("[ioctl] mrtd needs a firmware image\n{}", USAGE)),
+        Some(f) => f,
+    };
+    let image = read_file(&firmware)?;
+    let metadata = TdvfMetadata::parse(&image)?;
+    if sections {
+        for section in &metadata.sections {
+            println!("{}", section);
+        }
+    }
+    println!("{}", hex::encode(metadata.compute_mrtd(&image)?));
+    Ok(())
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let mut args = Args {
+        args: env::args().skip(1).collect(),
+        next: 0,
+    };
+    let mut device = None;
+    let mut qgs = None;
+    let command = loop {
+        match args.next() {
+            None => return Err(anyhow!("[ioctl] Missing command\n{}", USAGE)),
+            Some(arg) => match arg.as_str() {
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    return Ok(());
+                }
+                "--device" => device = Some(PathBuf::from(args.value(&arg)?)),
+                "--qgs" => qgs = Some(args.value(&arg)?.parse::<QgsAddress>()?),
+                _ => break arg,
+            },
+        }
+    };
+
+    let local: Option<Arc<dyn TdxBackend>> = match device {
+        None => None,
+        Some(path) => {
+            match discover_tdx_device_at(&path, None).map(|d| TdxDeviceHandle::open_device(&d)) {
+                Err(e) => return Err(anyhow!("[ioctl] {}", e)),
+                Ok(Err(e)) => return Err(anyhow!("[ioctl] {}", e)),
+                Ok(Ok(handle)) => Some(Arc::new(handle)),
+            }
+        }
+    };
+    match (qgs, local) {
+        (Some(address), Some(local)) => set_tdx_backend(Arc::new(
+            QgsSocketBackend::new(address).local_backend(local),
+        )),
+        (Some(address), None) => set_tdx_backend(Arc::new(QgsSocketBackend::new(address))),
+        (None, Some(local)) => set_tdx_backend(local),
+        (None, None) => {}
+    }
+
+    match command.as_str() {
+        "report" => cmd_report(&mut args),
+        "quote" => cmd_quote(&mut args),
+        "inspect" => cmd_inspect(&mut args),
+        "verify" => cmd_verify(&mut args),
+        "rtmr" => cmd_rtmr(&mut args),
+        "eventlog" => cmd_eventlog(&mut args),
+        "mrtd" => cmd_mrtd(&mut args),
+        _ => Err(anyhow!("[ioctl] Unknown command {}\n{}", command, USAGE)),
+    }
+}
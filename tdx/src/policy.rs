@@ -0,0 +1,613 @@
+use crate::evidence::hex_bytes;
+use crate::quote::Quote;
+use crate::tdreport::TdReport;
+use anyhow::*;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::result::Result;
+use std::result::Result::Ok;
+
+// TD_ATTRIBUTES bits, see Intel TDX Module ABI specification, section "ATTRIBUTES"
+pub const TD_ATTRIBUTES_DEBUG: u64 = 1 << 0;
+pub const TD_ATTRIBUTES_SEPT_VE_DISABLE: u64 = 1 << 28;
+pub const TD_ATTRIBUTES_MIGRATABLE: u64 = 1 << 29;
+pub const TD_ATTRIBUTES_PKS: u64 = 1 << 30;
+pub const TD_ATTRIBUTES_KL: u64 = 1 << 31;
+pub const TD_ATTRIBUTES_PERFMON: u64 = 1 << 63;
+
+const TD_ATTRIBUTE_NAMES: [(&str, u64); 6] = [
+    ("DEBUG", TD_ATTRIBUTES_DEBUG),
+    ("SEPT_VE_DISABLE", TD_ATTRIBUTES_SEPT_VE_DISABLE),
+    ("MIGRATABLE", TD_ATTRIBUTES_MIGRATABLE),
+    ("PKS", TD_ATTRIBUTES_PKS),
+    ("KL", TD_ATTRIBUTES_KL),
+    ("PERFMON", TD_ATTRIBUTES_PERFMON),
+];
+
+/// Reference values a TD has to match. Claims left out of the policy are not checked, a claim
+/// given an empty list of allowed values never passes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default, deserialize_with = "allowed_values")]
+    pub mrtd: Option<Vec<[u8; 48]>>,
+    #[serde(default, deserialize_with = "allowed_values")]
+    pub rtmr0: Option<Vec<[u8; 48]>>,
+    #[serde(default, deserialize_with = "allowed_values")]
+    pub rtmr1: Option<Vec<[u8; 48]>>,
+    #[serde(default, deserialize_with = "allowed_values")]
+    pub rtmr2: Option<Vec<[u8; 48]>>,
+    #[serde(default, deserialize_with = "allowed_values")]
+    pub rtmr3: Option<Vec<[u8; 48]>>,
+    #[serde(default, deserialize_with = "allowed_values")]
+    pub mrconfigid: Option<Vec<[u8; 48]>>,
+    #[serde(default, deserialize_with = "allowed_values")]
+    pub mrowner: Option<Vec<[u8; 48]>>,
+    #[serde(default, deserialize_with = "allowed_values")]
+    pub mrownerconfig: Option<Vec<[u8; 48]>>,
+    #[serde(default, with = "hex_bytes::option")]
+    pub min_tee_tcb_svn: Option<[u8; 16]>, // checked per SVN component
+    #[serde(default, deserialize_with = "attribute_mask")]
+    pub forbidden_attributes: u64, // TD_ATTRIBUTES bits that must be clear, by name or mask
+    #[serde(default)]
+    pub xfam: Option<MaskedValue>,
+}
+
+/// Passes when the claim's bits under `mask` equal `value`. A value with bits outside the mask
+/// could never match and is rejected when the policy is loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "MaskedValueFields")]
+pub struct MaskedValue {
+    pub value: u64,
+    pub mask: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaskedValueFields {
+    #[serde(deserialize_with = "u64_value")]
+    value: u64,
+    #[serde(deserialize_with = "u64_value")]
+    mask: u64,
+}
+
+impl TryFrom<MaskedValueFields> for MaskedValue {
+    type Error = anyhow::Error;
+
+    fn try_from(fields: MaskedValueFields) -> Result<Self, Self::Error> {
+        if fields.value & !fields.mask != 0 {
+            return Err(anyhow!(
+                "[Policy] Value {:#x} has bits outside mask {:#x}",
+                fields.value,
+                fields.mask
+            ));
+        }
+        Ok(MaskedValue {
+            value: fields.value,
+            mask: fields.mask,
+        })
+    }
+}
+
+fn allowed_values<'de, D: Deserializer<'de>, const N: usize>(
+    d: D,
+) -> Result<Option<Vec<[u8; N]>>, D::Error> {
+    hex_bytes::list::deserialize(d).map(Some)
+}
+
+// a number, or a string holding a decimal or 0x-prefixed hex number; TOML integers are
+// signed, so masks with bit 63 set have to be strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Int(u64),
+    Str(String),
+}
+
+impl Number {
+    fn parse(&self) -> Option<u64> {
+        match self {
+            Number::Int(v) => Some(*v),
+            Number::Str(s) => match s.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => s.parse().ok(),
+            },
+        }
+    }
+}
+
+fn u64_value<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    let number = Number::deserialize(d)?;
+    number
+        .parse()
+        .ok_or_else(|| serde::de::Error::custom("expected a number or a 0x-prefixed hex string"))
+}
+
+// a list of attribute names such as "DEBUG" and masks, or a single mask
+fn attribute_mask<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Attributes {
+        List(Vec<Number>),
+        Mask(Number),
+    }
+
+    let list = match Attributes::deserialize(d)? {
+        Attributes::List(list) => list,
+        Attributes::Mask(mask) => vec![mask],
+    };
+    let mut mask = 0;
+    for item in &list {
+        let bits = match item {
+            Number::Str(name) => TD_ATTRIBUTE_NAMES
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, bit)| *bit)
+                .or_else(|| item.parse()),
+            Number::Int(_) => item.parse(),
+        };
+        match bits {
+            None => return Err(serde::de::Error::custom("unknown TD attribute")),
+            Some(bits) => mask |= bits,
+        }
+    }
+    Ok(mask)
+}
+
+/// The TD measurements and attributes a policy is evaluated against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TdClaims {
+    pub mrtd: [u8; 48],
+    pub rtmrs: [[u8; 48]; 4],
+    pub mrconfigid: [u8; 48],
+    pub mrowner: [u8; 48],
+    pub mrownerconfig: [u8; 48],
+    pub tee_tcb_svn: [u8; 16],
+    pub td_attributes: u64,
+    pub xfam: u64,
+}
+
+impl From<&Quote> for TdClaims {
+    fn from(quote: &Quote) -> Self {
+        let body = &quote.body;
+        TdClaims {
+            mrtd: body.mrtd,
+            rtmrs: body.rtmrs,
+            mrconfigid: body.mrconfigid,
+            mrowner: body.mrowner,
+            mrownerconfig: body.mrownerconfig,
+            tee_tcb_svn: body.tee_tcb_svn,
+            td_attributes: u64::from_le_bytes(body.td_attributes),
+            xfam: u64::from_le_bytes(body.xfam),
+        }
+    }
+}
+
+// for a TD appraising itself; a TDREPORT is only trustworthy on the platform that produced it
+impl From<&TdReport> for TdClaims {
+    fn from(report: &TdReport) -> Self {
+        let td = &report.td_info;
+        TdClaims {
+            mrtd: td.mrtd,
+            rtmrs: td.rtmrs,
+            mrconfigid: td.mrconfigid,
+            mrowner: td.mrowner,
+            mrownerconfig: td.mrownerconfig,
+            tee_tcb_svn: report.tee_tcb_info.tee_tcb_svn,
+            td_attributes: u64::from_le_bytes(td.attributes),
+            xfam: u64::from_le_bytes(td.xfam),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PolicyFailure {
+    NotAllowed {
+        value: String,
+        allowed: usize,
+    },
+    SvnTooLow {
+        component: usize,
+        svn: u8,
+        minimum: u8,
+    },
+    ForbiddenAttributes {
+        attributes: u64,
+        forbidden: u64,
+    },
+    MaskMismatch {
+        actual: u64,
+        expected: MaskedValue,
+    },
+}
+
+impl fmt::Display for PolicyFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyFailure::NotAllowed { value, allowed } => {
+                write!(f, "{} is not one of the {} allowed values", value, allowed)
+            }
+            PolicyFailure::SvnTooLow {
+                component,
+                svn,
+                minimum,
+            } => write!(
+                f,
+                "SVN component {} is {}, the minimum is {}",
+                component, svn, minimum
+            ),
+            PolicyFailure::ForbiddenAttributes {
+                attributes,
+                forbidden,
+            } => {
+                let set = attributes & forbidden;
+                let names: Vec<String> = (0..64)
+                    .map(|bit| 1u64 << bit)
+                    .filter(|b| set & b != 0)
+                    .map(
+                        |b| match TD_ATTRIBUTE_NAMES.iter().find(|(_, bit)| *bit == b) {
+                            Some((name, _)) => name.to_string(),
+                            None => format!("{:#x}", b),
+                        },
+                    )
+                    .collect();
+                write!(
+                    f,
+                    "{:#018x} has forbidden bits set: {}",
+                    attributes,
+                    names.join(", ")
+                )
+            }
+            PolicyFailure::MaskMismatch { actual, expected } => write!(
+                f,
+                "{:#018x} masked with {:#018x} is {:#018x}, expected {:#018x}",
+                actual,
+                expected.mask,
+                actual & expected.mask,
+                expected.value
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClaimResult {
+    pub claim: &'static str,
+    pub result: Result<(), PolicyFailure>,
+}
+
+impl fmt::Display for ClaimResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.result {
+            Ok(_) => write!(f, "{}: ok", self.claim),
+            Err(e) => write!(f, "{}: FAILED: {}", self.claim, e),
+        }
+    }
+}
+
+/// Outcome of every claim the policy sets, in policy field order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PolicyReport {
+    pub claims: Vec<ClaimResult>,
+}
+
+impl PolicyReport {
+    pub fn is_pass(&self) -> bool {
+        self.claims.iter().all(|c| c.result.is_ok())
+    }
+
+    pub fn failures(&self) -> Vec<&ClaimResult> {
+        self.claims.iter().filter(|c| c.result.is_err()).collect()
+    }
+}
+
+impl fmt::Display for PolicyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for claim in &self.claims {
+            writeln!(f, "{}", claim)?;
+        }
+        write!(f, "{}", if self.is_pass() { "PASS" } else { "FAIL" })
+    }
+}
+
+fn check_allowed(value: &[u8; 48], allowed: &[[u8; 48]]) -> Result<(), PolicyFailure> {
+    if allowed.contains(value) {
+        return Ok(());
+    }
+    Err(PolicyFailure::NotAllowed {
+        value: hex::encode(value),
+        allowed: allowed.len(),
+    })
+}
+
+fn check_min_svn(svn: &[u8; 16], minimum: &[u8; 16]) -> Result<(), PolicyFailure> {
+    match (0..16).find(|&i| svn[i] < minimum[i]) {
+        None => Ok(()),
+        Some(component) => Err(PolicyFailure::SvnTooLow {
+            component,
+            svn: svn[component],
+            minimum: minimum[component],
+        }),
+    }
+}
+
+impl Policy {
+    pub fn from_toml(toml: &str) -> Result<Self, anyhow::Error> {
+        match toml::from_str(toml) {
+            Err(e) => Err(anyhow!("[Policy::from_toml] Bad policy: {}", e)),
+            Ok(policy) => Ok(policy),
+        }
+    }
+
+    pub fn from_json(json: &[u8]) -> Result<Self, anyhow::Error> {
+        match serde_json::from_slice(json) {
+            Err(e) => Err(anyhow!("[Policy::from_json] Bad policy: {}", e)),
+            Ok(policy) => Ok(policy),
+        }
+    }
+
+    // .json files are JSON, anything else TOML
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let bytes = match fs::read(path) {
+            Err(e) => {
+                return Err(anyhow!(
+                    "[Policy::load] Fail to read {}: {:?}",
+                    path.display(),
+                    e
+                ))
+            }
+            Ok(b) => b,
+        };
+        if path.extension().is_some_and(|e| e == "json") {
+            return Policy::from_json(&bytes);
+        }
+        match String::from_utf8(bytes) {
+            Err(_) => Err(anyhow!("[Policy::load] {} is not UTF-8", path.display())),
+            Ok(toml) => Policy::from_toml(&toml),
+        }
+    }
+
+    pub fn evaluate(&self, claims: &TdClaims) -> PolicyReport {
+        let mut results = Vec::new();
+        let mut check = |claim, result| results.push(ClaimResult { claim, result });
+
+        let measurements = [
+            ("mrtd", &self.mrtd, &claims.mrtd),
+            ("rtmr0", &self.rtmr0, &claims.rtmrs[0]),
+            ("rtmr1", &self.rtmr1, &claims.rtmrs[1]),
+            ("rtmr2", &self.rtmr2, &claims.rtmrs[2]),
+            ("rtmr3", &self.rtmr3, &claims.rtmrs[3]),
+            ("mrconfigid", &self.mrconfigid, &claims.mrconfigid),
+            ("mrowner", &self.mrowner, &claims.mrowner),
+            ("mrownerconfig", &self.mrownerconfig, &claims.mrownerconfig),
+        ];
+        for (claim, allowed, value) in measurements {
+            if let Some(allowed) = allowed {
+                check(claim, check_allowed(value, allowed));
+            }
+        }
+        if let Some(minimum) = &self.min_tee_tcb_svn {
+            check("tee_tcb_svn", check_min_svn(&claims.tee_tcb_svn, minimum));
+        }
+        if self.forbidden_attributes != 0 {
+            let result = match claims.td_attributes & self.forbidden_attributes {
+                0 => Ok(()),
+                _ => Err(PolicyFailure::ForbiddenAttributes {
+                    attributes: claims.td_attributes,
+                    forbidden: self.forbidden_attributes,
+                }),
+            };
+            check("td_attributes", result);
+        }
+        if let Some(expected) = self.xfam {
+            let result = match claims.xfam & expected.mask == expected.value {
+                true => Ok(()),
+                false => Err(PolicyFailure::MaskMismatch {
+                    actual: claims.xfam,
+                    expected,
+                }),
+            };
+            check("xfam", result);
+        }
+
+        PolicyReport { claims: results }
+    }
+
+    pub fn evaluate_quote(&self, quote: &Quote) -> PolicyReport {
+        self.evaluate(&TdClaims::from(quote))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    const MRTD: [u8; 48] = [0x11; 48];
+    const RTMR1: [u8; 48] = [0x22; 48];
+
+    const TOML_POLICY: &str = r#"
+        mrtd = ["111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111"]
+        rtmr1 = [
+            "000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+            "222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222",
+        ]
+        min_tee_tcb_svn = "03010000000000000000000000000000"
+        forbidden_attributes = ["DEBUG", "perfmon", "0x20000000"]
+        xfam = { value = "0x2e7", mask = "0xffffffffffffffff" }
+    "#;
+
+    const JSON_POLICY: &str = r#"{
+        "mrtd": ["111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111"],
+        "rtmr1": [
+            "000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+            "222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222"
+        ],
+        "min_tee_tcb_svn": "03010000000000000000000000000000",
+        "forbidden_attributes": "0x8000000020000001",
+        "xfam": { "value": 743, "mask": "18446744073709551615" }
+    }"#;
+
+    fn claims() -> TdClaims {
+        let mut tee_tcb_svn = [0; 16];
+        tee_tcb_svn[..2].copy_from_slice(&[3, 1]);
+        TdClaims {
+            mrtd: MRTD,
+            rtmrs: [[0; 48], RTMR1, [0; 48], [0; 48]],
+            mrconfigid: [0; 48],
+            mrowner: [0; 48],
+            mrownerconfig: [0; 48],
+            tee_tcb_svn,
+            td_attributes: TD_ATTRIBUTES_SEPT_VE_DISABLE,
+            xfam: 0x2e7,
+        }
+    }
+
+    #[test]
+    fn toml_and_json() {
+        let policy = Policy::from_toml(TOML_POLICY).unwrap();
+        assert_eq!(policy.mrtd, Some(vec![MRTD]));
+        assert_eq!(policy.rtmr1, Some(vec![[0; 48], RTMR1]));
+        assert_eq!(policy.rtmr0, None);
+        assert_eq!(
+            policy.forbidden_attributes,
+            TD_ATTRIBUTES_DEBUG | TD_ATTRIBUTES_PERFMON | TD_ATTRIBUTES_MIGRATABLE
+        );
+        assert_eq!(
+            policy.xfam,
+            Some(MaskedValue {
+                value: 0x2e7,
+                mask: u64::MAX
+            })
+        );
+
+        //attribute names, masks and numbers describe the same policy
+        assert_eq!(Policy::from_json(JSON_POLICY.as_bytes()).unwrap(), policy);
+        assert!(policy.evaluate(&claims()).is_pass());
+    }
+
+    #[test]
+    fn load_by_extension() {
+        let dir = env::temp_dir().join(format!("policy-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("policy.toml"), TOML_POLICY).unwrap();
+        fs::write(dir.join("policy.json"), JSON_POLICY).unwrap();
+        assert!(Policy::load(&dir.join("policy.toml")).is_ok());
+        assert!(Policy::load(&dir.join("policy.json")).is_ok());
+        //JSON is not TOML
+        fs::write(dir.join("policy.txt"), JSON_POLICY).unwrap();
+        assert!(Policy::load(&dir.join("policy.txt")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bit_63_masks() {
+        //TOML integers are signed, bit 63 only fits in a string
+        assert!(Policy::from_toml("forbidden_attributes = 9223372036854775808").is_err());
+        let policy = Policy::from_toml(r#"forbidden_attributes = "0x8000000000000000""#).unwrap();
+        assert_eq!(policy.forbidden_attributes, TD_ATTRIBUTES_PERFMON);
+        let policy =
+            Policy::from_toml(r#"xfam = { value = 0, mask = "0x8000000000000000" }"#).unwrap();
+        assert_eq!(policy.xfam.unwrap().mask, 1 << 63);
+    }
+
+    #[test]
+    fn rejects_bad_policies() {
+        for toml in [
+            "mrtdd = []",
+            r#"forbidden_attributes = ["DEBUGGING"]"#,
+            "xfam = { value = 1, mask = 1, shift = 0 }",
+            "xfam = { value = 1 }",
+            r#"mrtd = ["1111"]"#,
+            r#"min_tee_tcb_svn = "0301""#,
+            //can never match
+            "xfam = { value = 3, mask = 1 }",
+        ] {
+            assert!(Policy::from_toml(toml).is_err(), "{}", toml);
+        }
+        let e = Policy::from_json(br#"{"xfam": {"value": "0x100", "mask": "0xff"}}"#).unwrap_err();
+        assert!(e.to_string().contains("outside mask"), "{}", e);
+    }
+
+    #[test]
+    fn empty_allow_list_never_passes() {
+        let policy = Policy::from_toml("mrtd = []").unwrap();
+        let report = policy.evaluate(&claims());
+        assert!(!report.is_pass());
+        assert_eq!(
+            report.claims[0].result,
+            Err(PolicyFailure::NotAllowed {
+                value: hex::encode(MRTD),
+                allowed: 0
+            })
+        );
+        //an empty policy checks nothing
+        let report = Policy::default().evaluate(&claims());
+        assert!(report.is_pass() && report.claims.is_empty());
+    }
+
+    #[test]
+    fn min_svn_per_component() {
+        let policy =
+            Policy::from_toml(r#"min_tee_tcb_svn = "02020000000000000000000000000000""#).unwrap();
+        //3.1 is newer than 2.2 read as a number, but component 1 is still below its minimum
+        let report = policy.evaluate(&claims());
+        assert_eq!(
+            report.claims[0].result,
+            Err(PolicyFailure::SvnTooLow {
+                component: 1,
+                svn: 1,
+                minimum: 2
+            })
+        );
+        let mut claims = claims();
+        claims.tee_tcb_svn[1] = 2;
+        assert!(policy.evaluate(&claims).is_pass());
+    }
+
+    #[test]
+    fn xfam_mask() {
+        //AVX512 state (bits 5..7) must be off, the rest is not checked
+        let policy = Policy::from_toml(r#"xfam = { value = 0, mask = "0xe0" }"#).unwrap();
+        let mut claims = claims();
+        claims.xfam = 0x1f;
+        assert!(policy.evaluate(&claims).is_pass());
+        claims.xfam = 0x2e7;
+        assert_eq!(
+            policy.evaluate(&claims).claims[0].result,
+            Err(PolicyFailure::MaskMismatch {
+                actual: 0x2e7,
+                expected: MaskedValue {
+                    value: 0,
+                    mask: 0xe0
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn report_names_each_claim() {
+        let policy = Policy::from_toml(TOML_POLICY).unwrap();
+        let mut claims = claims();
+        claims.rtmrs[1] = [0x33; 48];
+        claims.tee_tcb_svn[0] = 2;
+        claims.td_attributes |= TD_ATTRIBUTES_DEBUG | TD_ATTRIBUTES_PERFMON;
+        let report = policy.evaluate(&claims);
+        assert_eq!(report.failures().len(), 3);
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "mrtd: ok\n\
+                 rtmr1: FAILED: {} is not one of the 2 allowed values\n\
+                 tee_tcb_svn: FAILED: SVN component 0 is 2, the minimum is 3\n\
+                 td_attributes: FAILED: 0x8000000010000001 has forbidden bits set: DEBUG, PERFMON\n\
+                 xfam: ok\n\
+                 FAIL",
+                hex::encode([0x33; 48])
+            )
+        );
+    }
+}
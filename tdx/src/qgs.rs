@@ -0,0 +1,754 @@
+use crate::collateral::*;
+use crate::crl::Crl;
+use crate::tee_tdx_lib::{TdxError, TDX_REPORT_LEN};
+use crate::verify::load_pem_chain;
+use std::convert::TryInto;
+use std::fmt;
+use std::result::Result;
+use std::result::Result::Ok;
+
+// error_code values of a QGS response, see qgs_msg_lib.h and sgx_ql_lib_common.h
+pub const QGS_MSG_SUCCESS: u32 = 0x0000;
+pub const QGS_MSG_ERROR_UNEXPECTED: u32 = 0x00012001;
+pub const QGS_MSG_ERROR_OUT_OF_MEMORY: u32 = 0x00012002;
+pub const QGS_MSG_ERROR_INVALID_PARAMETER: u32 = 0x00012003;
+pub const QGS_MSG_ERROR_INVALID_VERSION: u32 = 0x00012004;
+pub const QGS_MSG_ERROR_INVALID_TYPE: u32 = 0x00012005;
+pub const QGS_MSG_ERROR_INVALID_SIZE: u32 = 0x00012006;
+pub const QGS_MSG_ERROR_INVALID_CODE: u32 = 0x00012007;
+
+pub const SGX_QL_ERROR_UNEXPECTED: u32 = 0xe001;
+pub const SGX_QL_ERROR_INVALID_PARAMETER: u32 = 0xe002;
+pub const SGX_QL_ERROR_OUT_OF_MEMORY: u32 = 0xe003;
+pub const SGX_QL_ERROR_ECDSA_ID_MISMATCH: u32 = 0xe004;
+pub const SGX_QL_ATT_KEY_BLOB_ERROR: u32 = 0xe00a;
+pub const SGX_QL_UNSUPPORTED_ATT_KEY_ID: u32 = 0xe00b;
+pub const SGX_QL_INTERFACE_UNAVAILABLE: u32 = 0xe00d;
+pub const SGX_QL_PLATFORM_LIB_UNAVAILABLE: u32 = 0xe00e;
+pub const SGX_QL_ATT_KEY_NOT_INITIALIZED: u32 = 0xe00f;
+pub const SGX_QL_ATT_KEY_CERT_DATA_INVALID: u32 = 0xe010;
+pub const SGX_QL_NO_PLATFORM_CERT_DATA: u32 = 0xe011;
+pub const SGX_QL_OUT_OF_EPC: u32 = 0xe012;
+pub const SGX_QL_ERROR_REPORT: u32 = 0xe013;
+pub const SGX_QL_ENCLAVE_LOST: u32 = 0xe014;
+pub const SGX_QL_INVALID_REPORT: u32 = 0xe015;
+pub const SGX_QL_ENCLAVE_LOAD_ERROR: u32 = 0xe016;
+pub const SGX_QL_UNABLE_TO_GENERATE_QE_REPORT: u32 = 0xe017;
+pub const SGX_QL_KEY_CERTIFCATION_ERROR: u32 = 0xe018;
+pub const SGX_QL_NETWORK_ERROR: u32 = 0xe019;
+pub const SGX_QL_MESSAGE_ERROR: u32 = 0xe01a;
+pub const SGX_QL_SERVICE_UNAVAILABLE: u32 = 0xe040;
+pub const SGX_QL_NETWORK_FAILURE: u32 = 0xe041;
+pub const SGX_QL_SERVICE_TIMEOUT: u32 = 0xe042;
+pub const SGX_QL_ERROR_BUSY: u32 = 0xe043;
+
+/// Decoded error_code of a QGS response, grouped by what the caller can do about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QgsErrorCode {
+    Unexpected(u32),
+    OutOfMemory(u32),
+    InvalidMessage(u32), // QGS rejected the request message itself
+    QeFailure(u32),      // the quoting or provisioning certification enclave failed
+    PlatformLibUnavailable(u32),
+    AttestationKeyNotCertified(u32),
+    Network(u32),
+    Busy(u32),
+    Unknown(u32),
+}
+
+impl QgsErrorCode {
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            QGS_MSG_ERROR_UNEXPECTED | SGX_QL_ERROR_UNEXPECTED => QgsErrorCode::Unexpected(code),
+            QGS_MSG_ERROR_OUT_OF_MEMORY | SGX_QL_ERROR_OUT_OF_MEMORY | SGX_QL_OUT_OF_EPC => {
+                QgsErrorCode::OutOfMemory(code)
+            }
+            QGS_MSG_ERROR_INVALID_PARAMETER
+            | QGS_MSG_ERROR_INVALID_VERSION
+            | QGS_MSG_ERROR_INVALID_TYPE
+            | QGS_MSG_ERROR_INVALID_SIZE
+            | QGS_MSG_ERROR_INVALID_CODE
+            | SGX_QL_ERROR_INVALID_PARAMETER
+            | SGX_QL_INVALID_REPORT
+            | SGX_QL_UNSUPPORTED_ATT_KEY_ID
+            | SGX_QL_MESSAGE_ERROR => QgsErrorCode::InvalidMessage(code),
+            SGX_QL_ERROR_ECDSA_ID_MISMATCH
+            | SGX_QL_ATT_KEY_BLOB_ERROR
+            | SGX_QL_ERROR_REPORT
+            | SGX_QL_ENCLAVE_LOST
+            | SGX_QL_ENCLAVE_LOAD_ERROR
+            | SGX_QL_UNABLE_TO_GENERATE_QE_REPORT => QgsErrorCode::QeFailure(code),
+            SGX_QL_INTERFACE_UNAVAILABLE | SGX_QL_PLATFORM_LIB_UNAVAILABLE => {
+                QgsErrorCode::PlatformLibUnavailable(code)
+            }
+            SGX_QL_ATT_KEY_NOT_INITIALIZED
+            | SGX_QL_ATT_KEY_CERT_DATA_INVALID
+            | SGX_QL_NO_PLATFORM_CERT_DATA
+            | SGX_QL_KEY_CERTIFCATION_ERROR => QgsErrorCode::AttestationKeyNotCertified(code),
+            SGX_QL_NETWORK_ERROR | SGX_QL_NETWORK_FAILURE | SGX_QL_SERVICE_TIMEOUT => {
+                QgsErrorCode::Network(code)
+            }
+            SGX_QL_ERROR_BUSY | SGX_QL_SERVICE_UNAVAILABLE => QgsErrorCode::Busy(code),
+            _ => QgsErrorCode::Unknown(code),
+        }
+    }
+
+    pub fn code(&self) -> u32 {
+        match *self {
+            QgsErrorCode::Unexpected(c)
+            | QgsErrorCode::OutOfMemory(c)
+            | QgsErrorCode::InvalidMessage(c)
+            | QgsErrorCode::QeFailure(c)
+            | QgsErrorCode::PlatformLibUnavailable(c)
+            | QgsErrorCode::AttestationKeyNotCertified(c)
+            | QgsErrorCode::Network(c)
+            | QgsErrorCode::Busy(c)
+            | QgsErrorCode::Unknown(c) => c,
+        }
+    }
+
+    // transient host side conditions; a bad request or an unprovisioned platform will not recover by retrying
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            QgsErrorCode::OutOfMemory(_)
+                | QgsErrorCode::QeFailure(_)
+                | QgsErrorCode::Network(_)
+                | QgsErrorCode::Busy(_)
+        )
+    }
+}
+
+impl fmt::Display for QgsErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self {
+            QgsErrorCode::Unexpected(_) => "unexpected error",
+            QgsErrorCode::OutOfMemory(_) => "out of memory",
+            QgsErrorCode::InvalidMessage(_) => "invalid request",
+            QgsErrorCode::QeFailure(_) => "QE/PCE failure",
+            QgsErrorCode::PlatformLibUnavailable(_) => "platform library unavailable",
+            QgsErrorCode::AttestationKeyNotCertified(_) => "attestation key not certified",
+            QgsErrorCode::Network(_) => "network error",
+            QgsErrorCode::Busy(_) => "busy",
+            QgsErrorCode::Unknown(_) => "unknown error",
+        };
+        write!(f, "QGS {} ({:#x})", what, self.code())
+    }
+}
+
+// qgs_msg_header_t.type values
+pub const QGS_MSG_GET_QUOTE_REQ: u32 = 0;
+pub const QGS_MSG_GET_QUOTE_RESP: u32 = 1;
+pub const QGS_MSG_GET_COLLATERAL_REQ: u32 = 2;
+pub const QGS_MSG_GET_COLLATERAL_RESP: u32 = 3;
+pub const QGS_MSG_GET_PLATFORM_INFO_REQ: u32 = 4;
+pub const QGS_MSG_GET_PLATFORM_INFO_RESP: u32 = 5;
+
+pub const QGS_MSG_LIB_MAJOR_VER: u16 = 1;
+pub const QGS_MSG_LIB_MINOR_VER: u16 = 0;
+pub const QGS_MSG_HEADER_LEN: usize = 16;
+// sgx_att_key_id_ext_t, the unit of a GET_QUOTE id list
+pub const QGS_ATT_KEY_ID_LEN: usize = 256;
+// version of the collateral structure requested with GET_COLLATERAL
+pub const QGS_COLLATERAL_MAJOR_VER: u16 = 3;
+pub const QGS_COLLATERAL_MINOR_VER: u16 = 0;
+
+/// The sgx_ql_qve_collateral_t fields carried by GET_COLLATERAL_RESP, in wire order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QgsCollateral {
+    pub pck_crl_issuer_chain: Vec<u8>,
+    pub root_ca_crl: Vec<u8>,
+    pub pck_crl: Vec<u8>,
+    pub tcb_info_issuer_chain: Vec<u8>,
+    pub tcb_info: Vec<u8>,
+    pub qe_identity_issuer_chain: Vec<u8>,
+    pub qe_identity: Vec<u8>,
+}
+
+// QGS passes the PEM and JSON items as C strings
+fn strip_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    &bytes[0..end]
+}
+
+impl QgsCollateral {
+    fn fields(&self) -> [&Vec<u8>; 7] {
+        [
+            &self.pck_crl_issuer_chain,
+            &self.root_ca_crl,
+            &self.pck_crl,
+            &self.tcb_info_issuer_chain,
+            &self.tcb_info,
+            &self.qe_identity_issuer_chain,
+            &self.qe_identity,
+        ]
+    }
+
+    // TCB Info, QE Identity and their signing chain, as verify_quote consumes them
+    pub fn to_collateral(&self) -> Result<Collateral, anyhow::Error> {
+        Ok(Collateral {
+            tcb_info: TcbInfo::from_json(strip_nul(&self.tcb_info))?,
+            qe_identity: QeIdentity::from_json(strip_nul(&self.qe_identity))?,
+            tcb_signing_chain: load_pem_chain(strip_nul(&self.tcb_info_issuer_chain))?,
+        })
+    }
+
+    // the Root CA CRL and the PCK CRL
+    pub fn crls(&self) -> Result<Vec<Crl>, anyhow::Error> {
+        Ok(vec![
+            Crl::from_bytes(strip_nul(&self.root_ca_crl))?,
+            Crl::from_bytes(strip_nul(&self.pck_crl))?,
+        ])
+    }
+}
+
+/// A QGS v1 message, see qgs_msg_lib.h. Responses keep the header error_code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QgsMessage {
+    GetQuoteRequest {
+        report: Vec<u8>,
+        id_list: Vec<Vec<u8>>, // attestation key ids to choose from, empty for the QGS default
+    },
+    GetQuoteResponse {
+        error_code: u32,
+        selected_id: Vec<u8>, // empty when the request carried at most one id
+        quote: Vec<u8>,
+    },
+    GetCollateralRequest {
+        major_version: u16,
+        minor_version: u16,
+        pck_ca: Vec<u8>, // "processor" or "platform"
+        fmspc: Vec<u8>,
+    },
+    GetCollateralResponse {
+        error_code: u32,
+        major_version: u16,
+        minor_version: u16,
+        collateral: QgsCollateral,
+    },
+    GetPlatformInfoRequest,
+    GetPlatformInfoResponse {
+        error_code: u32,
+        tdqe_isvsvn: u16,
+        pce_isvsvn: u16,
+        platform_id: Vec<u8>,
+        cpusvn: Vec<u8>,
+    },
+}
+
+// little-endian cursor over a message body; reading past the end is a size error
+struct QgsReader<'a> {
+    msg: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> QgsReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], TdxError> {
+        if len > self.msg.len() - self.offset {
+            return Err(TdxError::SizeMismatch {
+                what: "QGS message",
+                expected: self.offset + len,
+                actual: self.msg.len(),
+            });
+        }
+        self.offset += len;
+        Ok(&self.msg[self.offset - len..self.offset])
+    }
+
+    fn u16(&mut self) -> Result<u16, TdxError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, TdxError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+impl QgsMessage {
+    pub fn msg_type(&self) -> u32 {
+        match self {
+            QgsMessage::GetQuoteRequest { .. } => QGS_MSG_GET_QUOTE_REQ,
+            QgsMessage::GetQuoteResponse { .. } => QGS_MSG_GET_QUOTE_RESP,
+            QgsMessage::GetCollateralRequest { .. } => QGS_MSG_GET_COLLATERAL_REQ,
+            QgsMessage::GetCollateralResponse { .. } => QGS_MSG_GET_COLLATERAL_RESP,
+            QgsMessage::GetPlatformInfoRequest => QGS_MSG_GET_PLATFORM_INFO_REQ,
+            QgsMessage::GetPlatformInfoResponse { .. } => QGS_MSG_GET_PLATFORM_INFO_RESP,
+        }
+    }
+
+    pub fn error_code(&self) -> u32 {
+        match *self {
+            QgsMessage::GetQuoteResponse { error_code, .. }
+            | QgsMessage::GetCollateralResponse { error_code, .. }
+            | QgsMessage::GetPlatformInfoResponse { error_code, .. } => error_code,
+            _ => QGS_MSG_SUCCESS,
+        }
+    }
+
+    // the response type QGS answers this request with
+    pub fn response_type(&self) -> Option<u32> {
+        match self.msg_type() {
+            t if t.is_multiple_of(2) => Some(t + 1),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            QgsMessage::GetQuoteRequest { report, id_list } => {
+                let id_list_size: usize = id_list.iter().map(|id| id.len()).sum();
+                body.extend_from_slice(&(report.len() as u32).to_le_bytes());
+                body.extend_from_slice(&(id_list_size as u32).to_le_bytes());
+                body.extend_from_slice(report);
+                for id in id_list {
+                    body.extend_from_slice(id);
+                }
+            }
+            QgsMessage::GetQuoteResponse {
+                selected_id, quote, ..
+            } => {
+                body.extend_from_slice(&(selected_id.len() as u32).to_le_bytes());
+                body.extend_from_slice(&(quote.len() as u32).to_le_bytes());
+                body.extend_from_slice(selected_id);
+                body.extend_from_slice(quote);
+            }
+            QgsMessage::GetCollateralRequest {
+                major_version,
+                minor_version,
+                pck_ca,
+                fmspc,
+            } => {
+                //qgs_msg_get_collateral_req_t: u16 versions, then u16 pck_ca and fmspc sizes
+                body.extend_from_slice(&major_version.to_le_bytes());
+                body.extend_from_slice(&minor_version.to_le_bytes());
+                body.extend_from_slice(&(pck_ca.len() as u16).to_le_bytes());
+                body.extend_from_slice(&(fmspc.len() as u16).to_le_bytes());
+                body.extend_from_slice(pck_ca);
+                body.extend_from_slice(fmspc);
+            }
+            QgsMessage::GetCollateralResponse {
+                major_version,
+                minor_version,
+                collateral,
+                ..
+            } => {
+                body.extend_from_slice(&major_version.to_le_bytes());
+                body.extend_from_slice(&minor_version.to_le_bytes());
+                for field in collateral.fields() {
+                    body.extend_from_slice(&(field.len() as u32).to_le_bytes());
+                }
+                for field in collateral.fields() {
+                    body.extend_from_slice(field);
+                }
+            }
+            QgsMessage::GetPlatformInfoRequest => (),
+            QgsMessage::GetPlatformInfoResponse {
+                tdqe_isvsvn,
+                pce_isvsvn,
+                platform_id,
+                cpusvn,
+                ..
+            } => {
+                body.extend_from_slice(&tdqe_isvsvn.to_le_bytes());
+                body.extend_from_slice(&pce_isvsvn.to_le_bytes());
+                body.extend_from_slice(&(platform_id.len() as u32).to_le_bytes());
+                body.extend_from_slice(&(cpusvn.len() as u32).to_le_bytes());
+                body.extend_from_slice(platform_id);
+                body.extend_from_slice(cpusvn);
+            }
+        }
+
+        let mut msg = Vec::with_capacity(QGS_MSG_HEADER_LEN + body.len());
+        msg.extend_from_slice(&QGS_MSG_LIB_MAJOR_VER.to_le_bytes());
+        msg.extend_from_slice(&QGS_MSG_LIB_MINOR_VER.to_le_bytes());
+        msg.extend_from_slice(&self.msg_type().to_le_bytes());
+        msg.extend_from_slice(&((QGS_MSG_HEADER_LEN + body.len()) as u32).to_le_bytes());
+        msg.extend_from_slice(&self.error_code().to_le_bytes());
+        msg.extend_from_slice(&body);
+        msg
+    }
+
+    // bytes past header.size are ignored, e.g. the rest of a GetQuote shared buffer
+    pub fn decode(msg: &[u8]) -> Result<Self, TdxError> {
+        let mut r = QgsReader { msg, offset: 0 };
+        let major_version = r.u16()?;
+        let minor_version = r.u16()?;
+        let msg_type = r.u32()?;
+        let size = r.u32()? as usize;
+        let error_code = r.u32()?;
+        if major_version != QGS_MSG_LIB_MAJOR_VER || msg_type > QGS_MSG_GET_PLATFORM_INFO_RESP {
+            return Err(TdxError::QgsResponse {
+                major_version,
+                minor_version,
+                msg_type,
+            });
+        }
+        if size < QGS_MSG_HEADER_LEN || size > msg.len() {
+            return Err(TdxError::SizeMismatch {
+                what: "QGS message",
+                expected: size,
+                actual: msg.len(),
+            });
+        }
+        r.msg = &msg[0..size];
+
+        let message = match msg_type {
+            QGS_MSG_GET_QUOTE_REQ => {
+                let report_size = r.u32()? as usize;
+                let id_list_size = r.u32()? as usize;
+                if report_size == 0 {
+                    return Err(TdxError::SizeMismatch {
+                        what: "QGS report",
+                        expected: TDX_REPORT_LEN as usize,
+                        actual: 0,
+                    });
+                }
+                if !id_list_size.is_multiple_of(QGS_ATT_KEY_ID_LEN) {
+                    return Err(TdxError::SizeMismatch {
+                        what: "QGS id list",
+                        expected: id_list_size - id_list_size % QGS_ATT_KEY_ID_LEN,
+                        actual: id_list_size,
+                    });
+                }
+                let report = r.bytes(report_size)?.to_vec();
+                let id_list = r
+                    .bytes(id_list_size)?
+                    .chunks(QGS_ATT_KEY_ID_LEN)
+                    .map(|id| id.to_vec())
+                    .collect();
+                QgsMessage::GetQuoteRequest { report, id_list }
+            }
+            QGS_MSG_GET_QUOTE_RESP => {
+                let selected_id_size = r.u32()? as usize;
+                let quote_size = r.u32()? as usize;
+                QgsMessage::GetQuoteResponse {
+                    error_code,
+                    selected_id: r.bytes(selected_id_size)?.to_vec(),
+                    quote: r.bytes(quote_size)?.to_vec(),
+                }
+            }
+            QGS_MSG_GET_COLLATERAL_REQ => {
+                let major_version = r.u16()?;
+                let minor_version = r.u16()?;
+                let pck_ca_size = r.u16()? as usize;
+                let fmspc_size = r.u16()? as usize;
+                QgsMessage::GetCollateralRequest {
+                    major_version,
+                    minor_version,
+                    pck_ca: r.bytes(pck_ca_size)?.to_vec(),
+                    fmspc: r.bytes(fmspc_size)?.to_vec(),
+                }
+            }
+            QGS_MSG_GET_COLLATERAL_RESP => {
+                let major_version = r.u16()?;
+                let minor_version = r.u16()?;
+                let mut sizes = [0; 7];
+                for s in sizes.iter_mut() {
+                    *s = r.u32()? as usize;
+                }
+                QgsMessage::GetCollateralResponse {
+                    error_code,
+                    major_version,
+                    minor_version,
+                    collateral: QgsCollateral {
+                        pck_crl_issuer_chain: r.bytes(sizes[0])?.to_vec(),
+                        root_ca_crl: r.bytes(sizes[1])?.to_vec(),
+                        pck_crl: r.bytes(sizes[2])?.to_vec(),
+                        tcb_info_issuer_chain: r.bytes(sizes[3])?.to_vec(),
+                        tcb_info: r.bytes(sizes[4])?.to_vec(),
+                        qe_identity_issuer_chain: r.bytes(sizes[5])?.to_vec(),
+                        qe_identity: r.bytes(sizes[6])?.to_vec(),
+                    },
+                }
+            }
+            QGS_MSG_GET_PLATFORM_INFO_REQ => QgsMessage::GetPlatformInfoRequest,
+            _ => {
+                let tdqe_isvsvn = r.u16()?;
+                let pce_isvsvn = r.u16()?;
+                let platform_id_size = r.u32()? as usize;
+                let cpusvn_size = r.u32()? as usize;
+                QgsMessage::GetPlatformInfoResponse {
+                    error_code,
+                    tdqe_isvsvn,
+                    pce_isvsvn,
+                    platform_id: r.bytes(platform_id_size)?.to_vec(),
+                    cpusvn: r.bytes(cpusvn_size)?.to_vec(),
+                }
+            }
+        };
+        Ok(message)
+    }
+
+    // decode a response to request, turning a non-zero error_code into TdxError::QgsError
+    pub fn decode_response(request: &QgsMessage, msg: &[u8]) -> Result<Self, TdxError> {
+        let response = QgsMessage::decode(msg)?;
+        if Some(response.msg_type()) != request.response_type() {
+            //decode has checked the header, report its versions as received
+            return Err(TdxError::QgsResponse {
+                major_version: u16::from_le_bytes([msg[0], msg[1]]),
+                minor_version: u16::from_le_bytes([msg[2], msg[3]]),
+                msg_type: response.msg_type(),
+            });
+        }
+        match response.error_code() {
+            QGS_MSG_SUCCESS => Ok(response),
+            code => Err(TdxError::QgsError(QgsErrorCode::from_code(code))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote_request(id_count: usize) -> QgsMessage {
+        QgsMessage::GetQuoteRequest {
+            report: vec![7; TDX_REPORT_LEN as usize],
+            id_list: (0..id_count)
+                .map(|i| vec![i as u8; QGS_ATT_KEY_ID_LEN])
+                .collect(),
+        }
+    }
+
+    fn collateral_request() -> QgsMessage {
+        QgsMessage::GetCollateralRequest {
+            major_version: QGS_COLLATERAL_MAJOR_VER,
+            minor_version: QGS_COLLATERAL_MINOR_VER,
+            pck_ca: b"processor".to_vec(),
+            fmspc: vec![0x00, 0x80, 0x6f, 0x05, 0x00, 0x00],
+        }
+    }
+
+    fn collateral_response() -> QgsMessage {
+        QgsMessage::GetCollateralResponse {
+            error_code: QGS_MSG_SUCCESS,
+            major_version: QGS_COLLATERAL_MAJOR_VER,
+            minor_version: QGS_COLLATERAL_MINOR_VER,
+            collateral: QgsCollateral {
+                pck_crl_issuer_chain: b"pck crl chain\0".to_vec(),
+                root_ca_crl: b"root crl\0".to_vec(),
+                pck_crl: b"pck crl\0".to_vec(),
+                tcb_info_issuer_chain: b"tcb chain\0".to_vec(),
+                tcb_info: b"{}\0".to_vec(),
+                qe_identity_issuer_chain: Vec::new(),
+                qe_identity: b"{}\0".to_vec(),
+            },
+        }
+    }
+
+    fn platform_info_response() -> QgsMessage {
+        QgsMessage::GetPlatformInfoResponse {
+            error_code: QGS_MSG_SUCCESS,
+            tdqe_isvsvn: 4,
+            pce_isvsvn: 13,
+            platform_id: vec![0xab; 16],
+            cpusvn: vec![0xcd; 16],
+        }
+    }
+
+    fn messages() -> Vec<QgsMessage> {
+        vec![
+            quote_request(0),
+            quote_request(2),
+            QgsMessage::GetQuoteResponse {
+                error_code: QGS_MSG_SUCCESS,
+                selected_id: vec![1; QGS_ATT_KEY_ID_LEN],
+                quote: vec![9; 5006],
+            },
+            collateral_request(),
+            collateral_response(),
+            QgsMessage::GetPlatformInfoRequest,
+            platform_info_response(),
+        ]
+    }
+
+    #[test]
+    fn round_trips() {
+        for message in messages() {
+            let encoded = message.encode();
+            assert_eq!(
+                u32::from_le_bytes(encoded[8..12].try_into().unwrap()) as usize,
+                encoded.len()
+            );
+            assert_eq!(QgsMessage::decode(&encoded).unwrap(), message);
+            // the rest of a shared buffer after header.size is ignored
+            let mut padded = encoded.clone();
+            padded.extend([0xff; 64]);
+            assert_eq!(QgsMessage::decode(&padded).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn quote_request_layout() {
+        let encoded = quote_request(2).encode();
+        assert_eq!(encoded[16..20], TDX_REPORT_LEN.to_le_bytes(), "report size");
+        assert_eq!(
+            encoded[20..24],
+            (2 * QGS_ATT_KEY_ID_LEN as u32).to_le_bytes(),
+            "id list size"
+        );
+        assert_eq!(
+            encoded.len(),
+            QGS_MSG_HEADER_LEN + 8 + TDX_REPORT_LEN as usize + 2 * QGS_ATT_KEY_ID_LEN
+        );
+    }
+
+    #[test]
+    fn collateral_request_layout() {
+        // header, u16 major and minor version, u16 pck_ca and fmspc sizes, then the data
+        let mut expected = Vec::new();
+        expected.extend(QGS_MSG_LIB_MAJOR_VER.to_le_bytes());
+        expected.extend(QGS_MSG_LIB_MINOR_VER.to_le_bytes());
+        expected.extend(QGS_MSG_GET_COLLATERAL_REQ.to_le_bytes());
+        expected.extend(39u32.to_le_bytes());
+        expected.extend(QGS_MSG_SUCCESS.to_le_bytes());
+        expected.extend([3, 0, 0, 0, 9, 0, 6, 0]);
+        expected.extend(b"processor");
+        expected.extend([0x00, 0x80, 0x6f, 0x05, 0x00, 0x00]);
+        assert_eq!(collateral_request().encode(), expected);
+    }
+
+    #[test]
+    fn truncated_messages() {
+        for message in messages() {
+            let encoded = message.encode();
+            for len in 0..encoded.len() {
+                assert!(
+                    matches!(
+                        QgsMessage::decode(&encoded[..len]),
+                        Err(TdxError::SizeMismatch { .. })
+                    ),
+                    "type {} truncated to {} bytes",
+                    message.msg_type(),
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_fields() {
+        // quote size past header.size, although the buffer holds more bytes
+        let mut encoded = QgsMessage::GetQuoteResponse {
+            error_code: QGS_MSG_SUCCESS,
+            selected_id: Vec::new(),
+            quote: vec![9; 16],
+        }
+        .encode();
+        encoded[20..24].copy_from_slice(&17u32.to_le_bytes());
+        encoded.extend([0; 16]);
+        assert_eq!(
+            QgsMessage::decode(&encoded),
+            Err(TdxError::SizeMismatch {
+                what: "QGS message",
+                expected: 41,
+                actual: 40,
+            })
+        );
+
+        let mut encoded = collateral_response().encode();
+        encoded[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            QgsMessage::decode(&encoded),
+            Err(TdxError::SizeMismatch { .. })
+        ));
+
+        let mut encoded = platform_info_response().encode();
+        encoded[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            QgsMessage::decode(&encoded),
+            Err(TdxError::SizeMismatch { .. })
+        ));
+
+        // a header claiming less than itself
+        let mut encoded = QgsMessage::GetPlatformInfoRequest.encode();
+        encoded[8..12].copy_from_slice(&8u32.to_le_bytes());
+        assert!(matches!(
+            QgsMessage::decode(&encoded),
+            Err(TdxError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn bad_quote_request_sizes() {
+        let mut encoded = quote_request(1).encode();
+        encoded[20..24].copy_from_slice(&100u32.to_le_bytes());
+        assert_eq!(
+            QgsMessage::decode(&encoded),
+            Err(TdxError::SizeMismatch {
+                what: "QGS id list",
+                expected: 0,
+                actual: 100,
+            })
+        );
+
+        let mut encoded = quote_request(0).encode();
+        encoded[16..20].copy_from_slice(&0u32.to_le_bytes());
+        assert!(matches!(
+            QgsMessage::decode(&encoded),
+            Err(TdxError::SizeMismatch {
+                what: "QGS report",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut encoded = platform_info_response().encode();
+        encoded[0..2].copy_from_slice(&2u16.to_le_bytes());
+        encoded[2..4].copy_from_slice(&5u16.to_le_bytes());
+        assert_eq!(
+            QgsMessage::decode(&encoded),
+            Err(TdxError::QgsResponse {
+                major_version: 2,
+                minor_version: 5,
+                msg_type: QGS_MSG_GET_PLATFORM_INFO_RESP,
+            })
+        );
+
+        let mut encoded = platform_info_response().encode();
+        encoded[4..8].copy_from_slice(&6u32.to_le_bytes());
+        assert!(matches!(
+            QgsMessage::decode(&encoded),
+            Err(TdxError::QgsResponse { msg_type: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn responses() {
+        let request = QgsMessage::GetPlatformInfoRequest;
+        assert_eq!(
+            QgsMessage::decode_response(&request, &platform_info_response().encode()).unwrap(),
+            platform_info_response()
+        );
+
+        // the wrong response type is reported with the versions from its header
+        let mut encoded = collateral_response().encode();
+        encoded[2..4].copy_from_slice(&3u16.to_le_bytes());
+        assert_eq!(
+            QgsMessage::decode_response(&request, &encoded),
+            Err(TdxError::QgsResponse {
+                major_version: QGS_MSG_LIB_MAJOR_VER,
+                minor_version: 3,
+                msg_type: QGS_MSG_GET_COLLATERAL_RESP,
+            })
+        );
+        // a request is not a response
+        assert!(matches!(
+            QgsMessage::decode_response(&request, &request.encode()),
+            Err(TdxError::QgsResponse { .. })
+        ));
+
+        let busy = QgsMessage::GetQuoteResponse {
+            error_code: SGX_QL_ERROR_BUSY,
+            selected_id: Vec::new(),
+            quote: Vec::new(),
+        };
+        assert_eq!(
+            QgsMessage::decode_response(&quote_request(0), &busy.encode()),
+            Err(TdxError::QgsError(QgsErrorCode::Busy(SGX_QL_ERROR_BUSY)))
+        );
+    }
+}
@@ -0,0 +1,587 @@
+use crate::qgs::*;
+use crate::tee_tdx_lib::*;
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::socket::{
+    connect, setsockopt, socket, sockopt, AddressFamily, SockFlag, SockType, VsockAddr,
+};
+use nix::sys::time::TimeVal;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::result::Result;
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// QGS listens on the host (CID 2) when configured with -p=4050
+pub const QGS_VSOCK_CID_HOST: u32 = 2;
+pub const QGS_VSOCK_PORT: u32 = 4050;
+// upper bound on a response length prefix, quotes with a PCK chain are a few KiB
+const QGS_MAX_MSG_LEN: u32 = 1 << 20;
+// a zero socket timeout blocks forever, the last moments before the deadline round up to this
+const QGS_MIN_SOCKET_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Where the QGS stand-in or the host QGS listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QgsAddress {
+    Vsock { cid: u32, port: u32 },
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl Default for QgsAddress {
+    fn default() -> Self {
+        QgsAddress::Vsock {
+            cid: QGS_VSOCK_CID_HOST,
+            port: QGS_VSOCK_PORT,
+        }
+    }
+}
+
+impl fmt::Display for QgsAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QgsAddress::Vsock { cid, port } => write!(f, "vsock:{}:{}", cid, port),
+            QgsAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+            QgsAddress::Tcp(addr) => write!(f, "tcp:{}", addr),
+        }
+    }
+}
+
+// inverse of Display: vsock:CID:PORT, unix:PATH or tcp:HOST:PORT
+impl FromStr for QgsAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("unix", path)) if !path.is_empty() => Ok(QgsAddress::Unix(PathBuf::from(path))),
+            Some(("tcp", addr)) if !addr.is_empty() => Ok(QgsAddress::Tcp(addr.to_string())),
+            Some(("vsock", rest)) => {
+                match rest.split_once(':').map(|(c, p)| (c.parse(), p.parse())) {
+                    Some((Ok(cid), Ok(port))) => Ok(QgsAddress::Vsock { cid, port }),
+                    _ => Err(anyhow::anyhow!("[QgsAddress] Bad vsock address {}", s)),
+                }
+            }
+            _ => Err(anyhow::anyhow!("[QgsAddress] Unknown address {}", s)),
+        }
+    }
+}
+
+// linux/vm_sockets.h, the timeval variant on 64-bit; libc does not export it
+const SO_VM_SOCKETS_CONNECT_TIMEOUT: libc::c_int = 6;
+
+// vsock connect() ignores SO_SNDTIMEO and waits SO_VM_SOCKETS_CONNECT_TIMEOUT, 2s by default
+fn set_vsock_connect_timeout(fd: RawFd, tv: &TimeVal) -> io::Result<()> {
+    let tv: &libc::timeval = tv.as_ref();
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::AF_VSOCK,
+            SO_VM_SOCKETS_CONNECT_TIMEOUT,
+            tv as *const libc::timeval as *const libc::c_void,
+            mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+enum QgsStream {
+    Vsock(File),
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl QgsStream {
+    fn connect(address: &QgsAddress, timeout: Duration) -> io::Result<Self> {
+        match address {
+            QgsAddress::Vsock { cid, port } => {
+                let fd = socket(
+                    AddressFamily::Vsock,
+                    SockType::Stream,
+                    SockFlag::SOCK_CLOEXEC,
+                    None,
+                )?;
+                //take ownership first so the fd is closed on every error path below
+                let file = unsafe { File::from_raw_fd(fd) };
+                set_vsock_connect_timeout(fd, &timeval(timeout))?;
+                let stream = QgsStream::Vsock(file);
+                stream.set_timeout(timeout)?;
+                connect(fd, &VsockAddr::new(*cid, *port))?;
+                Ok(stream)
+            }
+            QgsAddress::Unix(path) => {
+                let stream = QgsStream::Unix(UnixStream::connect(path)?);
+                stream.set_timeout(timeout)?;
+                Ok(stream)
+            }
+            QgsAddress::Tcp(addr) => {
+                let mut last = io::Error::from(io::ErrorKind::AddrNotAvailable);
+                for sa in addr.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&sa, timeout) {
+                        Err(e) => last = e,
+                        Ok(stream) => {
+                            let stream = QgsStream::Tcp(stream);
+                            stream.set_timeout(timeout)?;
+                            return Ok(stream);
+                        }
+                    }
+                }
+                Err(last)
+            }
+        }
+    }
+
+    // send and receive timeout of the next operations, timeout must not be zero
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            QgsStream::Vsock(file) => {
+                let tv = timeval(timeout);
+                setsockopt(file.as_raw_fd(), sockopt::ReceiveTimeout, &tv)?;
+                setsockopt(file.as_raw_fd(), sockopt::SendTimeout, &tv)?;
+                Ok(())
+            }
+            QgsStream::Unix(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+            QgsStream::Tcp(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+        }
+    }
+
+    fn as_io(&mut self) -> &mut dyn ReadWrite {
+        match self {
+            QgsStream::Vsock(s) => s,
+            QgsStream::Unix(s) => s,
+            QgsStream::Tcp(s) => s,
+        }
+    }
+}
+
+fn timeval(timeout: Duration) -> TimeVal {
+    TimeVal::new(timeout.as_secs() as _, timeout.subsec_micros() as _)
+}
+
+trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+/// Quotes straight from QGS over a socket, for TDs whose kernel lacks GetQuote support.
+/// TDREPORTs and RTMR extension still use the ioctls unless another local backend is set.
+#[derive(Clone, Default)]
+pub struct QgsSocketBackend {
+    address: QgsAddress,
+    local: Option<Arc<dyn TdxBackend>>,
+}
+
+impl fmt::Debug for QgsSocketBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QgsSocketBackend")
+            .field("address", &self.address)
+            .field("local", &self.local.as_ref().map(|b| b.name().to_string()))
+            .finish()
+    }
+}
+
+impl QgsSocketBackend {
+    pub fn new(address: QgsAddress) -> Self {
+        QgsSocketBackend {
+            address,
+            local: None,
+        }
+    }
+
+    // take TDREPORTs and RTMR extension from local, e.g. a SimulatedBackend talking to a QGS emulator
+    pub fn local_backend(mut self, local: Arc<dyn TdxBackend>) -> Self {
+        self.local = Some(local);
+        self
+    }
+
+    fn local(&self) -> &dyn TdxBackend {
+        match &self.local {
+            Some(local) => local.as_ref(),
+            None => &IoctlBackend,
+        }
+    }
+
+    pub fn vsock(cid: u32, port: u32) -> Self {
+        QgsSocketBackend::new(QgsAddress::Vsock { cid, port })
+    }
+
+    pub fn address(&self) -> &QgsAddress {
+        &self.address
+    }
+
+    fn io_error(&self, e: io::Error, started: Instant) -> TdxError {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                TdxError::Timeout(started.elapsed())
+            }
+            _ => TdxError::QgsIo {
+                address: self.address.to_string(),
+                errno: e.raw_os_error().map(Errno::from_i32),
+            },
+        }
+    }
+
+    // time left until the deadline of the whole transaction, after checking for cancellation
+    fn remaining(options: &QuoteOptions, started: Instant) -> Result<Duration, TdxError> {
+        options.check(started)?;
+        Ok(options
+            .timeout
+            .saturating_sub(started.elapsed())
+            .max(QGS_MIN_SOCKET_TIMEOUT))
+    }
+
+    // read_exact against the transaction deadline; each read waits at most poll_interval so a
+    // cancellation is noticed while QGS is still working on the quote
+    fn read_within(
+        &self,
+        stream: &mut QgsStream,
+        mut buf: &mut [u8],
+        options: &QuoteOptions,
+        started: Instant,
+    ) -> Result<(), TdxError> {
+        while !buf.is_empty() {
+            let timeout = Self::remaining(options, started)?
+                .min(options.poll_interval.max(QGS_MIN_SOCKET_TIMEOUT));
+            if let Err(e) = stream.set_timeout(timeout) {
+                return Err(self.io_error(e, started));
+            }
+            match stream.as_io().read(buf) {
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted => continue,
+                    _ => return Err(self.io_error(e, started)),
+                },
+                Ok(0) => return Err(self.io_error(io::ErrorKind::UnexpectedEof.into(), started)),
+                Ok(n) => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    // sends request with its 4-byte big-endian length prefix and decodes the matching response,
+    // options.timeout bounds the whole exchange from connect to the last byte of the response
+    pub fn transact(
+        &self,
+        request: &QgsMessage,
+        options: &QuoteOptions,
+    ) -> Result<QgsMessage, TdxError> {
+        let started = Instant::now();
+
+        let mut stream = match QgsStream::connect(&self.address, Self::remaining(options, started)?)
+        {
+            Err(e) => return Err(self.io_error(e, started)),
+            Ok(s) => s,
+        };
+
+        let msg = request.encode();
+        let mut framed = (msg.len() as u32).to_be_bytes().to_vec();
+        framed.extend_from_slice(&msg);
+        let written = stream
+            .set_timeout(Self::remaining(options, started)?)
+            .and_then(|_| stream.as_io().write_all(&framed))
+            .and_then(|_| stream.as_io().flush());
+        if let Err(e) = written {
+            return Err(self.io_error(e, started));
+        }
+
+        let mut len_bytes = [0; 4];
+        self.read_within(&mut stream, &mut len_bytes, options, started)?;
+        let len = u32::from_be_bytes(len_bytes);
+        if len > QGS_MAX_MSG_LEN {
+            return Err(TdxError::SizeMismatch {
+                what: "QGS response",
+                expected: QGS_MAX_MSG_LEN as usize,
+                actual: len as usize,
+            });
+        }
+
+        let mut response = vec![0; len as usize];
+        self.read_within(&mut stream, &mut response, options, started)?;
+        QgsMessage::decode_response(request, &response)
+    }
+
+    pub fn quote_for_report(
+        &self,
+        report: &[u8],
+        options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError> {
+        let (_, quote) = self.quote_for_report_with_ids(report, &[], options)?;
+        Ok(quote)
+    }
+
+    // let QGS choose among the attestation key ids in id_list, returns the selected id and the quote
+    pub fn quote_for_report_with_ids(
+        &self,
+        report: &[u8],
+        id_list: &[Vec<u8>],
+        options: &QuoteOptions,
+    ) -> Result<(Vec<u8>, Vec<u8>), TdxError> {
+        if report.len() != TDX_REPORT_LEN as usize {
+            return Err(TdxError::SizeMismatch {
+                what: "TDX report",
+                expected: TDX_REPORT_LEN as usize,
+                actual: report.len(),
+            });
+        }
+        let request = generate_qgs_quote_msg(report, id_list);
+        match self.transact(&request, options)? {
+            QgsMessage::GetQuoteResponse {
+                selected_id, quote, ..
+            } => Ok((selected_id, quote)),
+            _ => unreachable!("transact checks the response type"),
+        }
+    }
+
+    // verification collateral for the platform's FMSPC, pck_ca is "processor" or "platform"
+    pub fn get_collateral(
+        &self,
+        pck_ca: &str,
+        fmspc: &[u8],
+        options: &QuoteOptions,
+    ) -> Result<QgsCollateral, TdxError> {
+        let request = QgsMessage::GetCollateralRequest {
+            major_version: QGS_COLLATERAL_MAJOR_VER,
+            minor_version: QGS_COLLATERAL_MINOR_VER,
+            pck_ca: pck_ca.as_bytes().to_vec(),
+            fmspc: fmspc.to_vec(),
+        };
+        match self.transact(&request, options)? {
+            QgsMessage::GetCollateralResponse { collateral, .. } => Ok(collateral),
+            _ => unreachable!("transact checks the response type"),
+        }
+    }
+
+    pub fn get_platform_info(&self, options: &QuoteOptions) -> Result<QgsMessage, TdxError> {
+        self.transact(&QgsMessage::GetPlatformInfoRequest, options)
+    }
+}
+
+impl TdxBackend for QgsSocketBackend {
+    fn name(&self) -> &str {
+        "qgs-socket"
+    }
+
+    fn get_report(&self, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+        self.local().get_report(report_data)
+    }
+
+    fn get_quote(
+        &self,
+        report_data: &ReportData,
+        options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError> {
+        let report = self.local().get_report(report_data)?;
+        self.quote_for_report(&report, options)
+    }
+
+    fn extend_rtmr(
+        &self,
+        index: u8,
+        digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+    ) -> Result<(), RtmrError> {
+        self.local().extend_rtmr(index, digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quote::Quote;
+    use crate::simulator::{SimulatedBackend, SimulatedTdConfig};
+    use crate::verify::verify_quote_at;
+    use std::env;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+    use std::thread;
+    use std::time::SystemTime;
+
+    // a local stand-in QGS accepting one connection on a Unix socket and reading one request
+    fn stand_in_stream<F>(test: &str, serve: F) -> QgsAddress
+    where
+        F: FnOnce(UnixStream, QgsMessage) + Send + 'static,
+    {
+        let path = env::temp_dir().join(format!("qgs-{}-{}.sock", test, process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let socket = path.clone();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = std::fs::remove_file(socket);
+            let mut len_bytes = [0; 4];
+            stream.read_exact(&mut len_bytes).unwrap();
+            let mut msg = vec![0; u32::from_be_bytes(len_bytes) as usize];
+            stream.read_exact(&mut msg).unwrap();
+            serve(stream, QgsMessage::decode(&msg).unwrap());
+        });
+        QgsAddress::Unix(path)
+    }
+
+    // answers the request, respond returning None keeps the connection open without answering
+    fn stand_in<F>(test: &str, respond: F) -> QgsAddress
+    where
+        F: FnOnce(QgsMessage) -> Option<QgsMessage> + Send + 'static,
+    {
+        stand_in_stream(test, |mut stream, request| match respond(request) {
+            None => thread::sleep(Duration::from_secs(2)),
+            Some(response) => {
+                let response = response.encode();
+                stream
+                    .write_all(&(response.len() as u32).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn quote_from_stand_in() {
+        let platform = Arc::new(SimulatedBackend::new(SimulatedTdConfig::default()).unwrap());
+        let qgs = platform.clone();
+        let address = stand_in("quote", move |request| match request {
+            QgsMessage::GetQuoteRequest { report, .. } => Some(QgsMessage::GetQuoteResponse {
+                error_code: QGS_MSG_SUCCESS,
+                selected_id: Vec::new(),
+                quote: qgs.quote_for_report(&report).unwrap(),
+            }),
+            _ => None,
+        });
+
+        let backend = QgsSocketBackend::new(address).local_backend(platform.clone());
+        let quote = backend
+            .get_quote(&ReportData([3; 64]), &QuoteOptions::default())
+            .unwrap();
+        let quote = Quote::from_bytes(&quote).unwrap();
+        assert_eq!(quote.body.report_data, [3; 64]);
+        let verdict = verify_quote_at(&quote, platform.root_certificate(), SystemTime::now());
+        assert!(verdict.is_valid(), "{:?}", verdict.failures());
+    }
+
+    #[test]
+    fn error_code_from_stand_in() {
+        let platform = Arc::new(SimulatedBackend::new(SimulatedTdConfig::default()).unwrap());
+        let address = stand_in("busy", |_| {
+            Some(QgsMessage::GetQuoteResponse {
+                error_code: SGX_QL_ERROR_BUSY,
+                selected_id: Vec::new(),
+                quote: Vec::new(),
+            })
+        });
+
+        let backend = QgsSocketBackend::new(address).local_backend(platform);
+        let result = backend.get_quote(&ReportData([3; 64]), &QuoteOptions::default());
+        match result {
+            Err(e @ TdxError::QgsError(_)) => assert!(e.is_retriable()),
+            other => panic!("expected a QGS error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn silent_stand_in_times_out() {
+        let platform = Arc::new(SimulatedBackend::new(SimulatedTdConfig::default()).unwrap());
+        let address = stand_in("silent", |_| None);
+
+        let backend = QgsSocketBackend::new(address).local_backend(platform);
+        let options = QuoteOptions {
+            timeout: Duration::from_millis(200),
+            ..QuoteOptions::default()
+        };
+        let started = Instant::now();
+        let result = backend.get_quote(&ReportData([3; 64]), &options);
+        assert!(matches!(result, Err(TdxError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn trickling_stand_in_times_out() {
+        // one byte of a 100 byte response every 50ms, well past the deadline
+        let address = stand_in_stream("trickle", |mut stream, _| {
+            let _ = stream.write_all(&100u32.to_be_bytes());
+            for _ in 0..100 {
+                thread::sleep(Duration::from_millis(50));
+                if stream.write_all(&[0]).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let backend = QgsSocketBackend::new(address);
+        let options = QuoteOptions {
+            timeout: Duration::from_millis(300),
+            ..QuoteOptions::default()
+        };
+        let started = Instant::now();
+        let result = backend.get_platform_info(&options);
+        assert!(matches!(result, Err(TdxError::Timeout(_))), "{:?}", result);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cancel_between_header_and_body() {
+        let address = stand_in_stream("cancel", |mut stream, _| {
+            stream.write_all(&100u32.to_be_bytes()).unwrap();
+            thread::sleep(Duration::from_secs(2));
+        });
+
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+        let backend = QgsSocketBackend::new(address);
+        let options = QuoteOptions {
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(20),
+            cancel: Some(cancel),
+            ..QuoteOptions::default()
+        };
+        let started = Instant::now();
+        let result = backend.get_platform_info(&options);
+        assert!(matches!(result, Err(TdxError::Cancelled)), "{:?}", result);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn zero_timeout() {
+        // fails before connecting instead of waiting without a timeout
+        let backend = QgsSocketBackend::new(QgsAddress::Unix(PathBuf::from("/nonexistent")));
+        let options = QuoteOptions {
+            timeout: Duration::ZERO,
+            ..QuoteOptions::default()
+        };
+        assert!(matches!(
+            backend.get_platform_info(&options),
+            Err(TdxError::Timeout(_))
+        ));
+    }
+
+    #[test]
+    fn parse_address() {
+        assert_eq!(
+            QgsAddress::from_str("vsock:2:4050").unwrap(),
+            QgsAddress::Vsock { cid: 2, port: 4050 }
+        );
+        assert_eq!(
+            QgsAddress::from_str("unix:/run/qgs.sock").unwrap(),
+            QgsAddress::Unix(PathBuf::from("/run/qgs.sock"))
+        );
+        assert!(QgsAddress::from_str("vsock:2").is_err());
+        assert!(QgsAddress::from_str("udp:localhost:4050").is_err());
+    }
+}
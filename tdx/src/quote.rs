@@ -0,0 +1,664 @@
+use crate::evidence::hex_bytes;
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::result::Result;
+use std::result::Result::Ok;
+
+// Quote layout, see Intel TDX DCAP Quoting Library API, appendix "TD Quote Format"
+pub const QUOTE_VERSION_4: u16 = 4;
+pub const QUOTE_VERSION_5: u16 = 5;
+pub const TEE_TYPE_TDX: u32 = 0x81;
+pub const ATT_KEY_TYPE_ECDSA_P256: u16 = 2;
+pub const INTEL_QE_VENDOR_ID: [u8; 16] = [
+    0x93, 0x9a, 0x72, 0x33, 0xf7, 0x9c, 0x4c, 0xa9, 0x94, 0x0a, 0x0d, 0xb3, 0x95, 0x7f, 0x06, 0x07,
+];
+
+// v5 body descriptor types
+pub const BODY_TYPE_SGX_ENCLAVE: u16 = 1;
+pub const BODY_TYPE_TD10: u16 = 2;
+pub const BODY_TYPE_TD15: u16 = 3;
+
+// certification data types
+pub const CERT_DATA_TYPE_PCK_CERT_CHAIN: u16 = 5;
+pub const CERT_DATA_TYPE_QE_REPORT: u16 = 6;
+
+const TD10_BODY_LEN: usize = 584;
+const TD15_BODY_LEN: usize = 648;
+pub const ENCLAVE_REPORT_LEN: usize = 384;
+
+// little-endian cursor over quote bytes, every read is bounds checked
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, offset: 0 }
+    }
+
+    fn take(&mut self, len: usize, what: &str) -> Result<&'a [u8], anyhow::Error> {
+        if self.bytes.len() - self.offset < len {
+            return Err(anyhow!(
+                "[Quote::from_bytes] Truncated quote reading {} at offset {}: need {} bytes, {} left",
+                what,
+                self.offset,
+                len,
+                self.bytes.len() - self.offset
+            ));
+        }
+        let slice = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self, what: &str) -> Result<[u8; N], anyhow::Error> {
+        Ok(self.take(N, what)?.try_into().unwrap())
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, anyhow::Error> {
+        Ok(u16::from_le_bytes(self.array(what)?))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32, anyhow::Error> {
+        Ok(u32::from_le_bytes(self.array(what)?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteHeader {
+    pub version: u16,
+    pub att_key_type: u16,
+    pub tee_type: u32,
+    pub qe_svn: u16,
+    pub pce_svn: u16,
+    #[serde(with = "hex_bytes")]
+    pub qe_vendor_id: [u8; 16],
+    #[serde(with = "hex_bytes")]
+    pub user_data: [u8; 20],
+}
+
+impl QuoteHeader {
+    fn parse(reader: &mut Reader) -> Result<Self, anyhow::Error> {
+        Ok(QuoteHeader {
+            version: reader.u16("header.version")?,
+            att_key_type: reader.u16("header.att_key_type")?,
+            tee_type: reader.u32("header.tee_type")?,
+            qe_svn: reader.u16("header.qe_svn")?,
+            pce_svn: reader.u16("header.pce_svn")?,
+            qe_vendor_id: reader.array("header.qe_vendor_id")?,
+            user_data: reader.array("header.user_data")?,
+        })
+    }
+}
+
+/// TD report body of a quote: TD 1.0 (v4, or v5 body type 2) or TD 1.5 (v5 body type 3).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TdQuoteBody {
+    #[serde(with = "hex_bytes")]
+    pub tee_tcb_svn: [u8; 16],
+    #[serde(with = "hex_bytes")]
+    pub mrseam: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub mrsignerseam: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub seam_attributes: [u8; 8],
+    #[serde(with = "hex_bytes")]
+    pub td_attributes: [u8; 8],
+    #[serde(with = "hex_bytes")]
+    pub xfam: [u8; 8],
+    #[serde(with = "hex_bytes")]
+    pub mrtd: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub mrconfigid: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub mrowner: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub mrownerconfig: [u8; 48],
+    #[serde(with = "hex_bytes::list")]
+    pub rtmrs: [[u8; 48]; 4],
+    #[serde(with = "hex_bytes")]
+    pub report_data: [u8; 64],
+    #[serde(default, with = "hex_bytes::option")]
+    pub tee_tcb_svn2: Option<[u8; 16]>, // TD 1.5 body only
+    #[serde(default, with = "hex_bytes::option")]
+    pub mrservicetd: Option<[u8; 48]>, // TD 1.5 body only
+}
+
+impl TdQuoteBody {
+    fn parse(reader: &mut Reader, td15: bool) -> Result<Self, anyhow::Error> {
+        let mut body = TdQuoteBody {
+            tee_tcb_svn: reader.array("body.tee_tcb_svn")?,
+            mrseam: reader.array("body.mrseam")?,
+            mrsignerseam: reader.array("body.mrsignerseam")?,
+            seam_attributes: reader.array("body.seam_attributes")?,
+            td_attributes: reader.array("body.td_attributes")?,
+            xfam: reader.array("body.xfam")?,
+            mrtd: reader.array("body.mrtd")?,
+            mrconfigid: reader.array("body.mrconfigid")?,
+            mrowner: reader.array("body.mrowner")?,
+            mrownerconfig: reader.array("body.mrownerconfig")?,
+            rtmrs: [
+                reader.array("body.rtmr0")?,
+                reader.array("body.rtmr1")?,
+                reader.array("body.rtmr2")?,
+                reader.array("body.rtmr3")?,
+            ],
+            report_data: reader.array("body.report_data")?,
+            tee_tcb_svn2: None,
+            mrservicetd: None,
+        };
+        if td15 {
+            body.tee_tcb_svn2 = Some(reader.array("body.tee_tcb_svn2")?);
+            body.mrservicetd = Some(reader.array("body.mrservicetd")?);
+        }
+        Ok(body)
+    }
+}
+
+/// SGX enclave report body, used for the QE report inside the certification data.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnclaveReport {
+    #[serde(with = "hex_bytes")]
+    pub cpusvn: [u8; 16],
+    pub miscselect: u32,
+    #[serde(with = "hex_bytes")]
+    pub attributes: [u8; 16],
+    #[serde(with = "hex_bytes")]
+    pub mrenclave: [u8; 32],
+    #[serde(with = "hex_bytes")]
+    pub mrsigner: [u8; 32],
+    pub isv_prod_id: u16,
+    pub isv_svn: u16,
+    #[serde(with = "hex_bytes")]
+    pub report_data: [u8; 64],
+    #[serde(with = "hex_bytes")]
+    pub raw: Vec<u8>, // the signed 384 bytes
+}
+
+impl EnclaveReport {
+    fn parse(reader: &mut Reader) -> Result<Self, anyhow::Error> {
+        let raw = reader.take(ENCLAVE_REPORT_LEN, "qe_report")?;
+        let mut r = Reader::new(raw);
+        let cpusvn = r.array("qe_report.cpusvn")?;
+        let miscselect = r.u32("qe_report.miscselect")?;
+        r.take(28, "qe_report.reserved1")?;
+        let attributes = r.array("qe_report.attributes")?;
+        let mrenclave = r.array("qe_report.mrenclave")?;
+        r.take(32, "qe_report.reserved2")?;
+        let mrsigner = r.array("qe_report.mrsigner")?;
+        r.take(96, "qe_report.reserved3")?;
+        let isv_prod_id = r.u16("qe_report.isv_prod_id")?;
+        let isv_svn = r.u16("qe_report.isv_svn")?;
+        r.take(60, "qe_report.reserved4")?;
+        let report_data = r.array("qe_report.report_data")?;
+
+        Ok(EnclaveReport {
+            cpusvn,
+            miscselect,
+            attributes,
+            mrenclave,
+            mrsigner,
+            isv_prod_id,
+            isv_svn,
+            report_data,
+            raw: raw.to_vec(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificationData {
+    pub cert_type: u16,
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+    pub qe_report_data: Option<Box<QeReportCertificationData>>, // decoded when cert_type is 6
+}
+
+impl CertificationData {
+    // the QE report wrapper (type 6) appears only at the top level, never nested in itself
+    fn parse(reader: &mut Reader, nested: bool) -> Result<Self, anyhow::Error> {
+        let cert_type = reader.u16("certification_data.type")?;
+        let size = reader.u32("certification_data.size")? as usize;
+        let data = reader.take(size, "certification_data.data")?;
+        let qe_report_data = match cert_type {
+            CERT_DATA_TYPE_QE_REPORT if nested => {
+                return Err(anyhow!(
+                    "[Quote::from_bytes] QE report certification data nested in another"
+                ))
+            }
+            CERT_DATA_TYPE_QE_REPORT => {
+                //the QE report, its signature, auth data and the nested chain fill the size exactly
+                let mut r = Reader::new(data);
+                let qe_report_data = QeReportCertificationData::parse(&mut r)?;
+                if r.offset != data.len() {
+                    return Err(anyhow!(
+                        "[Quote::from_bytes] QE report certification data size {} leaves {} bytes unused",
+                        data.len(),
+                        data.len() - r.offset
+                    ));
+                }
+                Some(Box::new(qe_report_data))
+            }
+            _ => None,
+        };
+
+        Ok(CertificationData {
+            cert_type,
+            data: data.to_vec(),
+            qe_report_data,
+        })
+    }
+
+    // PEM encoded PCK leaf, intermediate and root certificates, following the QE report wrapper if needed
+    pub fn pck_cert_chain(&self) -> Option<&[u8]> {
+        match self.cert_type {
+            CERT_DATA_TYPE_PCK_CERT_CHAIN => Some(&self.data),
+            CERT_DATA_TYPE_QE_REPORT => self
+                .qe_report_data
+                .as_ref()
+                .and_then(|q| q.certification_data.pck_cert_chain()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QeReportCertificationData {
+    pub qe_report: EnclaveReport,
+    #[serde(with = "hex_bytes")]
+    pub qe_report_signature: [u8; 64],
+    #[serde(with = "hex_bytes")]
+    pub qe_auth_data: Vec<u8>,
+    pub certification_data: CertificationData,
+}
+
+impl QeReportCertificationData {
+    fn parse(reader: &mut Reader) -> Result<Self, anyhow::Error> {
+        let qe_report = EnclaveReport::parse(reader)?;
+        let qe_report_signature = reader.array("qe_report_signature")?;
+        let qe_auth_data_len = reader.u16("qe_auth_data.size")? as usize;
+        let qe_auth_data = reader.take(qe_auth_data_len, "qe_auth_data.data")?.to_vec();
+        let certification_data = CertificationData::parse(reader, true)?;
+
+        Ok(QeReportCertificationData {
+            qe_report,
+            qe_report_signature,
+            qe_auth_data,
+            certification_data,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteSignatureData {
+    #[serde(with = "hex_bytes")]
+    pub signature: [u8; 64], // ECDSA r || s over header and body
+    #[serde(with = "hex_bytes")]
+    pub attestation_key: [u8; 64], // raw P-256 public key x || y
+    pub certification_data: CertificationData,
+}
+
+impl QuoteSignatureData {
+    fn parse(reader: &mut Reader) -> Result<Self, anyhow::Error> {
+        Ok(QuoteSignatureData {
+            signature: reader.array("signature")?,
+            attestation_key: reader.array("attestation_key")?,
+            certification_data: CertificationData::parse(reader, false)?,
+        })
+    }
+
+    pub fn qe_report_data(&self) -> Option<&QeReportCertificationData> {
+        self.certification_data.qe_report_data.as_deref()
+    }
+}
+
+/// Parsed TD quote (v4 or v5) as returned by `get_tdx_quote`.
+/// Serializes with its raw bytes next to the decoded fields, which must agree on deserialization.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "QuoteFields")]
+pub struct Quote {
+    pub header: QuoteHeader,
+    pub body_type: u16,
+    pub body: TdQuoteBody,
+    pub signature_data: QuoteSignatureData,
+    #[serde(with = "hex_bytes")]
+    raw: Vec<u8>,
+    #[serde(skip)]
+    signed_len: usize,
+}
+
+#[derive(Deserialize)]
+struct QuoteFields {
+    header: QuoteHeader,
+    body_type: u16,
+    body: TdQuoteBody,
+    signature_data: QuoteSignatureData,
+    #[serde(with = "hex_bytes")]
+    raw: Vec<u8>,
+}
+
+impl TryFrom<QuoteFields> for Quote {
+    type Error = anyhow::Error;
+
+    fn try_from(fields: QuoteFields) -> Result<Self, Self::Error> {
+        let quote = Quote::from_bytes(&fields.raw)?;
+        if quote.header != fields.header
+            || quote.body_type != fields.body_type
+            || quote.body != fields.body
+            || quote.signature_data != fields.signature_data
+        {
+            return Err(anyhow!("[Quote] Decoded fields do not match the raw quote"));
+        }
+        Ok(quote)
+    }
+}
+
+impl Quote {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = Reader::new(bytes);
+        let header = QuoteHeader::parse(&mut reader)?;
+        if header.tee_type != TEE_TYPE_TDX {
+            return Err(anyhow!(
+                "[Quote::from_bytes] Not a TDX quote: tee type {:#x}",
+                header.tee_type
+            ));
+        }
+
+        let body_type = match header.version {
+            QUOTE_VERSION_4 => BODY_TYPE_TD10,
+            QUOTE_VERSION_5 => {
+                let body_type = reader.u16("body.type")?;
+                let body_size = reader.u32("body.size")? as usize;
+                let expected_size = match body_type {
+                    BODY_TYPE_TD10 => TD10_BODY_LEN,
+                    BODY_TYPE_TD15 => TD15_BODY_LEN,
+                    _ => {
+                        return Err(anyhow!(
+                            "[Quote::from_bytes] Unsupported quote body type {}",
+                            body_type
+                        ))
+                    }
+                };
+                if body_size != expected_size {
+                    return Err(anyhow!(
+                        "[Quote::from_bytes] Wrong quote body size for type {}: expected {}, got {}",
+                        body_type,
+                        expected_size,
+                        body_size
+                    ));
+                }
+                body_type
+            }
+            v => {
+                return Err(anyhow!(
+                    "[Quote::from_bytes] Unsupported quote version {}",
+                    v
+                ))
+            }
+        };
+        let body = TdQuoteBody::parse(&mut reader, body_type == BODY_TYPE_TD15)?;
+        let signed_len = reader.offset;
+
+        let signature_data_len = reader.u32("signature_data.size")? as usize;
+        let signature_data_bytes = reader.take(signature_data_len, "signature_data")?;
+        let signature_data = QuoteSignatureData::parse(&mut Reader::new(signature_data_bytes))?;
+
+        Ok(Quote {
+            header,
+            body_type,
+            body,
+            signature_data,
+            raw: bytes[0..reader.offset].to_vec(),
+            signed_len,
+        })
+    }
+
+    // the quote bytes, without any trailing padding of the buffer it was parsed from
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    // header and body bytes covered by the attestation key signature
+    pub fn signed_data(&self) -> &[u8] {
+        &self.raw[0..self.signed_len]
+    }
+
+    pub fn mrtd(&self) -> &[u8; 48] {
+        &self.body.mrtd
+    }
+
+    pub fn rtmr(&self, index: usize) -> Option<&[u8; 48]> {
+        self.body.rtmrs.get(index)
+    }
+
+    pub fn report_data(&self) -> &[u8; 64] {
+        &self.body.report_data
+    }
+
+    pub fn pck_cert_chain(&self) -> Option<&[u8]> {
+        self.signature_data.certification_data.pck_cert_chain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{SimulatedBackend, SimulatedTdConfig};
+    use crate::tdreport::TdReportVersion;
+    use crate::tee_tdx_lib::ReportData;
+
+    const HEADER_LEN: usize = 48;
+
+    fn simulated_quote(version: TdReportVersion) -> Vec<u8> {
+        let platform = SimulatedBackend::new(SimulatedTdConfig {
+            version,
+            ..SimulatedTdConfig::default()
+        })
+        .unwrap();
+        let report = platform.build_report(&ReportData([8; 64]));
+        platform.quote_for_report(&report).unwrap()
+    }
+
+    // a v4 quote with a v5 body descriptor for a TD 1.0 body in front of the same body
+    fn v5_td10_quote(v4: &[u8]) -> Vec<u8> {
+        let mut quote = QUOTE_VERSION_5.to_le_bytes().to_vec();
+        quote.extend(&v4[2..HEADER_LEN]);
+        quote.extend(BODY_TYPE_TD10.to_le_bytes());
+        quote.extend((TD10_BODY_LEN as u32).to_le_bytes());
+        quote.extend(&v4[HEADER_LEN..]);
+        quote
+    }
+
+    fn certification_data(cert_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = cert_type.to_le_bytes().to_vec();
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    // the QE report certification data of quote, as the simulator wrote it
+    fn qe_report_data(quote: &Quote) -> Vec<u8> {
+        let qe = quote.signature_data.qe_report_data().unwrap();
+        let mut bytes = qe.qe_report.raw.clone();
+        bytes.extend(qe.qe_report_signature);
+        bytes.extend((qe.qe_auth_data.len() as u16).to_le_bytes());
+        bytes.extend(&qe.qe_auth_data);
+        bytes.extend(certification_data(
+            qe.certification_data.cert_type,
+            &qe.certification_data.data,
+        ));
+        bytes
+    }
+
+    // quote with its certification data replaced, the signature data size adjusted to match
+    fn with_certification_data(quote: &Quote, certification: &[u8]) -> Vec<u8> {
+        let signature_data = &quote.signature_data;
+        let mut bytes = quote.signed_data().to_vec();
+        bytes.extend(((128 + certification.len()) as u32).to_le_bytes());
+        bytes.extend(signature_data.signature);
+        bytes.extend(signature_data.attestation_key);
+        bytes.extend(certification);
+        bytes
+    }
+
+    fn parse_error(bytes: &[u8]) -> String {
+        Quote::from_bytes(bytes).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn v4_quote() {
+        let bytes = simulated_quote(TdReportVersion::TDX10);
+        let quote = Quote::from_bytes(&bytes).unwrap();
+        assert_eq!(quote.header.version, QUOTE_VERSION_4);
+        assert_eq!(quote.header.att_key_type, ATT_KEY_TYPE_ECDSA_P256);
+        assert_eq!(quote.header.qe_vendor_id, INTEL_QE_VENDOR_ID);
+        assert_eq!(quote.body_type, BODY_TYPE_TD10);
+        assert_eq!(quote.report_data(), &[8; 64]);
+        assert_eq!(quote.body.tee_tcb_svn2, None);
+        assert_eq!(quote.body.mrservicetd, None);
+        assert_eq!(quote.signed_data().len(), HEADER_LEN + TD10_BODY_LEN);
+        assert!(quote.pck_cert_chain().unwrap().starts_with(b"-----BEGIN"));
+
+        // the rest of a GetQuote buffer is not part of the quote
+        let mut padded = bytes.clone();
+        padded.extend([0; 100]);
+        assert_eq!(Quote::from_bytes(&padded).unwrap().as_bytes(), &bytes[..]);
+    }
+
+    #[test]
+    fn v5_quotes() {
+        let quote = Quote::from_bytes(&simulated_quote(TdReportVersion::TDX15)).unwrap();
+        assert_eq!(quote.header.version, QUOTE_VERSION_5);
+        assert_eq!(quote.body_type, BODY_TYPE_TD15);
+        assert_eq!(quote.report_data(), &[8; 64]);
+        assert!(quote.body.tee_tcb_svn2.is_some());
+        assert!(quote.body.mrservicetd.is_some());
+        assert_eq!(quote.signed_data().len(), HEADER_LEN + 6 + TD15_BODY_LEN);
+
+        // a TD 1.0 body in a v5 quote parses like the v4 one
+        let v4_bytes = simulated_quote(TdReportVersion::TDX10);
+        let v4 = Quote::from_bytes(&v4_bytes).unwrap();
+        let quote = Quote::from_bytes(&v5_td10_quote(&v4_bytes)).unwrap();
+        assert_eq!(quote.header.version, QUOTE_VERSION_5);
+        assert_eq!(quote.body_type, BODY_TYPE_TD10);
+        assert_eq!(quote.body, v4.body);
+        assert_eq!(quote.signature_data, v4.signature_data);
+        assert_eq!(quote.signed_data().len(), HEADER_LEN + 6 + TD10_BODY_LEN);
+    }
+
+    #[test]
+    fn bad_headers() {
+        let mut bytes = simulated_quote(TdReportVersion::TDX10);
+        bytes[0..2].copy_from_slice(&3u16.to_le_bytes());
+        assert!(parse_error(&bytes).contains("Unsupported quote version 3"));
+
+        let mut bytes = simulated_quote(TdReportVersion::TDX10);
+        bytes[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert!(parse_error(&bytes).contains("Not a TDX quote: tee type 0x0"));
+
+        let v4 = simulated_quote(TdReportVersion::TDX10);
+        let mut bytes = v5_td10_quote(&v4);
+        bytes[HEADER_LEN..HEADER_LEN + 2].copy_from_slice(&BODY_TYPE_SGX_ENCLAVE.to_le_bytes());
+        assert!(parse_error(&bytes).contains("Unsupported quote body type 1"));
+
+        let mut bytes = v5_td10_quote(&v4);
+        bytes[HEADER_LEN + 2..HEADER_LEN + 6]
+            .copy_from_slice(&(TD15_BODY_LEN as u32).to_le_bytes());
+        assert!(
+            parse_error(&bytes).contains("Wrong quote body size for type 2: expected 584, got 648")
+        );
+    }
+
+    #[test]
+    fn truncated_signature_data() {
+        let bytes = simulated_quote(TdReportVersion::TDX15);
+        let signed_len = Quote::from_bytes(&bytes).unwrap().signed_data().len();
+        for len in 0..bytes.len() {
+            assert!(
+                parse_error(&bytes[..len]).contains("Truncated quote"),
+                "quote truncated to {} bytes",
+                len
+            );
+        }
+
+        // a signature data size past the end of the quote
+        let mut bytes = bytes;
+        let size = u32::from_le_bytes(bytes[signed_len..signed_len + 4].try_into().unwrap());
+        bytes[signed_len..signed_len + 4].copy_from_slice(&(size + 1).to_le_bytes());
+        assert!(parse_error(&bytes).contains("Truncated quote reading signature_data "));
+    }
+
+    #[test]
+    fn certification_data_types() {
+        let quote = Quote::from_bytes(&simulated_quote(TdReportVersion::TDX15)).unwrap();
+        let pem = quote.pck_cert_chain().unwrap().to_vec();
+
+        // a bare PCK chain without the QE report
+        let bytes = with_certification_data(
+            &quote,
+            &certification_data(CERT_DATA_TYPE_PCK_CERT_CHAIN, &pem),
+        );
+        let bare = Quote::from_bytes(&bytes).unwrap();
+        assert!(bare.signature_data.qe_report_data().is_none());
+        assert_eq!(bare.pck_cert_chain(), Some(&pem[..]));
+
+        // an unknown type is kept as is but carries no chain
+        let bytes = with_certification_data(&quote, &certification_data(99, &pem));
+        let unknown = Quote::from_bytes(&bytes).unwrap();
+        assert_eq!(unknown.signature_data.certification_data.cert_type, 99);
+        assert!(unknown.signature_data.qe_report_data().is_none());
+        assert_eq!(unknown.pck_cert_chain(), None);
+
+        // a QE report wrapping another QE report
+        let mut qe = qe_report_data(&quote);
+        let nested_at = qe.len() - pem.len() - 6;
+        qe[nested_at..nested_at + 2].copy_from_slice(&CERT_DATA_TYPE_QE_REPORT.to_le_bytes());
+        let bytes =
+            with_certification_data(&quote, &certification_data(CERT_DATA_TYPE_QE_REPORT, &qe));
+        assert!(parse_error(&bytes).contains("QE report certification data nested in another"));
+    }
+
+    #[test]
+    fn qe_report_wrong_length() {
+        let quote = Quote::from_bytes(&simulated_quote(TdReportVersion::TDX15)).unwrap();
+        let qe = qe_report_data(&quote);
+        let rebuilt =
+            with_certification_data(&quote, &certification_data(CERT_DATA_TYPE_QE_REPORT, &qe));
+        assert_eq!(rebuilt, quote.as_bytes());
+
+        // shorter than the enclave report itself
+        let bytes = with_certification_data(
+            &quote,
+            &certification_data(CERT_DATA_TYPE_QE_REPORT, &qe[..ENCLAVE_REPORT_LEN - 1]),
+        );
+        assert!(parse_error(&bytes).contains("Truncated quote reading qe_report "));
+
+        // the nested chain cut short
+        let bytes = with_certification_data(
+            &quote,
+            &certification_data(CERT_DATA_TYPE_QE_REPORT, &qe[..qe.len() - 1]),
+        );
+        assert!(parse_error(&bytes).contains("Truncated quote reading certification_data.data"));
+
+        // an auth data size running past the end
+        let mut long_auth = qe.clone();
+        let auth_size = ENCLAVE_REPORT_LEN + 64;
+        long_auth[auth_size..auth_size + 2].copy_from_slice(&0xffffu16.to_le_bytes());
+        let bytes = with_certification_data(
+            &quote,
+            &certification_data(CERT_DATA_TYPE_QE_REPORT, &long_auth),
+        );
+        assert!(parse_error(&bytes).contains("Truncated quote reading qe_auth_data.data"));
+
+        // bytes left over after the nested chain
+        let mut padded = qe;
+        padded.extend([0; 16]);
+        let bytes = with_certification_data(
+            &quote,
+            &certification_data(CERT_DATA_TYPE_QE_REPORT, &padded),
+        );
+        assert!(parse_error(&bytes).contains("leaves 16 bytes unused"));
+    }
+}
@@ -0,0 +1,391 @@
+// Expected RTMR1 and RTMR2 of a Linux TD booted through TDVF, from the kernel, initrd and
+// command line, for reference values next to the MRTD of the firmware image.
+//
+// RTMR1 holds what TDVF measures into PCR 4 and 5: the boot option action, the separator,
+// every EFI application it loads and the ExitBootServices actions. RTMR2 holds PCR 8 and 9:
+// grub's commands, files and kernel command line, and the events of the kernel's EFI stub.
+use crate::authenticode::authenticode_sha384;
+use crate::event_decode::{diff_entries, RtmrDiff};
+use crate::eventlog::*;
+use crate::evidence::hex_bytes;
+use anyhow::*;
+use serde::Serialize;
+use sha2::{Digest, Sha384};
+use std::result::Result;
+use std::result::Result::Ok;
+
+const RTMR1_MR_INDEX: u32 = 2;
+const RTMR2_MR_INDEX: u32 = 3;
+
+const CALLING_EFI_APPLICATION: &str = "Calling EFI Application from Boot Option";
+const EXIT_BOOT_SERVICES_INVOCATION: &str = "Exit Boot Services Invocation";
+const EXIT_BOOT_SERVICES_SUCCEEDED: &str = "Exit Boot Services Returned with Success";
+
+// tagged events of the Linux EFI stub, see drivers/firmware/efi/libstub/efi-stub-helper.c
+const INITRD_EVENT_TAG_ID: u32 = 0x8f3b22ec;
+const LOAD_OPTIONS_EVENT_TAG_ID: u32 = 0x8f3b22ed;
+const INITRD_EVENT_DESCRIPTION: &str = "Linux initrd";
+const LOAD_OPTIONS_EVENT_DESCRIPTION: &str = "LOADED_IMAGE::LoadOptions";
+
+/// Paths grub loads the kernel and initrd from, as written in its `linux` and `initrd` commands.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrubPaths {
+    pub kernel: String,
+    pub initrd: Option<String>,
+}
+
+/// A Linux boot through TDVF. Without grub the kernel is booted directly, as with QEMU's
+/// -kernel, -initrd and -append, and TDVF appends " initrd=initrd" to the command line when
+/// there is an initrd. With grub, grub 2.12 or later is assumed: it measures its `linux` and
+/// `initrd` commands, and hands the initrd to the EFI stub through LoadFile2.
+///
+/// The kernel must be the image exactly as the firmware loads it; a VMM that patches the setup
+/// header of the bzImage changes its Authenticode digest.
+#[derive(Clone, Debug)]
+pub struct LinuxBoot {
+    kernel: Vec<u8>,
+    initrd: Option<Vec<u8>>,
+    cmdline: String,
+    boot_loaders: Vec<Vec<u8>>,
+    grub: Option<GrubPaths>,
+}
+
+/// Expected RTMR1 and RTMR2 with the events extending them, in log order.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ExpectedRtmrs {
+    #[serde(with = "hex_bytes")]
+    pub rtmr1: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub rtmr2: [u8; 48],
+    pub events: Vec<EventLogEntry>,
+}
+
+impl ExpectedRtmrs {
+    /// Compare against the RTMRs of a TD report or quote, RTMR1 and RTMR2 only.
+    pub fn diff(&self, reported: &[[u8; 48]; 4]) -> Vec<RtmrDiff> {
+        diff_entries(&self.events, reported)
+            .into_iter()
+            .filter(|d| d.index == 1 || d.index == 2)
+            .collect()
+    }
+}
+
+fn sha384_event(mr_index: u32, event_type: u32, measured: &[u8], event: Vec<u8>) -> EventLogEntry {
+    EventLogEntry {
+        mr_index,
+        event_type,
+        digests: vec![(TPM_ALG_SHA384, Sha384::digest(measured).to_vec())],
+        event,
+    }
+}
+
+fn efi_action(action: &str) -> EventLogEntry {
+    sha384_event(
+        RTMR1_MR_INDEX,
+        EV_EFI_ACTION,
+        action.as_bytes(),
+        action.as_bytes().to_vec(),
+    )
+}
+
+// the UEFI_IMAGE_LOAD_EVENT depends on where the image was loaded and is left empty
+fn image_load(image: &[u8]) -> Result<EventLogEntry, anyhow::Error> {
+    Ok(EventLogEntry {
+        mr_index: RTMR1_MR_INDEX,
+        event_type: EV_EFI_BOOT_SERVICES_APPLICATION,
+        digests: vec![(TPM_ALG_SHA384, authenticode_sha384(image)?.to_vec())],
+        event: Vec::new(),
+    })
+}
+
+// grub measures the string or file contents, the event is its description with a NUL
+fn grub_event(measured: &[u8], description: &str) -> EventLogEntry {
+    let mut event = description.as_bytes().to_vec();
+    event.push(0);
+    sha384_event(RTMR2_MR_INDEX, EV_IPL, measured, event)
+}
+
+// TCG_PCClientTaggedEvent: tag id, size and the NUL terminated description
+fn stub_event(tag: u32, description: &str, measured: &[u8]) -> EventLogEntry {
+    let mut tagged = description.as_bytes().to_vec();
+    tagged.push(0);
+    let mut event = tag.to_le_bytes().to_vec();
+    event.extend((tagged.len() as u32).to_le_bytes());
+    event.extend(tagged);
+    sha384_event(RTMR2_MR_INDEX, EV_EVENT_TAG, measured, event)
+}
+
+fn utf16_with_nul(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .chain(Some(0))
+        .flat_map(|c| c.to_le_bytes())
+        .collect()
+}
+
+impl LinuxBoot {
+    pub fn new(kernel: Vec<u8>) -> Self {
+        LinuxBoot {
+            kernel,
+            initrd: None,
+            cmdline: String::new(),
+            boot_loaders: Vec::new(),
+            grub: None,
+        }
+    }
+
+    pub fn initrd(mut self, initrd: Vec<u8>) -> Self {
+        self.initrd = Some(initrd);
+        self
+    }
+
+    pub fn cmdline(mut self, cmdline: &str) -> Self {
+        self.cmdline = cmdline.to_string();
+        self
+    }
+
+    /// An EFI application loaded before the kernel, such as shim or grub, in load order.
+    pub fn boot_loader(mut self, image: Vec<u8>) -> Self {
+        self.boot_loaders.push(image);
+        self
+    }
+
+    pub fn grub(mut self, paths: GrubPaths) -> Self {
+        self.grub = Some(paths);
+        self
+    }
+
+    // the command line the EFI stub finds in its LoadOptions
+    fn load_options(&self) -> String {
+        match &self.grub {
+            Some(grub) if self.cmdline.is_empty() => format!("BOOT_IMAGE={}", grub.kernel),
+            Some(grub) => format!("BOOT_IMAGE={} {}", grub.kernel, self.cmdline),
+            None if self.initrd.is_some() => format!("{} initrd=initrd", self.cmdline),
+            None => self.cmdline.clone(),
+        }
+    }
+
+    fn grub_events(&self, grub: &GrubPaths) -> Vec<EventLogEntry> {
+        let linux = if self.cmdline.is_empty() {
+            format!("linux {}", grub.kernel)
+        } else {
+            format!("linux {} {}", grub.kernel, self.cmdline)
+        };
+        let kernel_cmdline = self.load_options();
+        let mut events = vec![
+            grub_event(linux.as_bytes(), &format!("grub_cmd: {}", linux)),
+            grub_event(&self.kernel, &grub.kernel),
+            grub_event(
+                kernel_cmdline.as_bytes(),
+                &format!("kernel_cmdline: {}", kernel_cmdline),
+            ),
+        ];
+        if let (Some(path), Some(initrd)) = (&grub.initrd, &self.initrd) {
+            let command = format!("initrd {}", path);
+            events.push(grub_event(
+                command.as_bytes(),
+                &format!("grub_cmd: {}", command),
+            ));
+            events.push(grub_event(initrd, path));
+        }
+        events
+    }
+
+    /// The RTMR1 and RTMR2 events of this boot, in the order they are logged.
+    pub fn expected_events(&self) -> Result<Vec<EventLogEntry>, anyhow::Error> {
+        if let Some(grub) = &self.grub {
+            if grub.initrd.is_some() != self.initrd.is_some() {
+                return Err(anyhow!(
+                    "[LinuxBoot] With grub the initrd needs both its path and its image"
+                ));
+            }
+        }
+
+        let mut events = vec![
+            efi_action(CALLING_EFI_APPLICATION),
+            sha384_event(
+                RTMR1_MR_INDEX,
+                EV_SEPARATOR,
+                &0u32.to_le_bytes(),
+                0u32.to_le_bytes().to_vec(),
+            ),
+        ];
+        for image in &self.boot_loaders {
+            events.push(image_load(image)?);
+        }
+        events.push(image_load(&self.kernel)?);
+
+        if let Some(grub) = &self.grub {
+            events.extend(self.grub_events(grub));
+        }
+        let load_options = self.load_options();
+        if !load_options.is_empty() {
+            events.push(stub_event(
+                LOAD_OPTIONS_EVENT_TAG_ID,
+                LOAD_OPTIONS_EVENT_DESCRIPTION,
+                &utf16_with_nul(&load_options),
+            ));
+        }
+        if let Some(initrd) = &self.initrd {
+            events.push(stub_event(
+                INITRD_EVENT_TAG_ID,
+                INITRD_EVENT_DESCRIPTION,
+                initrd,
+            ));
+        }
+
+        events.push(efi_action(EXIT_BOOT_SERVICES_INVOCATION));
+        events.push(efi_action(EXIT_BOOT_SERVICES_SUCCEEDED));
+        Ok(events)
+    }
+
+    pub fn expected_rtmrs(&self) -> Result<ExpectedRtmrs, anyhow::Error> {
+        let events = self.expected_events()?;
+        let mut rtmrs = [[0u8; 48]; 4];
+        for entry in &events {
+            let index = entry.mr_index as usize - 1;
+            let mut hasher = Sha384::new();
+            hasher.update(rtmrs[index]);
+            hasher.update(&entry.digests[0].1);
+            rtmrs[index] = hasher.finalize().into();
+        }
+        Ok(ExpectedRtmrs {
+            rtmr1: rtmrs[1],
+            rtmr2: rtmrs[2],
+            events,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    const KERNEL: &str = "tests/data/pe32plus.efi";
+    const INITRD: &[u8] = b"initrd image";
+    // RTMR1 and RTMR2 computed independently with Python's hashlib from the event digests
+    const DIRECT_RTMRS: [&str; 2] = [
+        "f339c5216c5b24fb928dc49c5815c55d51d00ffa8b4111171f35a80a27a37a74da4b0e3abb283192a2f6c27716a8326a",
+        "5ddc016e8e1122963da3e2910072628793f49a8780b0af809aaa16c405015f1e50d535f069f8f8be1009b02e9331732b",
+    ];
+    const GRUB_RTMRS: [&str; 2] = [
+        "224a438cf14802f69309b5b15395e43db38ffe0097fe6a3dd25f04163c1c3d4037bb7b12504dfd9b968b2eb2527277d8",
+        "ab03ec8659c16681241ce97dc1b0c432bf16fa9e7b3f64adb22b5ec2dfb7618080ce9c08141a6b271e7a0200a29a1e97",
+    ];
+
+    fn kernel() -> Vec<u8> {
+        fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join(KERNEL)).unwrap()
+    }
+
+    fn grub_boot() -> LinuxBoot {
+        LinuxBoot::new(kernel())
+            .initrd(INITRD.to_vec())
+            .cmdline("root=/dev/vda1")
+            .boot_loader(kernel())
+            .grub(GrubPaths {
+                kernel: "/vmlinuz".to_string(),
+                initrd: Some("/initrd.img".to_string()),
+            })
+    }
+
+    // the events as a TCG2 log with a SHA384-only Spec ID event, to replay them through EventLog
+    fn encode_log(events: &[EventLogEntry]) -> Vec<u8> {
+        let mut spec_id = b"Spec ID Event03\0".to_vec();
+        spec_id.extend(0u32.to_le_bytes());
+        spec_id.extend([0, 2, 0, 2]);
+        spec_id.extend(1u32.to_le_bytes());
+        spec_id.extend(TPM_ALG_SHA384.to_le_bytes());
+        spec_id.extend(48u16.to_le_bytes());
+        spec_id.push(0);
+
+        let mut log = Vec::new();
+        log.extend(0u32.to_le_bytes());
+        log.extend(EV_NO_ACTION.to_le_bytes());
+        log.extend([0; 20]);
+        log.extend((spec_id.len() as u32).to_le_bytes());
+        log.extend(spec_id);
+        for entry in events {
+            log.extend(entry.mr_index.to_le_bytes());
+            log.extend(entry.event_type.to_le_bytes());
+            log.extend((entry.digests.len() as u32).to_le_bytes());
+            for (alg, digest) in &entry.digests {
+                log.extend(alg.to_le_bytes());
+                log.extend(digest);
+            }
+            log.extend((entry.event.len() as u32).to_le_bytes());
+            log.extend(&entry.event);
+        }
+        log
+    }
+
+    fn check_replay(boot: &LinuxBoot, known: [&str; 2]) {
+        let expected = boot.expected_rtmrs().unwrap();
+        assert_eq!(hex::encode(expected.rtmr1), known[0]);
+        assert_eq!(hex::encode(expected.rtmr2), known[1]);
+
+        let log = EventLog::from_bytes(&encode_log(&expected.events)).unwrap();
+        assert_eq!(log.entries, expected.events);
+        let rtmrs = log.replay_rtmrs().unwrap();
+        assert_eq!(rtmrs[0], [0; 48]);
+        assert_eq!(rtmrs[1], expected.rtmr1);
+        assert_eq!(rtmrs[2], expected.rtmr2);
+        assert!(expected.diff(&rtmrs).iter().all(|d| d.matches()));
+    }
+
+    #[test]
+    fn direct_boot() {
+        let boot = LinuxBoot::new(kernel())
+            .initrd(INITRD.to_vec())
+            .cmdline("console=hvc0");
+        check_replay(&boot, DIRECT_RTMRS);
+    }
+
+    #[test]
+    fn grub_boot_replay() {
+        let boot = grub_boot();
+        let events = boot.expected_events().unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| e.mr_index == RTMR2_MR_INDEX)
+                .map(|e| e.decode().to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "\"grub_cmd: linux /vmlinuz root=/dev/vda1\"",
+                "\"/vmlinuz\"",
+                "\"kernel_cmdline: BOOT_IMAGE=/vmlinuz root=/dev/vda1\"",
+                "\"grub_cmd: initrd /initrd.img\"",
+                "\"/initrd.img\"",
+                "tag 0x8f3b22ed (26 bytes)",
+                "tag 0x8f3b22ec (13 bytes)",
+            ]
+        );
+        check_replay(&boot, GRUB_RTMRS);
+    }
+
+    #[test]
+    fn mismatch_is_reported() {
+        let expected = grub_boot().expected_rtmrs().unwrap();
+        let mut reported = [[0u8; 48]; 4];
+        reported[1] = expected.rtmr1;
+        let diffs = expected.diff(&reported);
+        assert_eq!(diffs.len(), 2);
+        assert!(diffs[0].matches());
+        assert!(!diffs[1].matches());
+        assert_eq!(diffs[1].events.len(), 7);
+    }
+
+    #[test]
+    fn grub_initrd_needs_path_and_image() {
+        let boot = LinuxBoot::new(kernel()).grub(GrubPaths {
+            kernel: "/vmlinuz".to_string(),
+            initrd: Some("/initrd.img".to_string()),
+        });
+        assert!(boot
+            .expected_rtmrs()
+            .unwrap_err()
+            .to_string()
+            .contains("needs both its path and its image"));
+    }
+}
@@ -0,0 +1,564 @@
+use crate::collateral::*;
+use crate::quote::*;
+use crate::tdreport::*;
+use crate::tee_tdx_lib::*;
+use crate::verify::{subject_name, verifying_key};
+use anyhow::*;
+use der::oid::{AssociatedOid, ObjectIdentifier};
+use der::{Length, Writer};
+use p256::ecdsa::{signature::Signer, DerSignature, Signature, SigningKey};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256, Sha384};
+use std::result::Result;
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use x509_cert::builder::{Builder, CertificateBuilder, Profile};
+use x509_cert::der::{pem::LineEnding, EncodePem};
+use x509_cert::ext::{AsExtension, Extension};
+use x509_cert::name::Name;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::SubjectPublicKeyInfoOwned;
+use x509_cert::time::Validity;
+use x509_cert::Certificate;
+
+const SIMULATED_CERT_VALIDITY_SECS: u64 = 365 * 24 * 3600;
+const TD_QE_ISV_PROD_ID: u16 = 2;
+const QE_REPORT_CPUSVN_OFFSET: usize = 0;
+const QE_REPORT_ATTRIBUTES_OFFSET: usize = 48;
+const QE_REPORT_MRSIGNER_OFFSET: usize = 128;
+const QE_REPORT_ISV_PROD_ID_OFFSET: usize = 256;
+const QE_REPORT_ISV_SVN_OFFSET: usize = 258;
+const QE_REPORT_REPORT_DATA_OFFSET: usize = 320;
+
+/// Identity and measurements of the simulated TD and of the platform it pretends to run on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulatedTdConfig {
+    pub version: TdReportVersion,
+    pub attributes: [u8; 8],
+    pub xfam: [u8; 8],
+    pub mrtd: [u8; 48],
+    pub mrconfigid: [u8; 48],
+    pub mrowner: [u8; 48],
+    pub mrownerconfig: [u8; 48],
+    pub rtmrs: [[u8; 48]; 4], // initial values, extend_rtmr updates them
+    pub servtd_hash: [u8; 48],
+    pub tee_tcb_svn: [u8; 16],
+    pub tee_tcb_svn2: [u8; 16],
+    pub mrseam: [u8; 48],
+    pub mrsignerseam: [u8; 48],
+    pub qe_svn: u16,
+    pub qe_mrsigner: [u8; 32],
+    pub pck: PckExtensions,
+}
+
+impl Default for SimulatedTdConfig {
+    fn default() -> Self {
+        SimulatedTdConfig {
+            version: TdReportVersion::TDX15,
+            attributes: [0; 8],
+            xfam: [0xe7, 0x02, 0x06, 0, 0, 0, 0, 0],
+            mrtd: [0; 48],
+            mrconfigid: [0; 48],
+            mrowner: [0; 48],
+            mrownerconfig: [0; 48],
+            rtmrs: [[0; 48]; 4],
+            servtd_hash: [0; 48],
+            tee_tcb_svn: [0; 16],
+            tee_tcb_svn2: [0; 16],
+            mrseam: [0; 48],
+            mrsignerseam: [0; 48],
+            qe_svn: 0,
+            qe_mrsigner: [0; 32],
+            pck: PckExtensions {
+                sgx_tcb_components: [0; 16],
+                pce_svn: 0,
+                cpusvn: [0; 16],
+                pce_id: [0; 2],
+                fmspc: [0; 6],
+            },
+        }
+    }
+}
+
+// the SGX extension of the simulated PCK leaf, already DER encoded
+struct PckSgxExtension(Vec<u8>);
+
+impl AssociatedOid for PckSgxExtension {
+    const OID: ObjectIdentifier = SGX_EXTENSIONS_OID;
+}
+
+impl der::Encode for PckSgxExtension {
+    fn encoded_len(&self) -> der::Result<Length> {
+        Length::try_from(self.0.len())
+    }
+
+    fn encode(&self, writer: &mut impl Writer) -> der::Result<()> {
+        writer.write(&self.0)
+    }
+}
+
+impl AsExtension for PckSgxExtension {
+    fn critical(&self, _subject: &Name, _extensions: &[Extension]) -> bool {
+        false
+    }
+}
+
+fn build_certificate(
+    profile: Profile,
+    serial: u32,
+    subject: &str,
+    key: &SigningKey,
+    issuer_key: &SigningKey,
+    extension: Option<&PckSgxExtension>,
+) -> Result<Certificate, anyhow::Error> {
+    let spki = match SubjectPublicKeyInfoOwned::from_key(*key.verifying_key()) {
+        Err(e) => return Err(anyhow!("[SimulatedBackend] Bad public key: {:?}", e)),
+        Ok(s) => s,
+    };
+    let validity = match Validity::from_now(Duration::from_secs(SIMULATED_CERT_VALIDITY_SECS)) {
+        Err(e) => return Err(anyhow!("[SimulatedBackend] Bad validity: {:?}", e)),
+        Ok(v) => v,
+    };
+    let name = match Name::from_str(subject) {
+        Err(e) => return Err(anyhow!("[SimulatedBackend] Bad name {}: {:?}", subject, e)),
+        Ok(n) => n,
+    };
+    let mut builder = match CertificateBuilder::new(
+        profile,
+        SerialNumber::from(serial),
+        validity,
+        name,
+        spki,
+        issuer_key,
+    ) {
+        Err(e) => {
+            return Err(anyhow!(
+                "[SimulatedBackend] Fail to build {}: {:?}",
+                subject,
+                e
+            ))
+        }
+        Ok(b) => b,
+    };
+    if let Some(extension) = extension {
+        if let Err(e) = builder.add_extension(extension) {
+            return Err(anyhow!("[SimulatedBackend] Fail to add extension: {:?}", e));
+        }
+    }
+    match builder.build::<DerSignature>() {
+        Err(e) => Err(anyhow!(
+            "[SimulatedBackend] Fail to sign {}: {:?}",
+            subject,
+            e
+        )),
+        Ok(c) => Ok(c),
+    }
+}
+
+/// A software TD producing TDREPORTs and quotes that verify against `root_certificate()`.
+pub struct SimulatedBackend {
+    config: SimulatedTdConfig,
+    rtmrs: Mutex<[[u8; 48]; 4]>,
+    mac_key: [u8; 32],
+    pck_key: SigningKey,
+    attestation_key: SigningKey,
+    pck_chain: Vec<Certificate>, // PCK leaf first, root CA last
+}
+
+/// Keys and certificates of a simulated PCK hierarchy. The root and CA keys can sign CRLs
+/// for the chain.
+pub struct SimulatedPki {
+    pub root_key: SigningKey,
+    pub ca_key: SigningKey,
+    pub pck_key: SigningKey,
+    pub chain: Vec<Certificate>, // PCK leaf, PCK platform CA, root CA
+}
+
+impl SimulatedPki {
+    // fresh keys, the PCK leaf carrying the SGX extensions of config
+    pub fn new(config: &SimulatedTdConfig) -> Result<Self, anyhow::Error> {
+        let root_key = SigningKey::random(&mut OsRng);
+        let ca_key = SigningKey::random(&mut OsRng);
+        let pck_key = SigningKey::random(&mut OsRng);
+
+        let root = build_certificate(
+            Profile::Root,
+            1,
+            "CN=Simulated SGX Root CA,O=Simulated",
+            &root_key,
+            &root_key,
+            None,
+        )?;
+        let ca = build_certificate(
+            Profile::SubCA {
+                issuer: root.tbs_certificate.subject.clone(),
+                path_len_constraint: Some(0),
+            },
+            2,
+            "CN=Simulated SGX PCK Platform CA,O=Simulated",
+            &ca_key,
+            &root_key,
+            None,
+        )?;
+        let pck = build_certificate(
+            Profile::Leaf {
+                issuer: ca.tbs_certificate.subject.clone(),
+                enable_key_agreement: false,
+                enable_key_encipherment: false,
+            },
+            3,
+            "CN=Simulated SGX PCK Certificate,O=Simulated",
+            &pck_key,
+            &ca_key,
+            Some(&PckSgxExtension(config.pck.to_der()?)),
+        )?;
+
+        Ok(SimulatedPki {
+            root_key,
+            ca_key,
+            pck_key,
+            chain: vec![pck, ca, root],
+        })
+    }
+
+    pub fn root_certificate(&self) -> &Certificate {
+        self.chain.last().unwrap()
+    }
+}
+
+// a fresh PCK key with its chain: PCK leaf carrying the SGX extensions of config,
+// PCK platform CA and root CA
+pub fn simulated_pck_chain(
+    config: &SimulatedTdConfig,
+) -> Result<(SigningKey, Vec<Certificate>), anyhow::Error> {
+    let pki = SimulatedPki::new(config)?;
+    Ok((pki.pck_key, pki.chain))
+}
+
+impl SimulatedBackend {
+    pub fn new(config: SimulatedTdConfig) -> Result<Self, anyhow::Error> {
+        let (pck_key, pck_chain) = simulated_pck_chain(&config)?;
+        SimulatedBackend::with_keys(config, SigningKey::random(&mut OsRng), pck_key, pck_chain)
+    }
+
+    // a fixed attestation key, and a PCK key with its chain (leaf first, root last) instead of fresh ones
+    pub fn with_keys(
+        config: SimulatedTdConfig,
+        attestation_key: SigningKey,
+        pck_key: SigningKey,
+        pck_chain: Vec<Certificate>,
+    ) -> Result<Self, anyhow::Error> {
+        match pck_chain.first() {
+            None => return Err(anyhow!("[SimulatedBackend] Empty PCK chain")),
+            Some(leaf) if verifying_key(leaf)? != *pck_key.verifying_key() => {
+                return Err(anyhow!(
+                    "[SimulatedBackend] PCK key does not match {}",
+                    subject_name(leaf)
+                ))
+            }
+            Some(_) => (),
+        }
+
+        let mut mac_key = [0; 32];
+        OsRng.fill_bytes(&mut mac_key);
+        Ok(SimulatedBackend {
+            rtmrs: Mutex::new(config.rtmrs),
+            config,
+            mac_key,
+            pck_key,
+            attestation_key,
+            pck_chain,
+        })
+    }
+
+    pub fn config(&self) -> &SimulatedTdConfig {
+        &self.config
+    }
+
+    // trust anchor for verify_quote
+    pub fn root_certificate(&self) -> &Certificate {
+        self.pck_chain.last().unwrap()
+    }
+
+    pub fn pck_chain(&self) -> &[Certificate] {
+        &self.pck_chain
+    }
+
+    pub fn rtmrs(&self) -> [[u8; 48]; 4] {
+        *self.rtmrs.lock().unwrap()
+    }
+
+    fn mac(&self, report_mac_struct: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.mac_key);
+        hasher.update(report_mac_struct);
+        hasher.finalize().into()
+    }
+
+    pub fn build_report(&self, report_data: &ReportData) -> Vec<u8> {
+        let c = &self.config;
+        let td15 = c.version == TdReportVersion::TDX15;
+        let mut report = vec![0; TDX_REPORT_LEN as usize];
+
+        //TEE_TCB_INFO
+        let tcb_info = &mut report[256..512];
+        tcb_info[0..8].fill(0xff);
+        tcb_info[8..24].copy_from_slice(&c.tee_tcb_svn);
+        tcb_info[24..72].copy_from_slice(&c.mrseam);
+        tcb_info[72..120].copy_from_slice(&c.mrsignerseam);
+        if td15 {
+            tcb_info[128..144].copy_from_slice(&c.tee_tcb_svn2);
+        }
+
+        //TDINFO
+        let td_info = &mut report[512..1024];
+        td_info[0..8].copy_from_slice(&c.attributes);
+        td_info[8..16].copy_from_slice(&c.xfam);
+        td_info[16..64].copy_from_slice(&c.mrtd);
+        td_info[64..112].copy_from_slice(&c.mrconfigid);
+        td_info[112..160].copy_from_slice(&c.mrowner);
+        td_info[160..208].copy_from_slice(&c.mrownerconfig);
+        for (i, rtmr) in self.rtmrs().iter().enumerate() {
+            td_info[208 + i * 48..256 + i * 48].copy_from_slice(rtmr);
+        }
+        if td15 {
+            td_info[400..448].copy_from_slice(&c.servtd_hash);
+        }
+
+        //REPORTMACSTRUCT
+        let tee_tcb_info_hash = Sha384::digest(&report[256..512]);
+        let tee_info_hash = Sha384::digest(&report[512..1024]);
+        report[0] = REPORT_TYPE_TDX;
+        report[2] = if td15 { 1 } else { 0 };
+        report[16..32].copy_from_slice(&c.pck.cpusvn);
+        report[32..80].copy_from_slice(&tee_tcb_info_hash);
+        report[80..128].copy_from_slice(&tee_info_hash);
+        report[128..192].copy_from_slice(report_data.as_bytes());
+        let mac = self.mac(&report[0..224]);
+        report[224..256].copy_from_slice(&mac);
+        report
+    }
+
+    // the MAC is keyed per backend instance, so only reports from this backend pass
+    pub fn verify_report_mac(&self, report: &[u8]) -> bool {
+        report.len() == TDX_REPORT_LEN as usize && self.mac(&report[0..224]) == report[224..256]
+    }
+
+    // what QGS would return for a TDREPORT of this backend
+    pub fn quote_for_report(&self, report: &[u8]) -> Result<Vec<u8>, TdxError> {
+        if report.len() == TDX_REPORT_LEN as usize && !self.verify_report_mac(report) {
+            return Err(TdxError::InvalidTdReport(
+                "MAC does not match this simulated TD".to_string(),
+            ));
+        }
+        self.quote_for_unverified_report(report)
+    }
+
+    // quote any well-formed TDREPORT, e.g. one from a simulated TD in another process
+    pub fn quote_for_unverified_report(&self, report: &[u8]) -> Result<Vec<u8>, TdxError> {
+        if report.len() != TDX_REPORT_LEN as usize {
+            return Err(TdxError::SizeMismatch {
+                what: "TDX report",
+                expected: TDX_REPORT_LEN as usize,
+                actual: report.len(),
+            });
+        }
+        let td_report = match TdReport::from_bytes(report) {
+            Err(e) => return Err(TdxError::InvalidTdReport(e.to_string())),
+            Ok(r) => r,
+        };
+        let td15 = td_report.version() == TdReportVersion::TDX15;
+        let tcb = &td_report.tee_tcb_info;
+        let td = &td_report.td_info;
+
+        //header
+        let mut quote = Vec::new();
+        quote.extend(
+            if td15 {
+                QUOTE_VERSION_5
+            } else {
+                QUOTE_VERSION_4
+            }
+            .to_le_bytes(),
+        );
+        quote.extend(ATT_KEY_TYPE_ECDSA_P256.to_le_bytes());
+        quote.extend(TEE_TYPE_TDX.to_le_bytes());
+        quote.extend(self.config.qe_svn.to_le_bytes());
+        quote.extend(self.config.pck.pce_svn.to_le_bytes());
+        quote.extend(INTEL_QE_VENDOR_ID);
+        quote.extend([0; 20]);
+
+        //body
+        let mut body = Vec::new();
+        body.extend(tcb.tee_tcb_svn);
+        body.extend(tcb.mrseam);
+        body.extend(tcb.mrsignerseam);
+        body.extend(tcb.attributes);
+        body.extend(td.attributes);
+        body.extend(td.xfam);
+        body.extend(td.mrtd);
+        body.extend(td.mrconfigid);
+        body.extend(td.mrowner);
+        body.extend(td.mrownerconfig);
+        for rtmr in &td.rtmrs {
+            body.extend(rtmr);
+        }
+        body.extend(td_report.report_data());
+        if td15 {
+            body.extend(tcb.tee_tcb_svn2);
+            body.extend(td.servtd_hash);
+            quote.extend(BODY_TYPE_TD15.to_le_bytes());
+            quote.extend((body.len() as u32).to_le_bytes());
+        }
+        quote.extend(&body);
+
+        //QE report binding the attestation key, signed by the PCK key
+        let attestation_key = self.attestation_key.verifying_key().to_encoded_point(false);
+        let attestation_key = &attestation_key.as_bytes()[1..];
+        let qe_auth_data = [0u8; 32];
+        let mut qe_report = vec![0u8; 384];
+        qe_report[QE_REPORT_CPUSVN_OFFSET..QE_REPORT_CPUSVN_OFFSET + 16]
+            .copy_from_slice(&self.config.pck.cpusvn);
+        qe_report[QE_REPORT_ATTRIBUTES_OFFSET] = 0x11;
+        qe_report[QE_REPORT_MRSIGNER_OFFSET..QE_REPORT_MRSIGNER_OFFSET + 32]
+            .copy_from_slice(&self.config.qe_mrsigner);
+        qe_report[QE_REPORT_ISV_PROD_ID_OFFSET..QE_REPORT_ISV_PROD_ID_OFFSET + 2]
+            .copy_from_slice(&TD_QE_ISV_PROD_ID.to_le_bytes());
+        qe_report[QE_REPORT_ISV_SVN_OFFSET..QE_REPORT_ISV_SVN_OFFSET + 2]
+            .copy_from_slice(&self.config.qe_svn.to_le_bytes());
+        let binding = Sha256::new()
+            .chain_update(attestation_key)
+            .chain_update(qe_auth_data)
+            .finalize();
+        qe_report[QE_REPORT_REPORT_DATA_OFFSET..QE_REPORT_REPORT_DATA_OFFSET + 32]
+            .copy_from_slice(&binding);
+        let qe_report_signature: Signature = self.pck_key.sign(&qe_report);
+
+        let mut pem = String::new();
+        for cert in &self.pck_chain {
+            match cert.to_pem(LineEnding::LF) {
+                Err(e) => return Err(TdxError::InvalidTdReport(format!("{:?}", e))),
+                Ok(p) => pem.push_str(&p),
+            }
+        }
+        let mut pem = pem.into_bytes();
+        pem.push(0);
+
+        let mut qe_certification_data = Vec::new();
+        qe_certification_data.extend(&qe_report);
+        qe_certification_data.extend(qe_report_signature.to_bytes());
+        qe_certification_data.extend((qe_auth_data.len() as u16).to_le_bytes());
+        qe_certification_data.extend(qe_auth_data);
+        qe_certification_data.extend(CERT_DATA_TYPE_PCK_CERT_CHAIN.to_le_bytes());
+        qe_certification_data.extend((pem.len() as u32).to_le_bytes());
+        qe_certification_data.extend(&pem);
+
+        //signature over header and body by the attestation key
+        let quote_signature: Signature = self.attestation_key.sign(&quote);
+        let mut signature_data = Vec::new();
+        signature_data.extend(quote_signature.to_bytes());
+        signature_data.extend(attestation_key);
+        signature_data.extend(CERT_DATA_TYPE_QE_REPORT.to_le_bytes());
+        signature_data.extend((qe_certification_data.len() as u32).to_le_bytes());
+        signature_data.extend(&qe_certification_data);
+
+        quote.extend((signature_data.len() as u32).to_le_bytes());
+        quote.extend(&signature_data);
+        Ok(quote)
+    }
+}
+
+impl TdxBackend for SimulatedBackend {
+    fn name(&self) -> &str {
+        "simulated"
+    }
+
+    fn get_report(&self, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+        Ok(self.build_report(report_data))
+    }
+
+    fn get_quote(
+        &self,
+        report_data: &ReportData,
+        _options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError> {
+        self.quote_for_report(&self.build_report(report_data))
+    }
+
+    fn extend_rtmr(
+        &self,
+        index: u8,
+        digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+    ) -> Result<(), RtmrError> {
+        let mut rtmrs = self.rtmrs.lock().unwrap();
+        let rtmr = match rtmrs.get_mut(index as usize) {
+            None => return Err(RtmrError::InvalidIndex(index)),
+            Some(r) => r,
+        };
+        *rtmr = Sha384::new()
+            .chain_update(*rtmr)
+            .chain_update(digest)
+            .finalize()
+            .into();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::verify_quote_at;
+    use std::time::SystemTime;
+
+    #[test]
+    fn report_quote_verify_round_trip() {
+        let config = SimulatedTdConfig::default();
+        let (pck_key, pck_chain) = simulated_pck_chain(&config).unwrap();
+        let root = pck_chain.last().unwrap().clone();
+        let backend = SimulatedBackend::with_keys(
+            config.clone(),
+            SigningKey::random(&mut OsRng),
+            pck_key,
+            pck_chain,
+        )
+        .unwrap();
+        backend
+            .extend_rtmr(2, [1; TDX_EXTEND_RTMR_DATA_LEN])
+            .unwrap();
+
+        let report = backend.get_report(&ReportData([9; 64])).unwrap();
+        assert!(backend.verify_report_mac(&report));
+        let quote = Quote::from_bytes(&backend.quote_for_report(&report).unwrap()).unwrap();
+        assert_eq!(quote.body.report_data, [9; 64]);
+        assert_eq!(quote.body.mrtd, config.mrtd);
+        assert_eq!(quote.body.rtmrs, backend.rtmrs());
+        assert_ne!(quote.body.rtmrs[2], config.rtmrs[2]);
+
+        let verdict = verify_quote_at(&quote, &root, SystemTime::now());
+        assert!(verdict.is_valid(), "{:?}", verdict.failures());
+    }
+
+    #[test]
+    fn rejects_foreign_report() {
+        let backend = SimulatedBackend::new(SimulatedTdConfig::default()).unwrap();
+        let other = SimulatedBackend::new(SimulatedTdConfig::default()).unwrap();
+        let report = other.build_report(&ReportData([9; 64]));
+        assert!(!backend.verify_report_mac(&report));
+        assert!(matches!(
+            backend.quote_for_report(&report),
+            Err(TdxError::InvalidTdReport(_))
+        ));
+        assert!(backend.quote_for_unverified_report(&report).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_rtmr() {
+        let backend = SimulatedBackend::new(SimulatedTdConfig::default()).unwrap();
+        assert!(matches!(
+            backend.extend_rtmr(4, [1; TDX_EXTEND_RTMR_DATA_LEN]),
+            Err(RtmrError::InvalidIndex(4))
+        ));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::evidence::hex_bytes;
+use crate::tee_tdx_lib::TDX_REPORT_LEN;
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::result::Result;
+use std::result::Result::Ok;
+
+// TDREPORT_STRUCT layout, see Intel TDX Module ABI specification, section "TDREPORT_STRUCT"
+const REPORT_MAC_STRUCT_OFFSET: usize = 0;
+const TEE_TCB_INFO_OFFSET: usize = 256;
+const TD_INFO_OFFSET: usize = 512;
+
+// REPORTTYPE.TYPE value for TDX
+pub const REPORT_TYPE_TDX: u8 = 0x81;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TdReportVersion {
+    TDX10, // REPORTTYPE.VERSION 0, no SERVTD_HASH
+    TDX15, // REPORTTYPE.VERSION 1, TDINFO carries SERVTD_HASH
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportType {
+    pub tee_type: u8, // 0x81 for TDX
+    pub subtype: u8,
+    pub version: u8,
+    pub reserved: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportMacStruct {
+    pub report_type: ReportType,
+    #[serde(with = "hex_bytes")]
+    pub cpusvn: [u8; 16],
+    #[serde(with = "hex_bytes")]
+    pub tee_tcb_info_hash: [u8; 48], // SHA384 of TEE_TCB_INFO
+    #[serde(with = "hex_bytes")]
+    pub tee_info_hash: [u8; 48], // SHA384 of TDINFO
+    #[serde(with = "hex_bytes")]
+    pub report_data: [u8; 64],
+    #[serde(with = "hex_bytes")]
+    pub mac: [u8; 32],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TeeTcbInfo {
+    #[serde(with = "hex_bytes")]
+    pub valid: [u8; 8],
+    #[serde(with = "hex_bytes")]
+    pub tee_tcb_svn: [u8; 16],
+    #[serde(with = "hex_bytes")]
+    pub mrseam: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub mrsignerseam: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub attributes: [u8; 8],
+    #[serde(with = "hex_bytes")]
+    pub tee_tcb_svn2: [u8; 16], // TDX 1.5 only, reserved (zero) on TDX 1.0
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TdInfo {
+    #[serde(with = "hex_bytes")]
+    pub attributes: [u8; 8],
+    #[serde(with = "hex_bytes")]
+    pub xfam: [u8; 8],
+    #[serde(with = "hex_bytes")]
+    pub mrtd: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub mrconfigid: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub mrowner: [u8; 48],
+    #[serde(with = "hex_bytes")]
+    pub mrownerconfig: [u8; 48],
+    #[serde(with = "hex_bytes::list")]
+    pub rtmrs: [[u8; 48]; 4],
+    #[serde(with = "hex_bytes")]
+    pub servtd_hash: [u8; 48], // TDX 1.5 only, reserved (zero) on TDX 1.0
+}
+
+/// Parsed TDREPORT_STRUCT as returned by `get_tdx_report`. Serializes with its raw bytes,
+/// which the MAC covers, next to the decoded fields.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "TdReportFields")]
+pub struct TdReport {
+    pub report_mac: ReportMacStruct,
+    pub tee_tcb_info: TeeTcbInfo,
+    pub td_info: TdInfo,
+    #[serde(with = "hex_bytes")]
+    raw: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct TdReportFields {
+    report_mac: ReportMacStruct,
+    tee_tcb_info: TeeTcbInfo,
+    td_info: TdInfo,
+    #[serde(with = "hex_bytes")]
+    raw: Vec<u8>,
+}
+
+impl TryFrom<TdReportFields> for TdReport {
+    type Error = anyhow::Error;
+
+    fn try_from(fields: TdReportFields) -> Result<Self, Self::Error> {
+        let report = TdReport::from_bytes(&fields.raw)?;
+        if report.report_mac != fields.report_mac
+            || report.tee_tcb_info != fields.tee_tcb_info
+            || report.td_info != fields.td_info
+        {
+            return Err(anyhow!(
+                "[TdReport] Decoded fields do not match the raw report"
+            ));
+        }
+        Ok(report)
+    }
+}
+
+fn field<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+impl ReportMacStruct {
+    fn parse(bytes: &[u8]) -> Self {
+        ReportMacStruct {
+            report_type: ReportType {
+                tee_type: bytes[0],
+                subtype: bytes[1],
+                version: bytes[2],
+                reserved: bytes[3],
+            },
+            cpusvn: field(bytes, 16),
+            tee_tcb_info_hash: field(bytes, 32),
+            tee_info_hash: field(bytes, 80),
+            report_data: field(bytes, 128),
+            mac: field(bytes, 224),
+        }
+    }
+}
+
+impl TeeTcbInfo {
+    fn parse(bytes: &[u8]) -> Self {
+        TeeTcbInfo {
+            valid: field(bytes, 0),
+            tee_tcb_svn: field(bytes, 8),
+            mrseam: field(bytes, 24),
+            mrsignerseam: field(bytes, 72),
+            attributes: field(bytes, 120),
+            tee_tcb_svn2: field(bytes, 128),
+        }
+    }
+}
+
+impl TdInfo {
+    fn parse(bytes: &[u8]) -> Self {
+        TdInfo {
+            attributes: field(bytes, 0),
+            xfam: field(bytes, 8),
+            mrtd: field(bytes, 16),
+            mrconfigid: field(bytes, 64),
+            mrowner: field(bytes, 112),
+            mrownerconfig: field(bytes, 160),
+            rtmrs: [
+                field(bytes, 208),
+                field(bytes, 256),
+                field(bytes, 304),
+                field(bytes, 352),
+            ],
+            servtd_hash: field(bytes, 400),
+        }
+    }
+}
+
+impl TdReport {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        if bytes.len() != TDX_REPORT_LEN as usize {
+            return Err(anyhow!(
+                "[TdReport::from_bytes] Wrong TDX report size: expected {}, got {}",
+                TDX_REPORT_LEN,
+                bytes.len()
+            ));
+        }
+        if bytes[REPORT_MAC_STRUCT_OFFSET] != REPORT_TYPE_TDX {
+            return Err(anyhow!(
+                "[TdReport::from_bytes] Not a TDX report: report type {:#04x}",
+                bytes[REPORT_MAC_STRUCT_OFFSET]
+            ));
+        }
+
+        Ok(TdReport {
+            report_mac: ReportMacStruct::parse(
+                &bytes[REPORT_MAC_STRUCT_OFFSET..TEE_TCB_INFO_OFFSET],
+            ),
+            tee_tcb_info: TeeTcbInfo::parse(&bytes[TEE_TCB_INFO_OFFSET..TD_INFO_OFFSET]),
+            td_info: TdInfo::parse(&bytes[TD_INFO_OFFSET..]),
+            raw: bytes.to_vec(),
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn version(&self) -> TdReportVersion {
+        match self.report_mac.report_type.version {
+            0 => TdReportVersion::TDX10,
+            _ => TdReportVersion::TDX15,
+        }
+    }
+
+    pub fn report_data(&self) -> &[u8; 64] {
+        &self.report_mac.report_data
+    }
+
+    pub fn mrtd(&self) -> &[u8; 48] {
+        &self.td_info.mrtd
+    }
+
+    pub fn rtmr(&self, index: usize) -> Option<&[u8; 48]> {
+        self.td_info.rtmrs.get(index)
+    }
+
+    // SERVTD_HASH only exists in the TDX 1.5 layout
+    pub fn servtd_hash(&self) -> Option<&[u8; 48]> {
+        match self.version() {
+            TdReportVersion::TDX10 => None,
+            TdReportVersion::TDX15 => Some(&self.td_info.servtd_hash),
+        }
+    }
+
+    // TEE_TCB_SVN2 only exists in the TDX 1.5 layout
+    pub fn tee_tcb_svn2(&self) -> Option<&[u8; 16]> {
+        match self.version() {
+            TdReportVersion::TDX10 => None,
+            TdReportVersion::TDX15 => Some(&self.tee_tcb_info.tee_tcb_svn2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a report with every field filled with its own byte value, at its TDREPORT_STRUCT offset
+    fn report_bytes(version: u8) -> Vec<u8> {
+        let mut bytes = vec![0; TDX_REPORT_LEN as usize];
+        bytes[0..4].copy_from_slice(&[REPORT_TYPE_TDX, 0, version, 0]);
+        for (offset, len, value) in [
+            (16, 16, 0x01),
+            (32, 48, 0x02),
+            (80, 48, 0x03),
+            (128, 64, 0x04),
+            (224, 32, 0x05),
+            (256, 8, 0x11),
+            (264, 16, 0x12),
+            (280, 48, 0x13),
+            (328, 48, 0x14),
+            (376, 8, 0x15),
+            (384, 16, 0x16),
+            (512, 8, 0x21),
+            (520, 8, 0x22),
+            (528, 48, 0x23),
+            (576, 48, 0x24),
+            (624, 48, 0x25),
+            (672, 48, 0x26),
+            (720, 48, 0x27),
+            (768, 48, 0x28),
+            (816, 48, 0x29),
+            (864, 48, 0x2a),
+            (912, 48, 0x2b),
+        ] {
+            bytes[offset..offset + len].fill(value);
+        }
+        bytes
+    }
+
+    #[test]
+    fn field_offsets() {
+        let bytes = report_bytes(1);
+        let report = TdReport::from_bytes(&bytes).unwrap();
+        assert_eq!(report.as_bytes(), &bytes[..]);
+
+        let mac = &report.report_mac;
+        assert_eq!(
+            mac.report_type,
+            ReportType {
+                tee_type: REPORT_TYPE_TDX,
+                subtype: 0,
+                version: 1,
+                reserved: 0,
+            }
+        );
+        assert_eq!(mac.cpusvn, [0x01; 16]);
+        assert_eq!(mac.tee_tcb_info_hash, [0x02; 48]);
+        assert_eq!(mac.tee_info_hash, [0x03; 48]);
+        assert_eq!(mac.report_data, [0x04; 64]);
+        assert_eq!(mac.mac, [0x05; 32]);
+
+        let tcb = &report.tee_tcb_info;
+        assert_eq!(tcb.valid, [0x11; 8]);
+        assert_eq!(tcb.tee_tcb_svn, [0x12; 16]);
+        assert_eq!(tcb.mrseam, [0x13; 48]);
+        assert_eq!(tcb.mrsignerseam, [0x14; 48]);
+        assert_eq!(tcb.attributes, [0x15; 8]);
+        assert_eq!(tcb.tee_tcb_svn2, [0x16; 16]);
+
+        let td = &report.td_info;
+        assert_eq!(td.attributes, [0x21; 8]);
+        assert_eq!(td.xfam, [0x22; 8]);
+        assert_eq!(td.mrtd, [0x23; 48]);
+        assert_eq!(td.mrconfigid, [0x24; 48]);
+        assert_eq!(td.mrowner, [0x25; 48]);
+        assert_eq!(td.mrownerconfig, [0x26; 48]);
+        assert_eq!(td.rtmrs, [[0x27; 48], [0x28; 48], [0x29; 48], [0x2a; 48]]);
+        assert_eq!(td.servtd_hash, [0x2b; 48]);
+
+        assert_eq!(report.report_data(), &[0x04; 64]);
+        assert_eq!(report.mrtd(), &[0x23; 48]);
+        assert_eq!(report.rtmr(3), Some(&[0x2a; 48]));
+        assert_eq!(report.rtmr(4), None);
+    }
+
+    #[test]
+    fn tdx10_and_tdx15_layouts() {
+        let report = TdReport::from_bytes(&report_bytes(0)).unwrap();
+        assert_eq!(report.version(), TdReportVersion::TDX10);
+        assert_eq!(report.servtd_hash(), None);
+        assert_eq!(report.tee_tcb_svn2(), None);
+
+        let report = TdReport::from_bytes(&report_bytes(1)).unwrap();
+        assert_eq!(report.version(), TdReportVersion::TDX15);
+        assert_eq!(report.servtd_hash(), Some(&[0x2b; 48]));
+        assert_eq!(report.tee_tcb_svn2(), Some(&[0x16; 16]));
+    }
+
+    #[test]
+    fn wrong_length() {
+        let mut bytes = report_bytes(1);
+        for len in [0, 1023] {
+            assert!(TdReport::from_bytes(&bytes[..len])
+                .unwrap_err()
+                .to_string()
+                .contains(&format!("expected 1024, got {}", len)));
+        }
+        bytes.push(0);
+        assert!(TdReport::from_bytes(&bytes)
+            .unwrap_err()
+            .to_string()
+            .contains("expected 1024, got 1025"));
+    }
+
+    #[test]
+    fn not_a_tdx_report() {
+        let mut bytes = report_bytes(1);
+        bytes[0] = 0;
+        assert!(TdReport::from_bytes(&bytes)
+            .unwrap_err()
+            .to_string()
+            .contains("Not a TDX report: report type 0x00"));
+    }
+}
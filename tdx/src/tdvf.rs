@@ -0,0 +1,516 @@
+use anyhow::*;
+use sha2::{Digest, Sha384};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::result::Result;
+use std::result::Result::Ok;
+
+// OVMF GUIDed structure table, its footer GUID sits 0x30 bytes before the end of the image,
+// 96b582de-1fb2-45f7-baea-a366c55a082d
+const TABLE_FOOTER_GUID: [u8; 16] = [
+    0xde, 0x82, 0xb5, 0x96, 0xb2, 0x1f, 0xf7, 0x45, 0xba, 0xea, 0xa3, 0x66, 0xc5, 0x5a, 0x08, 0x2d,
+];
+const TABLE_FOOTER_OFFSET: usize = 0x30;
+// e47a6535-984a-4798-865e-4685a7bf8ec2, data is the metadata offset from the end of the image
+const TDX_METADATA_OFFSET_GUID: [u8; 16] = [
+    0x35, 0x65, 0x7a, 0xe4, 0x4a, 0x98, 0x98, 0x47, 0x86, 0x5e, 0x46, 0x85, 0xa7, 0xbf, 0x8e, 0xc2,
+];
+
+// TDVF metadata, see Intel TDX Virtual Firmware Design Guide, section "TDVF Metadata"
+const TDVF_SIGNATURE: &[u8; 4] = b"TDVF";
+const TDVF_DESCRIPTOR_LEN: usize = 16;
+const TDVF_SECTION_LEN: usize = 32;
+
+pub const TDVF_SECTION_TYPE_BFV: u32 = 0;
+pub const TDVF_SECTION_TYPE_CFV: u32 = 1;
+pub const TDVF_SECTION_TYPE_TD_HOB: u32 = 2;
+pub const TDVF_SECTION_TYPE_TEMP_MEM: u32 = 3;
+pub const TDVF_SECTION_TYPE_PERM_MEM: u32 = 4;
+pub const TDVF_SECTION_TYPE_PAYLOAD: u32 = 5;
+pub const TDVF_SECTION_TYPE_PAYLOAD_PARAM: u32 = 6;
+
+pub const TDVF_SECTION_ATTRIBUTES_MR_EXTEND: u32 = 1 << 0;
+pub const TDVF_SECTION_ATTRIBUTES_PAGE_AUG: u32 = 1 << 1;
+
+const PAGE_SIZE: u64 = 4096;
+// TDH.MR.EXTEND measures 256 bytes at a time
+const MR_EXTEND_CHUNK: usize = 256;
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+pub fn section_type_name(section_type: u32) -> &'static str {
+    match section_type {
+        TDVF_SECTION_TYPE_BFV => "BFV",
+        TDVF_SECTION_TYPE_CFV => "CFV",
+        TDVF_SECTION_TYPE_TD_HOB => "TD_HOB",
+        TDVF_SECTION_TYPE_TEMP_MEM => "TempMem",
+        TDVF_SECTION_TYPE_PERM_MEM => "PermMem",
+        TDVF_SECTION_TYPE_PAYLOAD => "Payload",
+        TDVF_SECTION_TYPE_PAYLOAD_PARAM => "PayloadParam",
+        _ => "unknown",
+    }
+}
+
+/// A TDVF_SECTION: raw_data_size bytes at data_offset in the image, loaded at memory_address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TdvfSection {
+    pub data_offset: u32,
+    pub raw_data_size: u32,
+    pub memory_address: u64,
+    pub memory_data_size: u64,
+    pub section_type: u32,
+    pub attributes: u32,
+}
+
+impl TdvfSection {
+    // PAGE.AUG memory is accepted by the guest later and never part of MRTD
+    pub fn is_added(&self) -> bool {
+        self.attributes & TDVF_SECTION_ATTRIBUTES_PAGE_AUG == 0
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.attributes & TDVF_SECTION_ATTRIBUTES_MR_EXTEND != 0
+    }
+}
+
+impl fmt::Display for TdvfSection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut attributes = Vec::new();
+        if self.is_extended() {
+            attributes.push("MR.EXTENDMR");
+        }
+        if !self.is_added() {
+            attributes.push("PAGE.AUG");
+        }
+        write!(
+            f,
+            "{:<12} memory {:#012x}+{:#x} data {:#x}+{:#x}",
+            section_type_name(self.section_type),
+            self.memory_address,
+            self.memory_data_size,
+            self.data_offset,
+            self.raw_data_size
+        )?;
+        for attribute in attributes {
+            write!(f, " {}", attribute)?;
+        }
+        Ok(())
+    }
+}
+
+/// TDVF metadata of a TDX firmware image, located through the OVMF GUIDed structure table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TdvfMetadata {
+    pub version: u32,
+    pub sections: Vec<TdvfSection>,
+}
+
+// data of the GUIDed table entry with guid; entries run backwards from the footer, each
+// ending in its u16 length, covering data, length and GUID, followed by its GUID
+fn find_guided_entry<'a>(image: &'a [u8], guid: &[u8; 16]) -> Result<&'a [u8], anyhow::Error> {
+    if image.len() < TABLE_FOOTER_OFFSET + 18
+        || image[image.len() - TABLE_FOOTER_OFFSET..][..16] != TABLE_FOOTER_GUID
+    {
+        return Err(anyhow!(
+            "[TdvfMetadata] No GUIDed structure table, not an OVMF/TDVF image"
+        ));
+    }
+    let footer = image.len() - TABLE_FOOTER_OFFSET;
+    let table_len = u16_at(image, footer - 2) as usize;
+    let table_start = match (footer + 16).checked_sub(table_len) {
+        None => {
+            return Err(anyhow!(
+                "[TdvfMetadata] Bad GUIDed table length {}",
+                table_len
+            ))
+        }
+        Some(start) => start,
+    };
+
+    let mut end = footer - 2;
+    while end >= table_start + 18 {
+        let entry_guid = &image[end - 16..end];
+        let entry_len = u16_at(image, end - 18) as usize;
+        let start = match end.checked_sub(entry_len) {
+            Some(start) if entry_len >= 18 && start >= table_start => start,
+            _ => {
+                return Err(anyhow!(
+                    "[TdvfMetadata] Bad GUIDed table entry length {}",
+                    entry_len
+                ))
+            }
+        };
+        if entry_guid == guid {
+            return Ok(&image[start..end - 18]);
+        }
+        end = start;
+    }
+    Err(anyhow!(
+        "[TdvfMetadata] No TDX metadata in the GUIDed table, not a TDVF image"
+    ))
+}
+
+impl TdvfMetadata {
+    pub fn parse(image: &[u8]) -> Result<Self, anyhow::Error> {
+        let entry = find_guided_entry(image, &TDX_METADATA_OFFSET_GUID)?;
+        if entry.len() < 4 {
+            return Err(anyhow!("[TdvfMetadata] Short TDX metadata offset entry"));
+        }
+        let offset = u32_at(entry, 0) as usize;
+        let start = match image.len().checked_sub(offset) {
+            Some(start) if start + TDVF_DESCRIPTOR_LEN <= image.len() => start,
+            _ => {
+                return Err(anyhow!(
+                    "[TdvfMetadata] Bad TDX metadata offset {:#x}",
+                    offset
+                ))
+            }
+        };
+
+        let descriptor = &image[start..];
+        if &descriptor[0..4] != TDVF_SIGNATURE {
+            return Err(anyhow!("[TdvfMetadata] Bad TDVF descriptor signature"));
+        }
+        let length = u32_at(descriptor, 4) as usize;
+        let version = u32_at(descriptor, 8);
+        let count = u32_at(descriptor, 12) as usize;
+        if length < TDVF_DESCRIPTOR_LEN + count * TDVF_SECTION_LEN || length > descriptor.len() {
+            return Err(anyhow!(
+                "[TdvfMetadata] TDVF descriptor length {} does not fit {} sections",
+                length,
+                count
+            ));
+        }
+
+        let mut sections = Vec::new();
+        for i in 0..count {
+            let s = &descriptor[TDVF_DESCRIPTOR_LEN + i * TDVF_SECTION_LEN..];
+            let section = TdvfSection {
+                data_offset: u32_at(s, 0),
+                raw_data_size: u32_at(s, 4),
+                memory_address: u64_at(s, 8),
+                memory_data_size: u64_at(s, 16),
+                section_type: u32_at(s, 24),
+                attributes: u32_at(s, 28),
+            };
+            if !section.memory_address.is_multiple_of(PAGE_SIZE)
+                || !section.memory_data_size.is_multiple_of(PAGE_SIZE)
+            {
+                return Err(anyhow!("[TdvfMetadata] Section {} is not page aligned", i));
+            }
+            if section.raw_data_size as u64 > section.memory_data_size
+                || section.data_offset as usize + section.raw_data_size as usize > image.len()
+            {
+                return Err(anyhow!(
+                    "[TdvfMetadata] Section {} data does not fit the image or its memory",
+                    i
+                ));
+            }
+            sections.push(section);
+        }
+
+        Ok(TdvfMetadata { version, sections })
+    }
+
+    /// MRTD after the VMM loads `image`: TDH.MEM.PAGE.ADD for every page of the sections that
+    /// are not PAGE.AUG, each followed by TDH.MR.EXTEND of its content for MR.EXTENDMR sections,
+    /// in section order as QEMU and KVM issue them.
+    pub fn compute_mrtd(&self, image: &[u8]) -> Result<[u8; 48], anyhow::Error> {
+        let mut mrtd = Sha384::new();
+        for section in self.sections.iter().filter(|s| s.is_added()) {
+            // memory content, the raw data zero padded to the section's memory size
+            let data_start = section.data_offset as usize;
+            let data = match image.get(data_start..data_start + section.raw_data_size as usize) {
+                None => {
+                    return Err(anyhow!(
+                        "[TdvfMetadata] Section data {:#x}+{:#x} is outside the image",
+                        section.data_offset,
+                        section.raw_data_size
+                    ))
+                }
+                Some(d) => d,
+            };
+
+            for page in 0..section.memory_data_size / PAGE_SIZE {
+                let gpa = section.memory_address + page * PAGE_SIZE;
+                mrtd.update(measurement_block(b"MEM.PAGE.ADD", gpa));
+                if !section.is_extended() {
+                    continue;
+                }
+                for chunk in 0..PAGE_SIZE as usize / MR_EXTEND_CHUNK {
+                    let offset = page as usize * PAGE_SIZE as usize + chunk * MR_EXTEND_CHUNK;
+                    let mut content = [0u8; MR_EXTEND_CHUNK];
+                    if offset < data.len() {
+                        let n = (data.len() - offset).min(MR_EXTEND_CHUNK);
+                        content[..n].copy_from_slice(&data[offset..offset + n]);
+                    }
+                    mrtd.update(measurement_block(
+                        b"MR.EXTEND",
+                        gpa + (chunk * MR_EXTEND_CHUNK) as u64,
+                    ));
+                    mrtd.update(content);
+                }
+            }
+        }
+        Ok(mrtd.finalize().into())
+    }
+}
+
+// the 128-byte block the TDX module hashes into MRTD: operation name, then the GPA at 16
+fn measurement_block(operation: &[u8], gpa: u64) -> [u8; 128] {
+    let mut block = [0u8; 128];
+    block[..operation.len()].copy_from_slice(operation);
+    block[16..24].copy_from_slice(&gpa.to_le_bytes());
+    block
+}
+
+// expected MRTD of a TD booted from the TDVF/OVMF image at path
+pub fn compute_mrtd(path: &Path) -> Result<[u8; 48], anyhow::Error> {
+    let image = match fs::read(path) {
+        Err(e) => {
+            return Err(anyhow!(
+                "[compute_mrtd] Fail to read {}: {:?}",
+                path.display(),
+                e
+            ))
+        }
+        Ok(i) => i,
+    };
+    TdvfMetadata::parse(&image)?.compute_mrtd(&image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_LEN: usize = 0x2000;
+    const METADATA_OFFSET: usize = 0x1000;
+    // 0f5d3a4e-0d2a-4b5c-9e1f-3a2b1c0d9e8f, an unrelated entry between metadata and footer
+    const OTHER_GUID: [u8; 16] = [
+        0x4e, 0x3a, 0x5d, 0x0f, 0x2a, 0x0d, 0x5c, 0x4b, 0x9e, 0x1f, 0x3a, 0x2b, 0x1c, 0x0d, 0x9e,
+        0x8f,
+    ];
+    // computed independently with Python's hashlib from the layout of image() below
+    const EXPECTED_MRTD: &str = "64e074d7fd4ad8aa0c99f9b274e33ef9257736c5c8e506c18d89cb2726afe370958a35e40a08678960c02f9621392b6d";
+
+    fn sections() -> Vec<TdvfSection> {
+        vec![
+            // two pages, the second one beyond the raw data and measured as zeros
+            TdvfSection {
+                data_offset: 0,
+                raw_data_size: 0x1000,
+                memory_address: 0xffffe000,
+                memory_data_size: 0x2000,
+                section_type: TDVF_SECTION_TYPE_BFV,
+                attributes: TDVF_SECTION_ATTRIBUTES_MR_EXTEND,
+            },
+            TdvfSection {
+                data_offset: 0,
+                raw_data_size: 0,
+                memory_address: 0x800000,
+                memory_data_size: 0x1000,
+                section_type: TDVF_SECTION_TYPE_TD_HOB,
+                attributes: 0,
+            },
+            TdvfSection {
+                data_offset: 0,
+                raw_data_size: 0,
+                memory_address: 0x900000,
+                memory_data_size: 0x2000,
+                section_type: TDVF_SECTION_TYPE_TEMP_MEM,
+                attributes: TDVF_SECTION_ATTRIBUTES_PAGE_AUG,
+            },
+        ]
+    }
+
+    // OVMF-style image: firmware bytes in the first page, TDVF metadata in the second, and the
+    // GUIDed table ending at the footer 0x30 bytes before the end
+    fn image() -> Vec<u8> {
+        let mut image = vec![0u8; IMAGE_LEN];
+        for (i, b) in image[..0x1000].iter_mut().enumerate() {
+            *b = (i % 251) as u8;
+        }
+
+        let sections = sections();
+        let descriptor = &mut image[METADATA_OFFSET..];
+        descriptor[0..4].copy_from_slice(TDVF_SIGNATURE);
+        let length = TDVF_DESCRIPTOR_LEN + sections.len() * TDVF_SECTION_LEN;
+        descriptor[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        descriptor[8..12].copy_from_slice(&1u32.to_le_bytes());
+        descriptor[12..16].copy_from_slice(&(sections.len() as u32).to_le_bytes());
+        for (i, section) in sections.iter().enumerate() {
+            let s = &mut descriptor[TDVF_DESCRIPTOR_LEN + i * TDVF_SECTION_LEN..];
+            s[0..4].copy_from_slice(&section.data_offset.to_le_bytes());
+            s[4..8].copy_from_slice(&section.raw_data_size.to_le_bytes());
+            s[8..16].copy_from_slice(&section.memory_address.to_le_bytes());
+            s[16..24].copy_from_slice(&section.memory_data_size.to_le_bytes());
+            s[24..28].copy_from_slice(&section.section_type.to_le_bytes());
+            s[28..32].copy_from_slice(&section.attributes.to_le_bytes());
+        }
+
+        // entries: data, u16 length, GUID; the metadata entry first, the other next to the footer
+        let mut table = Vec::new();
+        for (guid, data) in [
+            (
+                TDX_METADATA_OFFSET_GUID,
+                (IMAGE_LEN - METADATA_OFFSET) as u32,
+            ),
+            (OTHER_GUID, 0x12345678),
+        ] {
+            table.extend(data.to_le_bytes());
+            table.extend(22u16.to_le_bytes());
+            table.extend(guid);
+        }
+        table.extend((table.len() as u16 + 18).to_le_bytes());
+        table.extend(TABLE_FOOTER_GUID);
+        let footer_end = IMAGE_LEN - TABLE_FOOTER_OFFSET + 16;
+        image[footer_end - table.len()..footer_end].copy_from_slice(&table);
+        image
+    }
+
+    fn parse_error(image: &[u8]) -> String {
+        TdvfMetadata::parse(image).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn guided_table_walk() {
+        let image = image();
+        assert_eq!(
+            find_guided_entry(&image, &OTHER_GUID).unwrap(),
+            &0x12345678u32.to_le_bytes()
+        );
+        assert_eq!(
+            find_guided_entry(&image, &TDX_METADATA_OFFSET_GUID).unwrap(),
+            &((IMAGE_LEN - METADATA_OFFSET) as u32).to_le_bytes()
+        );
+        assert!(find_guided_entry(&image, &[0; 16])
+            .unwrap_err()
+            .to_string()
+            .contains("No TDX metadata"));
+    }
+
+    #[test]
+    fn metadata_sections() {
+        let metadata = TdvfMetadata::parse(&image()).unwrap();
+        assert_eq!(metadata.version, 1);
+        assert_eq!(metadata.sections, sections());
+        assert_eq!(
+            metadata.sections[0].to_string(),
+            "BFV          memory 0x00ffffe000+0x2000 data 0x0+0x1000 MR.EXTENDMR"
+        );
+        assert_eq!(
+            metadata.sections[2].to_string(),
+            "TempMem      memory 0x0000900000+0x2000 data 0x0+0x0 PAGE.AUG"
+        );
+    }
+
+    #[test]
+    fn known_mrtd() {
+        let image = image();
+        let mrtd = TdvfMetadata::parse(&image)
+            .unwrap()
+            .compute_mrtd(&image)
+            .unwrap();
+        assert_eq!(hex::encode(mrtd), EXPECTED_MRTD);
+
+        // every byte of an extended section is measured
+        let mut changed = image.clone();
+        changed[0xfff] ^= 1;
+        assert_ne!(
+            TdvfMetadata::parse(&changed)
+                .unwrap()
+                .compute_mrtd(&changed)
+                .unwrap(),
+            mrtd
+        );
+    }
+
+    #[test]
+    fn bad_footer_guid() {
+        let mut image = image();
+        image[IMAGE_LEN - TABLE_FOOTER_OFFSET] ^= 0xff;
+        assert!(parse_error(&image).contains("No GUIDed structure table"));
+        assert!(parse_error(&image[..TABLE_FOOTER_OFFSET]).contains("No GUIDed structure table"));
+    }
+
+    #[test]
+    fn bad_table_lengths() {
+        let footer = IMAGE_LEN - TABLE_FOOTER_OFFSET;
+        let mut image = image();
+        image[footer - 2..footer].copy_from_slice(&0xffffu16.to_le_bytes());
+        assert!(parse_error(&image).contains("Bad GUIDed table length"));
+
+        // the entry next to the footer claims more than the table holds
+        let mut image = self::image();
+        image[footer - 20..footer - 18].copy_from_slice(&0x100u16.to_le_bytes());
+        assert!(parse_error(&image).contains("Bad GUIDed table entry length"));
+    }
+
+    #[test]
+    fn bad_metadata_offset() {
+        let footer = IMAGE_LEN - TABLE_FOOTER_OFFSET;
+        // data of the metadata entry, before its length, its GUID and the other entry
+        let offset_at = footer - 2 - 22 - 22;
+        let mut image = image();
+        image[offset_at..offset_at + 4].copy_from_slice(&0x10000u32.to_le_bytes());
+        assert!(parse_error(&image).contains("Bad TDX metadata offset"));
+        image[offset_at..offset_at + 4].copy_from_slice(&8u32.to_le_bytes());
+        assert!(parse_error(&image).contains("Bad TDX metadata offset"));
+
+        let mut image = self::image();
+        image[METADATA_OFFSET] = b'X';
+        assert!(parse_error(&image).contains("Bad TDVF descriptor signature"));
+    }
+
+    #[test]
+    fn section_count_past_descriptor() {
+        let mut image = image();
+        image[METADATA_OFFSET + 12..METADATA_OFFSET + 16].copy_from_slice(&4u32.to_le_bytes());
+        assert!(parse_error(&image).contains("does not fit 4 sections"));
+
+        // a descriptor length running past the end of the image
+        let mut image = self::image();
+        image[METADATA_OFFSET + 12..METADATA_OFFSET + 16]
+            .copy_from_slice(&0x1000000u32.to_le_bytes());
+        image[METADATA_OFFSET + 4..METADATA_OFFSET + 8]
+            .copy_from_slice(&0x20000010u32.to_le_bytes());
+        assert!(parse_error(&image).contains("does not fit 16777216 sections"));
+    }
+
+    #[test]
+    fn section_data_past_image() {
+        let section = METADATA_OFFSET + TDVF_DESCRIPTOR_LEN;
+        let mut image = image();
+        image[section..section + 4].copy_from_slice(&0x1800u32.to_le_bytes());
+        assert!(parse_error(&image).contains("Section 0 data does not fit"));
+
+        let mut image = self::image();
+        image[section + 8] = 0x10;
+        assert!(parse_error(&image).contains("Section 0 is not page aligned"));
+
+        // metadata built elsewhere is checked against the image again
+        let metadata = TdvfMetadata {
+            version: 1,
+            sections: vec![TdvfSection {
+                data_offset: 0x1800,
+                ..sections()[0].clone()
+            }],
+        };
+        assert!(metadata
+            .compute_mrtd(&self::image())
+            .unwrap_err()
+            .to_string()
+            .contains("outside the image"));
+    }
+}
@@ -1,13 +1,24 @@
-use anyhow::*;
+use crate::qgs::{QgsErrorCode, QgsMessage};
+use crate::tsm::ConfigfsTsmBackend;
+use nix::errno::Errno;
 use nix::*;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::alloc::{self, Layout};
 use std::convert::TryInto;
+use std::fmt;
+use std::fs;
 use std::fs::File;
-use std::mem;
+use std::mem::ManuallyDrop;
 use std::os::unix::io::AsRawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::ptr::NonNull;
 use std::result::Result;
 use std::result::Result::Ok;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[repr(C)]
 pub struct tdx10_report_req {
@@ -24,31 +35,14 @@ pub struct tdx15_report_req {
     tdreport: [u8; TDX_REPORT_LEN as usize],
 }
 
-#[repr(C)]
-pub struct qgs_msg_header {
-    major_version: u16,
-    minor_version: u16,
-    msg_type: u32,
-    size: u32,       // size of the whole message, include this header, in byte
-    error_code: u32, // used in response only
-}
-
-#[repr(C)]
-pub struct qgs_msg_get_quote_req {
-    header: qgs_msg_header,                        // header.type = GET_QUOTE_REQ
-    report_size: u32,                              // cannot be 0
-    id_list_size: u32,                             // length of id_list, in byte, can be 0
-    report_id_list: [u8; TDX_REPORT_LEN as usize], // report followed by id list
-}
-
+// start of the shared GetQuote buffer, the QGS message follows at TDX_QUOTE_DATA_OFFSET
 #[repr(C)]
 pub struct tdx_quote_hdr {
-    version: u64,                       // Quote version, filled by TD
-    status: u64,                        // Status code of Quote request, filled by VMM
-    in_len: u32,                        // Length of TDREPORT, filled by TD
-    out_len: u32,                       // Length of Quote, filled by VMM
-    data_len_be_bytes: [u8; 4],         // big-endian 4 bytes indicate the size of data following
-    data: [u8; TDX_QUOTE_LEN as usize], // Actual Quote data or TDREPORT on input
+    version: u64,               // Quote version, filled by TD
+    status: u64,                // Status code of Quote request, filled by VMM
+    in_len: u32,                // Length of TDREPORT, filled by TD
+    out_len: u32,               // Length of Quote, filled by VMM
+    data_len_be_bytes: [u8; 4], // big-endian 4 bytes indicate the size of data following
 }
 
 #[repr(C)]
@@ -58,115 +52,804 @@ pub struct tdx_quote_req {
 }
 
 #[repr(C)]
-pub struct qgs_msg_get_quote_resp {
-    header: qgs_msg_header,        // header.type = GET_QUOTE_RESP
-    selected_id_size: u32,         // can be 0 in case only one id is sent in request
-    quote_size: u32,               // length of quote_data, in byte
-    id_quote: [u8; TDX_QUOTE_LEN], // selected id followed by quote
+pub struct tdx_extend_rtmr_req {
+    data: [u8; TDX_EXTEND_RTMR_DATA_LEN], // digest to extend, SHA384 sized
+    index: u8,                            // RTMR index
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TdxType {
     TDX10,
     TDX15,
 }
 
-const REPORT_DATA_LEN: u32 = 64;
-const TDX_REPORT_LEN: u32 = 1024;
-const TDX_QUOTE_LEN: usize = 4 * 4096;
+pub const REPORT_DATA_LEN: u32 = 64;
+pub const TDX_REPORT_LEN: u32 = 1024;
+pub const TDX_QUOTE_LEN: usize = 4 * 4096; // initial GetQuote buffer size
+pub const TDX_QUOTE_MAX_LEN: usize = 1 << 20;
+const TDX_QUOTE_DATA_OFFSET: usize = 28; // tdx_quote_hdr without its trailing padding
+const TDX_QUOTE_BUFFER_ALIGN: usize = 4096;
+pub const TDX_EXTEND_RTMR_DATA_LEN: usize = 48;
+pub const TDX_RTMR_COUNT: u8 = 4;
+const DEFAULT_QUOTE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_QUOTE_POLL_INTERVAL_MS: u64 = 100;
 
-pub struct TdxInfo {
-    tdx_version: TdxType,
-    device_node: File,
+/// The 64 bytes bound into REPORTMACSTRUCT.reportdata, passed to the TDX module unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportData(pub [u8; REPORT_DATA_LEN as usize]);
+
+impl ReportData {
+    pub fn new(bytes: [u8; REPORT_DATA_LEN as usize]) -> Self {
+        ReportData(bytes)
+    }
+
+    // e.g. a SHA-256 digest in the low 32 bytes; longer input is rejected
+    pub fn from_slice_zero_padded(bytes: &[u8]) -> Result<Self, TdxError> {
+        if bytes.len() > REPORT_DATA_LEN as usize {
+            return Err(TdxError::SizeMismatch {
+                what: "report data",
+                expected: REPORT_DATA_LEN as usize,
+                actual: bytes.len(),
+            });
+        }
+        let mut data = [0; REPORT_DATA_LEN as usize];
+        data[0..bytes.len()].copy_from_slice(bytes);
+        Ok(ReportData(data))
+    }
+
+    // base64 report data that must decode to exactly 64 bytes
+    pub fn from_base64(report_data: &str) -> Result<Self, TdxError> {
+        let bytes = match base64::decode(report_data) {
+            Ok(v) => v,
+            Err(e) => return Err(TdxError::InvalidReportData(e.to_string())),
+        };
+        match bytes.try_into() {
+            Ok(data) => Ok(ReportData(data)),
+            Err(v) => Err(TdxError::SizeMismatch {
+                what: "report data",
+                expected: REPORT_DATA_LEN as usize,
+                actual: v.len(),
+            }),
+        }
+    }
+
+    // the legacy String entry points accept any length: bytes past 64 are dropped and shorter
+    // data is zero padded, use from_base64 to reject anything but 64 bytes
+    pub fn from_base64_truncated(report_data: &str) -> Result<Self, TdxError> {
+        let bytes = match base64::decode(report_data) {
+            Ok(v) => v,
+            Err(e) => return Err(TdxError::InvalidReportData(e.to_string())),
+        };
+        let len = bytes.len().min(REPORT_DATA_LEN as usize);
+        ReportData::from_slice_zero_padded(&bytes[0..len])
+    }
+
+    // SHA-256 of data, zero-padded to 64 bytes
+    pub fn from_sha256(data: &[u8]) -> Self {
+        let mut bytes = [0; REPORT_DATA_LEN as usize];
+        bytes[0..32].copy_from_slice(&Sha256::digest(data));
+        ReportData(bytes)
+    }
+
+    // SHA-384 of data, zero-padded to 64 bytes
+    pub fn from_sha384(data: &[u8]) -> Self {
+        let mut bytes = [0; REPORT_DATA_LEN as usize];
+        bytes[0..48].copy_from_slice(&Sha384::digest(data));
+        ReportData(bytes)
+    }
+
+    pub fn from_sha512(data: &[u8]) -> Self {
+        ReportData(Sha512::digest(data).into())
+    }
+
+    // binds a verifier nonce to a TD held key: SHA-512(nonce || public_key)
+    pub fn from_nonce_and_public_key(nonce: &[u8], public_key: &[u8]) -> Self {
+        let mut hasher = Sha512::new();
+        hasher.update(nonce);
+        hasher.update(public_key);
+        ReportData(hasher.finalize().into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; REPORT_DATA_LEN as usize] {
+        &self.0
+    }
+}
+
+impl From<[u8; REPORT_DATA_LEN as usize]> for ReportData {
+    fn from(bytes: [u8; REPORT_DATA_LEN as usize]) -> Self {
+        ReportData(bytes)
+    }
+}
+
+// tdx_quote_hdr.status values, see the GHCI specification
+pub const GET_QUOTE_SUCCESS: u64 = 0;
+pub const GET_QUOTE_IN_FLIGHT: u64 = 0xffff_ffff_ffff_ffff;
+pub const GET_QUOTE_ERROR: u64 = 0x8000_0000_0000_0000;
+pub const GET_QUOTE_SERVICE_UNAVAILABLE: u64 = 0x8000_0000_0000_0001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GetQuoteStatus {
+    Success,
+    InFlight,           // the VMM has not finished the request yet
+    Error,              // the VMM failed the request
+    ServiceUnavailable, // no quote generation service is reachable from the host
+    Unknown(u64),
 }
 
-impl TdxInfo {
-    fn new(_tdx_version: TdxType, _device_node: File) -> Self {
-        TdxInfo {
-            tdx_version: _tdx_version,
-            device_node: _device_node,
+impl GetQuoteStatus {
+    pub fn from_status(status: u64) -> Self {
+        match status {
+            GET_QUOTE_SUCCESS => GetQuoteStatus::Success,
+            GET_QUOTE_IN_FLIGHT => GetQuoteStatus::InFlight,
+            GET_QUOTE_ERROR => GetQuoteStatus::Error,
+            GET_QUOTE_SERVICE_UNAVAILABLE => GetQuoteStatus::ServiceUnavailable,
+            s => GetQuoteStatus::Unknown(s),
         }
     }
+
+    pub fn status(&self) -> u64 {
+        match *self {
+            GetQuoteStatus::Success => GET_QUOTE_SUCCESS,
+            GetQuoteStatus::InFlight => GET_QUOTE_IN_FLIGHT,
+            GetQuoteStatus::Error => GET_QUOTE_ERROR,
+            GetQuoteStatus::ServiceUnavailable => GET_QUOTE_SERVICE_UNAVAILABLE,
+            GetQuoteStatus::Unknown(s) => s,
+        }
+    }
+
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            GetQuoteStatus::InFlight | GetQuoteStatus::ServiceUnavailable
+        )
+    }
 }
 
-fn get_tdx_version() -> TdxType {
-    if Path::new("/dev/tdx-guest").exists() {
-        TdxType::TDX10
-    } else if Path::new("/dev/tdx_guest").exists() {
-        TdxType::TDX15
-    } else if Path::new("/dev/tdx-attest").exists() {
-        panic!("get_tdx_version: Deprecated device node /dev/tdx-attest, please upgrade to use /dev/tdx-guest or /dev/tdx_guest");
-    } else {
-        panic!("get_tdx_version: no TDX device found!");
-    }
-}
-
-pub fn get_tdx_report(report_data: String) -> Result<Vec<u8>, anyhow::Error> {
-    //detect TDX version
-    let tdx_info = match get_tdx_version() {
-        TdxType::TDX10 => {
-            let device_node = match File::options()
-                .read(true)
-                .write(true)
-                .open("/dev/tdx-guest")
-            {
-                Err(e) => {
-                    return Err(anyhow!(
-                        "[get_tdx_report] Fail to open {}: {:?}",
-                        "/dev/tdx-guest",
-                        e
-                    ))
+impl fmt::Display for GetQuoteStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GetQuoteStatus::Success => write!(f, "success"),
+            GetQuoteStatus::InFlight => write!(f, "in flight"),
+            GetQuoteStatus::Error => write!(f, "error"),
+            GetQuoteStatus::ServiceUnavailable => write!(f, "service unavailable"),
+            GetQuoteStatus::Unknown(s) => write!(f, "unknown status {:#x}", s),
+        }
+    }
+}
+
+pub const TDX10_DEVICE_PATH: &str = "/dev/tdx-guest";
+pub const TDX15_DEVICE_PATH: &str = "/dev/tdx_guest";
+pub const TDX_ATTEST_DEVICE_PATH: &str = "/dev/tdx-attest"; // deprecated, not supported
+
+/// Cooperative cancellation for a GetQuote request that is still in flight.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuoteOptions {
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+    pub cancel: Option<CancellationToken>,
+    // GetQuote buffer size, grown up to max_buffer_len and retried when the quote does not fit
+    pub buffer_len: usize,
+    pub max_buffer_len: usize,
+}
+
+impl Default for QuoteOptions {
+    fn default() -> Self {
+        QuoteOptions {
+            timeout: Duration::from_secs(DEFAULT_QUOTE_TIMEOUT_SECS),
+            poll_interval: Duration::from_millis(DEFAULT_QUOTE_POLL_INTERVAL_MS),
+            cancel: None,
+            buffer_len: TDX_QUOTE_LEN,
+            max_buffer_len: TDX_QUOTE_MAX_LEN,
+        }
+    }
+}
+
+impl QuoteOptions {
+    pub(crate) fn check(&self, started: Instant) -> Result<(), TdxError> {
+        if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+            return Err(TdxError::Cancelled);
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= self.timeout {
+            return Err(TdxError::Timeout(elapsed));
+        }
+        Ok(())
+    }
+}
+
+// page aligned heap buffer shared with the VMM: tdx_quote_hdr, then the QGS message
+struct QuoteBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl QuoteBuffer {
+    // at least len bytes, rounded up to whole pages
+    fn new(len: usize) -> Self {
+        let len = len
+            .max(TDX_QUOTE_DATA_OFFSET)
+            .next_multiple_of(TDX_QUOTE_BUFFER_ALIGN);
+        let layout = Layout::from_size_align(len, TDX_QUOTE_BUFFER_ALIGN).unwrap();
+        let ptr = match NonNull::new(unsafe { alloc::alloc_zeroed(layout) }) {
+            None => alloc::handle_alloc_error(layout),
+            Some(p) => p,
+        };
+        QuoteBuffer { ptr, layout }
+    }
+
+    fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    fn header(&self) -> *mut tdx_quote_hdr {
+        self.ptr.as_ptr() as *mut tdx_quote_hdr
+    }
+
+    fn data(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.ptr.as_ptr().add(TDX_QUOTE_DATA_OFFSET),
+                self.len() - TDX_QUOTE_DATA_OFFSET,
+            )
+        }
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.ptr.as_ptr().add(TDX_QUOTE_DATA_OFFSET),
+                self.len() - TDX_QUOTE_DATA_OFFSET,
+            )
+        }
+    }
+}
+
+impl Drop for QuoteBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+// a submitted GetQuote request whose shared buffer the VMM may still be filling
+struct PendingQuote {
+    buffer: ManuallyDrop<QuoteBuffer>,
+}
+
+// The VMM keeps writing the buffer until it clears GET_QUOTE_IN_FLIGHT, so a request given up on
+// by timeout or cancellation leaks its buffer rather than handing memory the host still writes
+// back to the allocator.
+impl Drop for PendingQuote {
+    fn drop(&mut self) {
+        if self.status() != GetQuoteStatus::InFlight {
+            unsafe { ManuallyDrop::drop(&mut self.buffer) }
+        }
+    }
+}
+
+impl PendingQuote {
+    fn status(&self) -> GetQuoteStatus {
+        let status = unsafe { ptr::read_volatile(ptr::addr_of!((*self.buffer.header()).status)) };
+        GetQuoteStatus::from_status(status)
+    }
+
+    fn out_len(&self) -> u32 {
+        unsafe { ptr::read_volatile(ptr::addr_of!((*self.buffer.header()).out_len)) }
+    }
+
+    fn data_len(&self) -> u32 {
+        let bytes =
+            unsafe { ptr::read_volatile(ptr::addr_of!((*self.buffer.header()).data_len_be_bytes)) };
+        u32::from_be_bytes(bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryError {
+    NotFound(Vec<PathBuf>),        // none of the searched nodes exist
+    DeprecatedDevice(PathBuf),     // only /dev/tdx-attest exists
+    UnknownAbi(PathBuf),           // override path whose name does not imply an ABI
+    Inaccessible(PathBuf, String), // the node exists but cannot be inspected
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscoveryError::NotFound(paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "no TDX device found, searched {}", paths.join(", "))
+            }
+            DiscoveryError::DeprecatedDevice(p) => write!(
+                f,
+                "deprecated device node {}, please upgrade to use {} or {}",
+                p.display(),
+                TDX10_DEVICE_PATH,
+                TDX15_DEVICE_PATH
+            ),
+            DiscoveryError::UnknownAbi(p) => {
+                write!(f, "cannot tell the TDX ABI of {}", p.display())
+            }
+            DiscoveryError::Inaccessible(p, e) => write!(f, "{}: {}", p.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TdxError {
+    Discovery(DiscoveryError),
+    DeviceOpen {
+        path: PathBuf,
+        errno: Option<Errno>,
+    },
+    InvalidReportData(String), // report data is not valid base64
+    InvalidTdReport(String),   // not a TDREPORT the backend can quote
+    Ioctl {
+        request: &'static str,
+        errno: Errno,
+    },
+    QuoteStatus(GetQuoteStatus), // status of the GetQuote request, filled by VMM
+    QgsError(QgsErrorCode),      // non-zero error_code of the QGS response
+    // not a v1.0 GET_QUOTE_RESP
+    QgsResponse {
+        major_version: u16,
+        minor_version: u16,
+        msg_type: u32,
+    },
+    SizeMismatch {
+        what: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    Timeout(Duration), // GetQuote still in flight when the timeout expired
+    // the VMM needs a larger GetQuote buffer than max_buffer_len allows
+    QuoteBufferTooSmall {
+        capacity: usize,
+        required: usize,
+    },
+    Cancelled,
+    TsmIo {
+        path: PathBuf,
+        errno: Option<Errno>,
+    },
+    TsmProvider(String), // the configfs-tsm provider is not tdx_guest
+    QgsIo {
+        address: String,
+        errno: Option<Errno>,
+    },
+    // another writer raced on the configfs-tsm report entry
+    TsmGenerationMismatch {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for TdxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TdxError::Discovery(e) => write!(f, "{}", e),
+            TdxError::DeviceOpen { path, errno } => match errno {
+                Some(errno) => write!(f, "fail to open {}: {}", path.display(), errno),
+                None => write!(f, "fail to open {}", path.display()),
+            },
+            TdxError::InvalidTdReport(e) => write!(f, "invalid TDX report: {}", e),
+            TdxError::InvalidReportData(e) => write!(f, "report data is not base64 encoded: {}", e),
+            TdxError::Ioctl { request, errno } => write!(f, "{} ioctl failed: {}", request, errno),
+            TdxError::QuoteStatus(status) => write!(f, "GetQuote {}", status),
+            TdxError::QgsError(code) => write!(f, "{}", code),
+            TdxError::QgsResponse {
+                major_version,
+                minor_version,
+                msg_type,
+            } => write!(
+                f,
+                "unexpected QGS response version {}.{} type {}",
+                major_version, minor_version, msg_type
+            ),
+            TdxError::SizeMismatch {
+                what,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "wrong {} size: expected {}, got {}",
+                what, expected, actual
+            ),
+            TdxError::Timeout(elapsed) => write!(f, "GetQuote timed out after {:?}", elapsed),
+            TdxError::Cancelled => write!(f, "GetQuote cancelled"),
+            TdxError::QuoteBufferTooSmall { capacity, required } => write!(
+                f,
+                "GetQuote needs a {} byte buffer, {} available",
+                required, capacity
+            ),
+            TdxError::TsmIo { path, errno } => match errno {
+                Some(errno) => write!(f, "configfs-tsm {}: {}", path.display(), errno),
+                None => write!(f, "configfs-tsm {}", path.display()),
+            },
+            TdxError::QgsIo { address, errno } => match errno {
+                Some(errno) => write!(f, "QGS at {}: {}", address, errno),
+                None => write!(f, "QGS at {} closed the connection", address),
+            },
+            TdxError::TsmProvider(p) => write!(f, "configfs-tsm provider is {}, not tdx_guest", p),
+            TdxError::TsmGenerationMismatch { expected, actual } => write!(
+                f,
+                "configfs-tsm generation changed from {} to {} while reading the quote",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TdxError {}
+
+impl TdxError {
+    // whether the same request may succeed later, e.g. after a back-off
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            TdxError::QuoteStatus(status) => status.is_retriable(),
+            TdxError::QgsError(code) => code.is_retriable(),
+            TdxError::Timeout(_) | TdxError::TsmGenerationMismatch { .. } => true,
+            TdxError::Ioctl { errno, .. } => {
+                matches!(errno, Errno::EBUSY | Errno::EAGAIN | Errno::EINTR)
+            }
+            TdxError::QgsIo { errno, .. } => matches!(
+                errno,
+                Some(Errno::ECONNREFUSED | Errno::ECONNRESET | Errno::EPIPE | Errno::EINTR)
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl From<DiscoveryError> for TdxError {
+    fn from(e: DiscoveryError) -> Self {
+        TdxError::Discovery(e)
+    }
+}
+
+/// A TDX guest device node found on this system, with the ioctl ABI generation it implies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TdxDevice {
+    pub path: PathBuf,
+    pub tdx_version: TdxType,
+    pub accessible: bool, // the caller may open the node for read and write
+}
+
+impl TdxDevice {
+    fn probe(path: &Path, tdx_version: TdxType) -> Result<Self, DiscoveryError> {
+        match fs::metadata(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(DiscoveryError::NotFound(vec![path.to_path_buf()]))
+            }
+            Err(e) => {
+                return Err(DiscoveryError::Inaccessible(
+                    path.to_path_buf(),
+                    e.to_string(),
+                ))
+            }
+            Ok(_) => (),
+        };
+        Ok(TdxDevice {
+            path: path.to_path_buf(),
+            tdx_version,
+            accessible: unistd::access(path, unistd::AccessFlags::R_OK | unistd::AccessFlags::W_OK)
+                .is_ok(),
+        })
+    }
+
+    pub fn open(&self) -> std::io::Result<File> {
+        File::options().read(true).write(true).open(&self.path)
+    }
+}
+
+// look for the TDX 1.0 and TDX 1.5 guest device nodes
+pub fn discover_tdx_device() -> Result<TdxDevice, DiscoveryError> {
+    for (path, tdx_version) in [
+        (TDX10_DEVICE_PATH, TdxType::TDX10),
+        (TDX15_DEVICE_PATH, TdxType::TDX15),
+    ] {
+        if Path::new(path).exists() {
+            return TdxDevice::probe(Path::new(path), tdx_version);
+        }
+    }
+    if Path::new(TDX_ATTEST_DEVICE_PATH).exists() {
+        return Err(DiscoveryError::DeprecatedDevice(PathBuf::from(
+            TDX_ATTEST_DEVICE_PATH,
+        )));
+    }
+    Err(DiscoveryError::NotFound(vec![
+        PathBuf::from(TDX10_DEVICE_PATH),
+        PathBuf::from(TDX15_DEVICE_PATH),
+        PathBuf::from(TDX_ATTEST_DEVICE_PATH),
+    ]))
+}
+
+// use a device node bind-mounted elsewhere, e.g. in a container;
+// the ABI is taken from tdx_version, or from the node name when None
+pub fn discover_tdx_device_at(
+    path: &Path,
+    tdx_version: Option<TdxType>,
+) -> Result<TdxDevice, DiscoveryError> {
+    let tdx_version = match tdx_version {
+        Some(v) => v,
+        None => match path.file_name().and_then(|n| n.to_str()) {
+            Some("tdx-guest") => TdxType::TDX10,
+            Some("tdx_guest") => TdxType::TDX15,
+            Some("tdx-attest") => return Err(DiscoveryError::DeprecatedDevice(path.to_path_buf())),
+            _ => return Err(DiscoveryError::UnknownAbi(path.to_path_buf())),
+        },
+    };
+    TdxDevice::probe(path, tdx_version)
+}
+
+/// An open TDX guest device for long-lived callers such as quote servers: opened once, shared
+/// between threads, with concurrent requests serialized on it.
+pub struct TdxDeviceHandle {
+    device: TdxDevice,
+    device_node: File,
+    ioctl_lock: Mutex<()>, // one ioctl on the node at a time
+    quote_lock: Mutex<()>, // one GetQuote from submission until its response is read
+}
+
+impl TdxDeviceHandle {
+    pub fn open() -> Result<Self, TdxError> {
+        TdxDeviceHandle::open_device(&discover_tdx_device()?)
+    }
+
+    pub fn open_device(device: &TdxDevice) -> Result<Self, TdxError> {
+        match device.open() {
+            Err(e) => Err(TdxError::DeviceOpen {
+                path: device.path.clone(),
+                errno: e.raw_os_error().map(Errno::from_i32),
+            }),
+            Ok(fd) => Ok(TdxDeviceHandle {
+                device: device.clone(),
+                device_node: fd,
+                ioctl_lock: Mutex::new(()),
+                quote_lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    pub fn device(&self) -> &TdxDevice {
+        &self.device
+    }
+
+    pub fn get_report(&self, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+        let _ioctl = self.ioctl_lock.lock().unwrap();
+        match self.device.tdx_version {
+            TdxType::TDX10 => get_tdx10_report(&self.device_node, report_data),
+            TdxType::TDX15 => get_tdx15_report(&self.device_node, report_data),
+        }
+    }
+
+    // wait for GET_QUOTE_IN_FLIGHT to clear, up to options.timeout or until cancelled
+    pub fn get_quote(
+        &self,
+        report_data: &ReportData,
+        options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError> {
+        let started = Instant::now();
+        let _quote = self.quote_lock.lock().unwrap();
+
+        //retrive TDX report
+        let report = self.get_report(report_data)?;
+        if report.len() != TDX_REPORT_LEN as usize {
+            return Err(TdxError::SizeMismatch {
+                what: "TDX report",
+                expected: TDX_REPORT_LEN as usize,
+                actual: report.len(),
+            });
+        }
+        let qgs_msg = generate_qgs_quote_msg(&report, &[]);
+
+        let mut buffer_len = options.buffer_len;
+        loop {
+            let pending = self.submit_quote_request(&qgs_msg, buffer_len)?;
+            while pending.status() == GetQuoteStatus::InFlight {
+                options.check(started)?;
+                thread::sleep(options.poll_interval);
+            }
+            match read_quote_response(&pending, &qgs_msg) {
+                Err(TdxError::QuoteBufferTooSmall { required, .. })
+                    if required <= options.max_buffer_len =>
+                {
+                    options.check(started)?;
+                    buffer_len = required;
                 }
-                Ok(fd) => fd,
-            };
-            TdxInfo::new(TdxType::TDX10, device_node)
+                result => return result,
+            }
+        }
+    }
+
+    pub fn extend_rtmr(
+        &self,
+        index: u8,
+        digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+    ) -> Result<(), RtmrError> {
+        if index >= TDX_RTMR_COUNT {
+            return Err(RtmrError::InvalidIndex(index));
         }
-        TdxType::TDX15 => {
-            let device_node = match File::options()
-                .read(true)
-                .write(true)
-                .open("/dev/tdx_guest")
-            {
-                Err(e) => {
-                    return Err(anyhow!(
-                        "[get_tdx_report] Fail to open {}: {:?}",
-                        "/dev/tdx_guest",
-                        e
-                    ))
+        let request = tdx_extend_rtmr_req {
+            data: digest,
+            index,
+        };
+
+        //build the operator code and apply the ioctl command
+        let _ioctl = self.ioctl_lock.lock().unwrap();
+        let result = match self.device.tdx_version {
+            TdxType::TDX10 => {
+                ioctl_write_ptr!(extend_rtmr10_ioctl, b'T', 3, u64);
+                unsafe {
+                    extend_rtmr10_ioctl(
+                        self.device_node.as_raw_fd(),
+                        ptr::addr_of!(request) as *const u64,
+                    )
                 }
-                Ok(fd) => fd,
-            };
-            TdxInfo::new(TdxType::TDX15, device_node)
+            }
+            TdxType::TDX15 => {
+                ioctl_write_ptr!(extend_rtmr15_ioctl, b'T', 3, tdx_extend_rtmr_req);
+                unsafe { extend_rtmr15_ioctl(self.device_node.as_raw_fd(), ptr::addr_of!(request)) }
+            }
+        };
+        match result {
+            Err(e) => Err(rtmr_errno_error(
+                index,
+                e,
+                &self.device.path.display().to_string(),
+            )),
+            Ok(_) => Ok(()),
         }
-    };
+    }
+}
 
-    match tdx_info.tdx_version {
-        TdxType::TDX10 => match get_tdx10_report(tdx_info.device_node, report_data) {
-            Err(e) => return Err(anyhow!("[get_tdx_report] Fail to get TDX report: {:?}", e)),
-            Ok(report) => Ok(report),
-        },
-        TdxType::TDX15 => match get_tdx15_report(tdx_info.device_node, report_data) {
-            Err(e) => return Err(anyhow!("[get_tdx_report] Fail to get TDX report: {:?}", e)),
-            Ok(report) => Ok(report),
-        },
+impl TdxBackend for TdxDeviceHandle {
+    fn name(&self) -> &str {
+        "ioctl"
+    }
+
+    fn get_report(&self, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+        TdxDeviceHandle::get_report(self, report_data)
+    }
+
+    fn get_quote(
+        &self,
+        report_data: &ReportData,
+        options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError> {
+        TdxDeviceHandle::get_quote(self, report_data, options)
+    }
+
+    fn extend_rtmr(
+        &self,
+        index: u8,
+        digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+    ) -> Result<(), RtmrError> {
+        TdxDeviceHandle::extend_rtmr(self, index, digest)
     }
 }
 
-fn get_tdx10_report(device_node: File, report_data: String) -> Result<Vec<u8>, anyhow::Error> {
-    let report_data_bytes = match base64::decode(report_data) {
-        Ok(v) => v,
-        Err(e) => return Err(anyhow!("report data is not base64 encoded: {:?}", e)),
-    };
+/// Source of TDREPORTs, quotes and RTMR extensions behind the get_tdx_* functions.
+pub trait TdxBackend: Send + Sync {
+    fn name(&self) -> &str;
+    fn get_report(&self, report_data: &ReportData) -> Result<Vec<u8>, TdxError>;
+    fn get_quote(
+        &self,
+        report_data: &ReportData,
+        options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError>;
+    fn extend_rtmr(
+        &self,
+        index: u8,
+        digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+    ) -> Result<(), RtmrError>;
+}
+
+/// The /dev/tdx-guest and /dev/tdx_guest ioctl interface, opening the device node per call.
+/// Set a TdxDeviceHandle as backend to keep it open instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IoctlBackend;
+
+impl TdxBackend for IoctlBackend {
+    fn name(&self) -> &str {
+        "ioctl"
+    }
+
+    fn get_report(&self, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+        TdxDeviceHandle::open()?.get_report(report_data)
+    }
+
+    fn get_quote(
+        &self,
+        report_data: &ReportData,
+        options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError> {
+        TdxDeviceHandle::open()?.get_quote(report_data, options)
+    }
 
+    fn extend_rtmr(
+        &self,
+        index: u8,
+        digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+    ) -> Result<(), RtmrError> {
+        let tdx_device = match discover_tdx_device() {
+            Err(e) => return Err(RtmrError::NoDevice(e)),
+            Ok(d) => d,
+        };
+        let device = tdx_device.path.display().to_string();
+        let handle = match TdxDeviceHandle::open_device(&tdx_device) {
+            Err(TdxError::DeviceOpen {
+                errno: Some(Errno::EACCES | Errno::EPERM),
+                ..
+            }) => return Err(RtmrError::PermissionDenied(format!("open {}", device))),
+            Err(e) => return Err(RtmrError::Failed(e.to_string())),
+            Ok(h) => h,
+        };
+        handle.extend_rtmr(index, digest)
+    }
+}
+
+static TDX_BACKEND: RwLock<Option<Arc<dyn TdxBackend>>> = RwLock::new(None);
+
+// route all get_tdx_* and extend_rtmr calls of this process to backend
+pub fn set_tdx_backend(backend: Arc<dyn TdxBackend>) {
+    *TDX_BACKEND.write().unwrap() = Some(backend);
+}
+
+pub fn reset_tdx_backend() {
+    *TDX_BACKEND.write().unwrap() = None;
+}
+
+pub fn tdx_backend() -> Arc<dyn TdxBackend> {
+    match TDX_BACKEND.read().unwrap().as_ref() {
+        Some(backend) => backend.clone(),
+        None => detect_tdx_backend(),
+    }
+}
+
+// configfs-tsm on kernels that expose it with the tdx_guest provider, the device ioctls otherwise
+pub fn detect_tdx_backend() -> Arc<dyn TdxBackend> {
+    if ConfigfsTsmBackend::is_available() {
+        Arc::new(ConfigfsTsmBackend::new())
+    } else {
+        Arc::new(IoctlBackend)
+    }
+}
+
+// report_data is truncated or zero padded to 64 bytes, see ReportData::from_base64_truncated
+pub fn get_tdx_report(report_data: String) -> Result<Vec<u8>, TdxError> {
+    get_tdx_report_raw(&ReportData::from_base64_truncated(&report_data)?)
+}
+
+pub fn get_tdx_report_raw(report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+    tdx_backend().get_report(report_data)
+}
+
+fn get_tdx10_report(device_node: &File, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
     //prepare get TDX report request data
-    //let report_data_bytes = decoded_report_data.as_bytes();
-    let mut report_data_array: [u8; REPORT_DATA_LEN as usize] = [0; REPORT_DATA_LEN as usize];
-    report_data_array[0..((REPORT_DATA_LEN as usize) - 1)]
-        .copy_from_slice(&report_data_bytes[0..((REPORT_DATA_LEN as usize) - 1)]);
+    let report_data_array: [u8; REPORT_DATA_LEN as usize] = report_data.0;
     let td_report: [u8; TDX_REPORT_LEN as usize] = [0; TDX_REPORT_LEN as usize];
 
     //build the request
     let request = tdx10_report_req {
-        subtype: 0 as u8,
+        subtype: 0u8,
         reportdata: ptr::addr_of!(report_data_array) as u64,
         rpd_len: REPORT_DATA_LEN,
         tdreport: ptr::addr_of!(td_report) as u64,
@@ -177,206 +860,487 @@ fn get_tdx10_report(device_node: File, report_data: String) -> Result<Vec<u8>, a
     ioctl_readwrite!(get_report10_ioctl, b'T', 1, u64);
 
     //apply the ioctl command
-    match unsafe { get_report10_ioctl(device_node.as_raw_fd(), ptr::addr_of!(request) as *mut u64) }
+    if let Err(e) =
+        unsafe { get_report10_ioctl(device_node.as_raw_fd(), ptr::addr_of!(request) as *mut u64) }
     {
-        Err(e) => {
-            return Err(anyhow!(
-                "[get_tdx10_report] Fail to get TDX report: {:?}",
-                e
-            ))
-        }
-        Ok(_) => (),
+        return Err(TdxError::Ioctl {
+            request: "TDX 1.0 get report",
+            errno: e,
+        });
     };
 
     Ok(td_report.to_vec())
 }
 
-fn get_tdx15_report(device_node: File, report_data: String) -> Result<Vec<u8>, anyhow::Error> {
-    let report_data_bytes = match base64::decode(report_data) {
-        Ok(v) => v,
-        Err(e) => return Err(anyhow!("report data is not base64 encoded: {:?}", e)),
-    };
-
+fn get_tdx15_report(device_node: &File, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
     //prepare get TDX report request data
-    let mut request = tdx15_report_req {
-        reportdata: [0; REPORT_DATA_LEN as usize],
+    let request = tdx15_report_req {
+        reportdata: report_data.0,
         tdreport: [0; TDX_REPORT_LEN as usize],
     };
-    request.reportdata[0..((REPORT_DATA_LEN as usize) - 1)]
-        .copy_from_slice(&report_data_bytes[0..((REPORT_DATA_LEN as usize) - 1)]);
 
     //build the operator code
     ioctl_readwrite!(get_report15_ioctl, b'T', 1, tdx15_report_req);
 
     //apply the ioctl command
-    match unsafe {
+    if let Err(e) = unsafe {
         get_report15_ioctl(
             device_node.as_raw_fd(),
             ptr::addr_of!(request) as *mut tdx15_report_req,
         )
     } {
-        Err(e) => {
-            return Err(anyhow!(
-                "[get_tdx15_report] Fail to get TDX report: {:?}",
-                e
-            ))
-        }
-        Ok(_) => (),
+        return Err(TdxError::Ioctl {
+            request: "TDX 1.5 get report",
+            errno: e,
+        });
     };
 
     Ok(request.tdreport.to_vec())
 }
 
-fn generate_qgs_quote_msg(report: [u8; TDX_REPORT_LEN as usize]) -> qgs_msg_get_quote_req {
-    //build quote service message header to be used by QGS
-    let qgs_header = qgs_msg_header {
-        major_version: 1,
-        minor_version: 0,
-        msg_type: 0,
-        size: 16 + 8 + TDX_REPORT_LEN, // header + report_size and id_list_size + TDX_REPORT_LEN
-        error_code: 0,
-    };
+// GET_QUOTE_REQ for report, letting QGS pick among id_list if it is not empty
+pub fn generate_qgs_quote_msg(report: &[u8], id_list: &[Vec<u8>]) -> QgsMessage {
+    QgsMessage::GetQuoteRequest {
+        report: report.to_vec(),
+        id_list: id_list.to_vec(),
+    }
+}
 
-    //build quote service message body to be used by QGS
-    let mut qgs_request = qgs_msg_get_quote_req {
-        header: qgs_header,
-        report_size: TDX_REPORT_LEN,
-        id_list_size: 0,
-        report_id_list: [0; TDX_REPORT_LEN as usize],
-    };
+// checks a GET_QUOTE_RESP to request and returns the quote it carries
+pub(crate) fn parse_qgs_quote_response(
+    request: &QgsMessage,
+    msg: &[u8],
+) -> Result<Vec<u8>, TdxError> {
+    match QgsMessage::decode_response(request, msg)? {
+        QgsMessage::GetQuoteResponse { quote, .. } => Ok(quote),
+        _ => unreachable!("decode_response checks the response type"),
+    }
+}
+
+impl TdxDeviceHandle {
+    // hand qgs_msg to the VMM in a shared buffer of at least buffer_len bytes
+    fn submit_quote_request(
+        &self,
+        qgs_msg: &QgsMessage,
+        buffer_len: usize,
+    ) -> Result<PendingQuote, TdxError> {
+        let qgs_msg_bytes = qgs_msg.encode();
+
+        //build quote generation request header
+        //the VMM may still update the buffer after the ioctl returns, so it stays at a stable heap address
+        let mut buffer =
+            QuoteBuffer::new(buffer_len.max(TDX_QUOTE_DATA_OFFSET + qgs_msg_bytes.len()));
+        unsafe {
+            ptr::write(
+                buffer.header(),
+                tdx_quote_hdr {
+                    version: 1,
+                    status: 0,
+                    in_len: (qgs_msg_bytes.len() + 4) as u32,
+                    out_len: 0,
+                    data_len_be_bytes: (qgs_msg_bytes.len() as u32).to_be_bytes(),
+                },
+            )
+        };
+        buffer.data_mut()[0..qgs_msg_bytes.len()].copy_from_slice(&qgs_msg_bytes);
+
+        let request = tdx_quote_req {
+            buf: buffer.ptr.as_ptr() as u64,
+            len: buffer.len() as u64,
+        };
 
-    qgs_request.report_id_list[0..((TDX_REPORT_LEN as usize) - 1)]
-        .copy_from_slice(&report[0..((TDX_REPORT_LEN as usize) - 1)]);
+        //build the operator code and apply the ioctl command
+        let _ioctl = self.ioctl_lock.lock().unwrap();
+        match self.device.tdx_version {
+            TdxType::TDX10 => {
+                ioctl_read!(get_quote10_ioctl, b'T', 2, u64);
+                match unsafe {
+                    get_quote10_ioctl(
+                        self.device_node.as_raw_fd(),
+                        ptr::addr_of!(request) as *mut u64,
+                    )
+                } {
+                    Err(e) => {
+                        return Err(TdxError::Ioctl {
+                            request: "TDX 1.0 get quote",
+                            errno: e,
+                        })
+                    }
+                    Ok(_r) => _r,
+                };
+            }
+            TdxType::TDX15 => {
+                ioctl_read!(get_quote15_ioctl, b'T', 4, tdx_quote_req);
+                match unsafe {
+                    get_quote15_ioctl(
+                        self.device_node.as_raw_fd(),
+                        ptr::addr_of!(request) as *mut tdx_quote_req,
+                    )
+                } {
+                    Err(e) => {
+                        return Err(TdxError::Ioctl {
+                            request: "TDX 1.5 get quote",
+                            errno: e,
+                        })
+                    }
+                    Ok(_r) => _r,
+                };
+            }
+        };
 
-    qgs_request
+        Ok(PendingQuote {
+            buffer: ManuallyDrop::new(buffer),
+        })
+    }
 }
 
-pub fn get_tdx_quote(report_data: String) -> Result<Vec<u8>, anyhow::Error> {
-    //retrive TDX report
-    let report_data_vec = match get_tdx_report(report_data) {
-        Err(e) => return Err(anyhow!("[get_tdx_quote] Fail to get TDX report: {:?}", e)),
-        Ok(report) => report,
-    };
-    let report_data_array: [u8; TDX_REPORT_LEN as usize] = match report_data_vec.try_into() {
-        Ok(r) => r,
-        Err(e) => return Err(anyhow!("[get_tdx_quote] Wrong TDX report format: {:?}", e)),
-    };
+fn read_quote_response(pending: &PendingQuote, request: &QgsMessage) -> Result<Vec<u8>, TdxError> {
+    //inspect the response and retrive quote data
+    let out_len = pending.out_len() as usize;
+    let qgs_msg_resp_size = pending.data_len() as usize;
 
-    //build QGS request message
-    let qgs_msg = generate_qgs_quote_msg(report_data_array);
-
-    let tdx_info = match get_tdx_version() {
-        TdxType::TDX10 => {
-            let device_node = match File::options()
-                .read(true)
-                .write(true)
-                .open("/dev/tdx-guest")
-            {
-                Err(e) => {
-                    return Err(anyhow!(
-                        "[get_tdx_quote] Fail to open {}: {:?}",
-                        "/dev/tdx-guest",
-                        e
-                    ))
-                }
-                Ok(fd) => fd,
-            };
-            TdxInfo::new(TdxType::TDX10, device_node)
+    //out_len counts the length prefix, which sits in the header
+    let required = TDX_QUOTE_DATA_OFFSET - 4 + out_len.max(qgs_msg_resp_size + 4);
+    if required > pending.buffer.len() {
+        return Err(TdxError::QuoteBufferTooSmall {
+            capacity: pending.buffer.len(),
+            required,
+        });
+    }
+
+    match pending.status() {
+        GetQuoteStatus::Success => (),
+        status => return Err(TdxError::QuoteStatus(status)),
+    }
+
+    if out_len != qgs_msg_resp_size + 4 {
+        return Err(TdxError::SizeMismatch {
+            what: "GetQuote output",
+            expected: qgs_msg_resp_size + 4,
+            actual: out_len,
+        });
+    }
+
+    parse_qgs_quote_response(request, &pending.buffer.data()[0..qgs_msg_resp_size])
+}
+
+// report_data is truncated or zero padded to 64 bytes, see ReportData::from_base64_truncated
+pub fn get_tdx_quote(report_data: String) -> Result<Vec<u8>, TdxError> {
+    get_tdx_quote_with_options(report_data, &QuoteOptions::default())
+}
+
+pub fn get_tdx_quote_with_options(
+    report_data: String,
+    options: &QuoteOptions,
+) -> Result<Vec<u8>, TdxError> {
+    get_tdx_quote_raw_with_options(&ReportData::from_base64_truncated(&report_data)?, options)
+}
+
+pub fn get_tdx_quote_raw(report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+    get_tdx_quote_raw_with_options(report_data, &QuoteOptions::default())
+}
+
+pub fn get_tdx_quote_raw_with_options(
+    report_data: &ReportData,
+    options: &QuoteOptions,
+) -> Result<Vec<u8>, TdxError> {
+    tdx_backend().get_quote(report_data, options)
+}
+
+// cancels the request on the blocking pool when its future is dropped before completing
+#[cfg(feature = "async")]
+struct CancelOnDrop(Option<CancellationToken>);
+
+#[cfg(feature = "async")]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
         }
-        TdxType::TDX15 => {
-            let device_node = match File::options()
-                .read(true)
-                .write(true)
-                .open("/dev/tdx_guest")
-            {
-                Err(e) => {
-                    return Err(anyhow!(
-                        "[get_tdx_quote] Fail to open {}: {:?}",
-                        "/dev/tdx_guest",
-                        e
-                    ))
-                }
-                Ok(fd) => fd,
-            };
-            TdxInfo::new(TdxType::TDX15, device_node)
+    }
+}
+
+// the request and its in-flight polling run on the blocking pool, not on an executor thread;
+// dropping the future cancels it through options.cancel, a token passed by the caller included
+#[cfg(feature = "async")]
+pub async fn get_tdx_quote_async(
+    report_data: ReportData,
+    mut options: QuoteOptions,
+) -> Result<Vec<u8>, TdxError> {
+    let token = options
+        .cancel
+        .get_or_insert_with(CancellationToken::new)
+        .clone();
+    let mut guard = CancelOnDrop(Some(token));
+    let backend = tdx_backend();
+    let result =
+        tokio::task::spawn_blocking(move || backend.get_quote(&report_data, &options)).await;
+    guard.0 = None;
+    match result {
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(TdxError::Cancelled),
+        Ok(quote) => quote,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtmrError {
+    InvalidIndex(u8),     // out of the RTMR0..RTMR3 range, rejected before the ioctl
+    UnsupportedIndex(u8), // the kernel or TDX module refused to extend this RTMR
+    PermissionDenied(String),
+    Unsupported(String), // the device node does not implement the extend ioctl
+    NoDevice(DiscoveryError),
+    Failed(String),
+}
+
+impl fmt::Display for RtmrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RtmrError::InvalidIndex(i) => write!(f, "invalid RTMR index {}", i),
+            RtmrError::UnsupportedIndex(i) => write!(f, "extending RTMR{} is not allowed", i),
+            RtmrError::PermissionDenied(s) => write!(f, "permission denied: {}", s),
+            RtmrError::Unsupported(s) => write!(f, "RTMR extend not supported: {}", s),
+            RtmrError::NoDevice(e) => write!(f, "RTMR extend failed: {}", e),
+            RtmrError::Failed(s) => write!(f, "RTMR extend failed: {}", s),
         }
-    };
+    }
+}
 
-    //build quote generation request header
-    let mut quote_header = tdx_quote_hdr {
-        version: 1,
-        status: 0,
-        in_len: (mem::size_of_val(&qgs_msg) + 4) as u32,
-        out_len: 0,
-        data_len_be_bytes: (1048 as u32).to_be_bytes(),
-        data: [0; TDX_QUOTE_LEN as usize],
-    };
+impl std::error::Error for RtmrError {}
 
-    let qgs_msg_bytes = unsafe {
-        let ptr = &qgs_msg as *const qgs_msg_get_quote_req as *const u8;
-        std::slice::from_raw_parts(ptr, mem::size_of::<qgs_msg_get_quote_req>())
-    };
-    quote_header.data[0..(16 + 8 + TDX_REPORT_LEN - 1) as usize]
-        .copy_from_slice(&qgs_msg_bytes[0..((16 + 8 + TDX_REPORT_LEN - 1) as usize)]);
+/// An RTMR extension made by this process, kept so callers can later produce a matching event log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtmrEvent {
+    pub index: u8,
+    pub digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+    pub event_data: Vec<u8>,
+}
 
-    let request = tdx_quote_req {
-        buf: ptr::addr_of!(quote_header) as u64,
-        len: TDX_QUOTE_LEN as u64,
-    };
+static RTMR_EVENTS: Mutex<Vec<RtmrEvent>> = Mutex::new(Vec::new());
 
-    //build the operator code and apply the ioctl command
-    match tdx_info.tdx_version {
-        TdxType::TDX10 => {
-            ioctl_read!(get_quote10_ioctl, b'T', 2, u64);
-            match unsafe {
-                get_quote10_ioctl(
-                    tdx_info.device_node.as_raw_fd(),
-                    ptr::addr_of!(request) as *mut u64,
-                )
-            } {
-                Err(e) => return Err(anyhow!("[get_tdx_quote] Fail to get TDX quote: {:?}", e)),
-                Ok(_r) => _r,
-            };
+fn rtmr_errno_error(index: u8, errno: Errno, device: &str) -> RtmrError {
+    match errno {
+        Errno::EPERM | Errno::EACCES => {
+            RtmrError::PermissionDenied(format!("extend RTMR{} on {}", index, device))
         }
-        TdxType::TDX15 => {
-            ioctl_read!(get_quote15_ioctl, b'T', 4, tdx_quote_req);
-            match unsafe {
-                get_quote15_ioctl(
-                    tdx_info.device_node.as_raw_fd(),
-                    ptr::addr_of!(request) as *mut tdx_quote_req,
-                )
-            } {
-                Err(e) => return Err(anyhow!("[get_tdx_quote] Fail to get TDX quote: {:?}", e)),
-                Ok(_r) => _r,
-            };
+        Errno::EINVAL => RtmrError::UnsupportedIndex(index),
+        Errno::ENOTTY | Errno::EOPNOTSUPP => {
+            RtmrError::Unsupported(format!("{} has no extend ioctl", device))
         }
-    };
+        e => RtmrError::Failed(format!("{}: {:?}", device, e)),
+    }
+}
 
-    //inspect the response and retrive quote data
-    let out_len = quote_header.out_len;
-    let qgs_msg_resp_size =
-        unsafe { std::mem::transmute::<[u8; 4], u32>(quote_header.data_len_be_bytes) }.to_be();
+pub fn extend_rtmr(index: u8, digest: [u8; TDX_EXTEND_RTMR_DATA_LEN]) -> Result<(), RtmrError> {
+    if index >= TDX_RTMR_COUNT {
+        return Err(RtmrError::InvalidIndex(index));
+    }
+    tdx_backend().extend_rtmr(index, digest)
+}
 
-    let qgs_msg_resp = unsafe {
-        let raw_ptr = ptr::addr_of!(quote_header.data) as *mut qgs_msg_get_quote_resp;
-        raw_ptr.as_mut().unwrap() as &mut qgs_msg_get_quote_resp
-    };
+// extend SHA384(event_data) and record the event for a later event log
+pub fn extend_rtmr_with_event(
+    index: u8,
+    event_data: &[u8],
+) -> Result<[u8; TDX_EXTEND_RTMR_DATA_LEN], RtmrError> {
+    let digest: [u8; TDX_EXTEND_RTMR_DATA_LEN] = Sha384::digest(event_data).into();
+    extend_rtmr(index, digest)?;
 
-    if out_len - qgs_msg_resp_size != 4 {
-        return Err(anyhow!(
-            "[get_tdx_quote] Fail to get TDX quote: wrong TDX quote size!"
-        ));
+    RTMR_EVENTS.lock().unwrap().push(RtmrEvent {
+        index,
+        digest,
+        event_data: event_data.to_vec(),
+    });
+    Ok(digest)
+}
+
+pub fn recorded_rtmr_events() -> Vec<RtmrEvent> {
+    RTMR_EVENTS.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quote::Quote;
+    use crate::simulator::{SimulatedBackend, SimulatedTdConfig};
+    use crate::tdreport::TdReport;
+    use crate::verify::verify_quote_at;
+    use std::time::SystemTime;
+
+    // the process wide backend is shared by every test that sets it
+    static BACKEND_LOCK: Mutex<()> = Mutex::new(());
+
+    // a failed test must not fail the others through a poisoned lock
+    fn lock_backend() -> std::sync::MutexGuard<'static, ()> {
+        BACKEND_LOCK.lock().unwrap_or_else(|e| e.into_inner())
     }
 
-    if qgs_msg_resp.header.major_version != 1
-        || qgs_msg_resp.header.minor_version != 0
-        || qgs_msg_resp.header.msg_type != 1
-        || qgs_msg_resp.header.error_code != 0
-    {
-        return Err(anyhow!(
-            "[get_tdx_quote] Fail to get TDX quote: QGS response error!"
+    fn simulated() -> Arc<SimulatedBackend> {
+        Arc::new(SimulatedBackend::new(SimulatedTdConfig::default()).unwrap())
+    }
+
+    fn check_quote(quote: &[u8], platform: &SimulatedBackend, report_data: &ReportData) {
+        let quote = Quote::from_bytes(quote).unwrap();
+        assert_eq!(quote.body.report_data, report_data.0);
+        let verdict = verify_quote_at(&quote, platform.root_certificate(), SystemTime::now());
+        assert!(verdict.is_valid(), "{:?}", verdict.failures());
+    }
+
+    #[test]
+    fn quote_through_global_backend() {
+        let _lock = lock_backend();
+        let platform = simulated();
+        set_tdx_backend(platform.clone());
+        assert_eq!(tdx_backend().name(), platform.name());
+
+        let report_data = ReportData([5; 64]);
+        let quote = get_tdx_quote_raw(&report_data);
+        reset_tdx_backend();
+        check_quote(&quote.unwrap(), &platform, &report_data);
+    }
+
+    #[test]
+    fn base64_report_data() {
+        let full: Vec<u8> = (0..64).collect();
+        assert_eq!(
+            ReportData::from_base64(&base64::encode(&full)).unwrap().0[..],
+            full[..]
+        );
+        assert_eq!(
+            ReportData::from_base64(&base64::encode([1; 32])),
+            Err(TdxError::SizeMismatch {
+                what: "report data",
+                expected: 64,
+                actual: 32,
+            })
+        );
+        assert!(matches!(
+            ReportData::from_base64("not base64!"),
+            Err(TdxError::InvalidReportData(_))
         ));
+
+        // the String entry points truncate and pad instead
+        let long: Vec<u8> = (0..80).collect();
+        assert_eq!(
+            ReportData::from_base64_truncated(&base64::encode(&long))
+                .unwrap()
+                .0[..],
+            long[0..64]
+        );
+        let mut padded = [0; 64];
+        padded[0..32].copy_from_slice(&[1; 32]);
+        assert_eq!(
+            ReportData::from_base64_truncated(&base64::encode([1; 32])).unwrap(),
+            ReportData(padded)
+        );
+        assert_eq!(
+            ReportData::from_base64_truncated("").unwrap(),
+            ReportData([0; 64])
+        );
+        assert!(matches!(
+            ReportData::from_base64_truncated("not base64!"),
+            Err(TdxError::InvalidReportData(_))
+        ));
+    }
+
+    #[test]
+    fn string_entry_points_pad_report_data() {
+        let _lock = lock_backend();
+        let platform = simulated();
+        set_tdx_backend(platform.clone());
+        let report = get_tdx_report(base64::encode(b"nonce"));
+        let quote = get_tdx_quote(base64::encode([9; 72]));
+        reset_tdx_backend();
+
+        let mut nonce = [0; 64];
+        nonce[0..5].copy_from_slice(b"nonce");
+        let report = TdReport::from_bytes(&report.unwrap()).unwrap();
+        assert_eq!(report.report_mac.report_data, nonce);
+        check_quote(&quote.unwrap(), &platform, &ReportData([9; 64]));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_quote_through_global_backend() {
+        let _lock = lock_backend();
+        let platform = simulated();
+        set_tdx_backend(platform.clone());
+
+        let report_data = ReportData([6; 64]);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let quote = runtime.block_on(get_tdx_quote_async(report_data, QuoteOptions::default()));
+        reset_tdx_backend();
+        check_quote(&quote.unwrap(), &platform, &report_data);
+    }
+
+    // a GetQuote that never completes, reporting how its request ended
+    #[cfg(feature = "async")]
+    struct PendingBackend(Mutex<std::sync::mpsc::Sender<Result<Vec<u8>, TdxError>>>);
+
+    #[cfg(feature = "async")]
+    impl TdxBackend for PendingBackend {
+        fn name(&self) -> &str {
+            "pending"
+        }
+
+        fn get_report(&self, _report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+            Ok(vec![0; TDX_REPORT_LEN as usize])
+        }
+
+        fn get_quote(
+            &self,
+            _report_data: &ReportData,
+            options: &QuoteOptions,
+        ) -> Result<Vec<u8>, TdxError> {
+            let started = Instant::now();
+            let result = loop {
+                if let Err(e) = options.check(started) {
+                    break Err(e);
+                }
+                thread::sleep(options.poll_interval);
+            };
+            let _ = self.0.lock().unwrap().send(result.clone());
+            result
+        }
+
+        fn extend_rtmr(
+            &self,
+            _index: u8,
+            _digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+        ) -> Result<(), RtmrError> {
+            Ok(())
+        }
     }
 
-    Ok(qgs_msg_resp.id_quote[0..(qgs_msg_resp.quote_size as usize)].to_vec())
+    #[cfg(feature = "async")]
+    #[test]
+    fn dropped_async_quote_is_cancelled() {
+        let _lock = lock_backend();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        set_tdx_backend(Arc::new(PendingBackend(Mutex::new(sender))));
+
+        let options = QuoteOptions {
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(10),
+            ..QuoteOptions::default()
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        // the timeout drops the future while the request is still in flight
+        let result = runtime.block_on(async {
+            tokio::time::timeout(
+                Duration::from_millis(100),
+                get_tdx_quote_async(ReportData([7; 64]), options),
+            )
+            .await
+        });
+        assert!(result.is_err());
+        let ended = receiver.recv_timeout(Duration::from_secs(2));
+        reset_tdx_backend();
+        assert_eq!(ended.unwrap(), Err(TdxError::Cancelled));
+    }
 }
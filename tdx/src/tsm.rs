@@ -0,0 +1,417 @@
+use crate::tee_tdx_lib::*;
+use nix::errno::Errno;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::result::Result;
+use std::result::Result::Ok;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+// configfs TSM report interface, see Documentation/ABI/testing/configfs-tsm
+pub const TSM_REPORT_PATH: &str = "/sys/kernel/config/tsm/report";
+pub const TSM_PROVIDER_TDX: &str = "tdx_guest";
+
+static TSM_ENTRY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Entry and attribute access under the report directory. configfs creates the attributes of
+/// an entry on mkdir and bumps generation on every inblob write; a fake tree has to do both.
+pub trait TsmFs: Send + Sync {
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+}
+
+// the real configfs, or any directory whose attributes are static files
+pub struct Configfs;
+
+impl TsmFs for Configfs {
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        fs::write(path, data)
+    }
+}
+
+fn tsm_error(path: &Path, e: io::Error) -> TdxError {
+    TdxError::TsmIo {
+        path: path.to_path_buf(),
+        errno: e.raw_os_error().map(Errno::from_i32),
+    }
+}
+
+fn read_attribute(tsm_fs: &dyn TsmFs, path: &Path) -> Result<String, TdxError> {
+    match tsm_fs.read(path) {
+        Err(e) => Err(tsm_error(path, e)),
+        Ok(bytes) => Ok(String::from_utf8_lossy(&bytes).trim().to_string()),
+    }
+}
+
+fn read_generation(tsm_fs: &dyn TsmFs, entry: &Path) -> Result<u64, TdxError> {
+    let path = entry.join("generation");
+    let generation = read_attribute(tsm_fs, &path)?;
+    match generation.parse() {
+        Err(_) => Err(TdxError::TsmIo {
+            path,
+            errno: Some(Errno::EINVAL),
+        }),
+        Ok(g) => Ok(g),
+    }
+}
+
+fn check_generation(tsm_fs: &dyn TsmFs, entry: &Path, expected: u64) -> Result<(), TdxError> {
+    let actual = read_generation(tsm_fs, entry)?;
+    if actual != expected {
+        return Err(TdxError::TsmGenerationMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+// a report entry under the TSM report directory, removed again when dropped if we created it
+struct TsmEntry {
+    path: PathBuf,
+    created: bool,
+    tsm_fs: Arc<dyn TsmFs>,
+}
+
+impl Drop for TsmEntry {
+    fn drop(&mut self) {
+        if self.created {
+            //configfs removes the attributes with the directory; best effort only
+            let _ = self.tsm_fs.remove_dir(&self.path);
+        }
+    }
+}
+
+/// Quotes through configfs-tsm (inblob/outblob); TDREPORTs and RTMR extension still use the ioctls.
+#[derive(Clone)]
+pub struct ConfigfsTsmBackend {
+    root: PathBuf,
+    entry_name: Option<String>,
+    tsm_fs: Arc<dyn TsmFs>,
+}
+
+impl fmt::Debug for ConfigfsTsmBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConfigfsTsmBackend")
+            .field("root", &self.root)
+            .field("entry_name", &self.entry_name)
+            .finish()
+    }
+}
+
+impl Default for ConfigfsTsmBackend {
+    fn default() -> Self {
+        ConfigfsTsmBackend::with_root(Path::new(TSM_REPORT_PATH))
+    }
+}
+
+impl ConfigfsTsmBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a different report directory, e.g. a fake tree in tests
+    pub fn with_root(root: &Path) -> Self {
+        ConfigfsTsmBackend {
+            root: root.to_path_buf(),
+            entry_name: None,
+            tsm_fs: Arc::new(Configfs),
+        }
+    }
+
+    // use this entry, creating it if missing, instead of a fresh one per request;
+    // an entry that already exists is left in place
+    pub fn entry_name(mut self, name: &str) -> Self {
+        self.entry_name = Some(name.to_string());
+        self
+    }
+
+    // access the report directory through tsm_fs, e.g. a fake that bumps generation itself
+    pub fn tsm_fs(mut self, tsm_fs: Arc<dyn TsmFs>) -> Self {
+        self.tsm_fs = tsm_fs;
+        self
+    }
+
+    // configfs-tsm is present and backed by the TDX guest driver, not e.g. sev_guest
+    pub fn is_available() -> bool {
+        Path::new(TSM_REPORT_PATH).is_dir()
+            && ConfigfsTsmBackend::new()
+                .provider()
+                .is_ok_and(|p| p == TSM_PROVIDER_TDX)
+    }
+
+    fn create_entry(&self) -> Result<TsmEntry, TdxError> {
+        let name = match &self.entry_name {
+            Some(n) => n.clone(),
+            None => format!(
+                "tdx-{}-{}",
+                process::id(),
+                TSM_ENTRY_COUNTER.fetch_add(1, Ordering::SeqCst)
+            ),
+        };
+        let path = self.root.join(name);
+        let tsm_fs = self.tsm_fs.clone();
+        match self.tsm_fs.create_dir(&path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && self.entry_name.is_some() => {
+                Ok(TsmEntry {
+                    path,
+                    created: false,
+                    tsm_fs,
+                })
+            }
+            Err(e) => Err(tsm_error(&path, e)),
+            Ok(()) => Ok(TsmEntry {
+                path,
+                created: true,
+                tsm_fs,
+            }),
+        }
+    }
+
+    // the provider attribute of an entry, "tdx_guest" for TDX
+    pub fn provider(&self) -> Result<String, TdxError> {
+        let entry = self.create_entry()?;
+        read_attribute(self.tsm_fs.as_ref(), &entry.path.join("provider"))
+    }
+
+    pub fn get_quote(&self, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+        self.get_quote_with_options(report_data, &QuoteOptions::default())
+    }
+
+    // the outblob read blocks in the kernel until the quote is ready and cannot be interrupted,
+    // so timeout and cancel are only checked between the steps around it
+    pub fn get_quote_with_options(
+        &self,
+        report_data: &ReportData,
+        options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError> {
+        let started = Instant::now();
+        options.check(started)?;
+        let tsm_fs = self.tsm_fs.as_ref();
+        let entry = self.create_entry()?;
+
+        let provider = read_attribute(tsm_fs, &entry.path.join("provider"))?;
+        if provider != TSM_PROVIDER_TDX {
+            return Err(TdxError::TsmProvider(provider));
+        }
+
+        //our write bumps generation by exactly one; any other value means another writer
+        //changed inblob around our write or read, and outblob may not be ours
+        let expected = read_generation(tsm_fs, &entry.path)? + 1;
+        let inblob = entry.path.join("inblob");
+        if let Err(e) = tsm_fs.write(&inblob, report_data.as_bytes()) {
+            return Err(tsm_error(&inblob, e));
+        }
+        check_generation(tsm_fs, &entry.path, expected)?;
+        options.check(started)?;
+
+        let outblob = entry.path.join("outblob");
+        let quote = match tsm_fs.read(&outblob) {
+            Err(e) => return Err(tsm_error(&outblob, e)),
+            Ok(q) => q,
+        };
+
+        check_generation(tsm_fs, &entry.path, expected)?;
+        Ok(quote)
+    }
+}
+
+impl TdxBackend for ConfigfsTsmBackend {
+    fn name(&self) -> &str {
+        "configfs-tsm"
+    }
+
+    fn get_report(&self, report_data: &ReportData) -> Result<Vec<u8>, TdxError> {
+        IoctlBackend.get_report(report_data)
+    }
+
+    fn get_quote(
+        &self,
+        report_data: &ReportData,
+        options: &QuoteOptions,
+    ) -> Result<Vec<u8>, TdxError> {
+        self.get_quote_with_options(report_data, options)
+    }
+
+    fn extend_rtmr(
+        &self,
+        index: u8,
+        digest: [u8; TDX_EXTEND_RTMR_DATA_LEN],
+    ) -> Result<(), RtmrError> {
+        IoctlBackend.extend_rtmr(index, digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::Mutex;
+
+    // behaves like configfs on a plain directory: mkdir creates the attributes, an inblob
+    // write bumps generation by bumps and produces outblob, rmdir removes the attributes
+    struct FakeConfigfs {
+        provider: &'static str,
+        bumps: u64,
+        writes: Mutex<u64>,
+    }
+
+    impl FakeConfigfs {
+        fn new(provider: &'static str, bumps: u64) -> Arc<Self> {
+            Arc::new(FakeConfigfs {
+                provider,
+                bumps,
+                writes: Mutex::new(0),
+            })
+        }
+    }
+
+    impl TsmFs for FakeConfigfs {
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            fs::create_dir(path)?;
+            fs::write(path.join("provider"), format!("{}\n", self.provider))?;
+            fs::write(path.join("generation"), "0\n")
+        }
+
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            fs::remove_dir_all(path)
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            fs::read(path)
+        }
+
+        fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+            fs::write(path, data)?;
+            if path.file_name() == Some("inblob".as_ref()) {
+                let mut writes = self.writes.lock().unwrap();
+                *writes += self.bumps;
+                let entry = path.parent().unwrap();
+                fs::write(entry.join("generation"), format!("{}\n", *writes))?;
+                fs::write(entry.join("outblob"), [b"quote:", data].concat())?;
+            }
+            Ok(())
+        }
+    }
+
+    fn fake_root(test: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("tsm-{}-{}", test, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn quote_from_created_entry() {
+        let root = fake_root("created");
+        let backend =
+            ConfigfsTsmBackend::with_root(&root).tsm_fs(FakeConfigfs::new(TSM_PROVIDER_TDX, 1));
+        let quote = backend.get_quote(&ReportData([1; 64])).unwrap();
+        assert_eq!(quote, [b"quote:".as_slice(), &[1; 64]].concat());
+        //the entry created for the request is gone again
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn quote_from_named_entry() {
+        let root = fake_root("named");
+        let fake = FakeConfigfs::new(TSM_PROVIDER_TDX, 1);
+        fake.create_dir(&root.join("entry")).unwrap();
+        let backend = ConfigfsTsmBackend::with_root(&root)
+            .entry_name("entry")
+            .tsm_fs(fake);
+        let first = backend.get_quote(&ReportData([1; 64])).unwrap();
+        assert_eq!(first, fs::read(root.join("entry/outblob")).unwrap());
+        //the entry is kept and reused, generation continues from its current value
+        let second = backend.get_quote(&ReportData([2; 64])).unwrap();
+        assert_eq!(second, fs::read(root.join("entry/outblob")).unwrap());
+        assert_eq!(
+            fs::read_to_string(root.join("entry/generation")).unwrap(),
+            "2\n"
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_provider() {
+        let root = fake_root("provider");
+        let backend =
+            ConfigfsTsmBackend::with_root(&root).tsm_fs(FakeConfigfs::new("sev_guest", 1));
+        assert_eq!(backend.provider().unwrap(), "sev_guest");
+        let result = backend.get_quote(&ReportData([1; 64]));
+        assert!(matches!(result, Err(TdxError::TsmProvider(p)) if p == "sev_guest"));
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn detects_concurrent_writer() {
+        let root = fake_root("concurrent");
+        //another writer's inblob write lands between ours and our outblob read
+        let backend =
+            ConfigfsTsmBackend::with_root(&root).tsm_fs(FakeConfigfs::new(TSM_PROVIDER_TDX, 2));
+        let result = backend.get_quote(&ReportData([1; 64]));
+        assert!(matches!(
+            result,
+            Err(TdxError::TsmGenerationMismatch {
+                expected: 1,
+                actual: 2
+            })
+        ));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn detects_unchanged_generation() {
+        let root = fake_root("static");
+        //a plain directory: nothing bumps generation
+        let entry = root.join("entry");
+        fs::create_dir(&entry).unwrap();
+        fs::write(entry.join("provider"), "tdx_guest\n").unwrap();
+        fs::write(entry.join("generation"), "0\n").unwrap();
+        fs::write(entry.join("outblob"), b"stale").unwrap();
+        let backend = ConfigfsTsmBackend::with_root(&root).entry_name("entry");
+        let result = backend.get_quote(&ReportData([1; 64]));
+        assert!(matches!(
+            result,
+            Err(TdxError::TsmGenerationMismatch {
+                expected: 1,
+                actual: 0
+            })
+        ));
+        assert!(entry.is_dir());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn honours_cancel() {
+        let root = fake_root("cancel");
+        let backend =
+            ConfigfsTsmBackend::with_root(&root).tsm_fs(FakeConfigfs::new(TSM_PROVIDER_TDX, 1));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let options = QuoteOptions {
+            cancel: Some(cancel),
+            ..QuoteOptions::default()
+        };
+        let result = backend.get_quote_with_options(&ReportData([1; 64]), &options);
+        assert!(matches!(result, Err(TdxError::Cancelled)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,675 @@
+use crate::crl::*;
+use crate::quote::*;
+use anyhow::*;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::result::Result;
+use std::result::Result::Ok;
+use std::time::SystemTime;
+use x509_cert::der::{Decode, DecodePem, Encode};
+use x509_cert::ext::pkix::{BasicConstraints, KeyUsage};
+use x509_cert::Certificate;
+
+// ecdsa-with-SHA256, the only signature algorithm used in the Intel SGX/TDX PKI
+const ECDSA_WITH_SHA256_OID: &str = "1.2.840.10045.4.3.2";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerificationFailure {
+    UnsupportedQuote(String),
+    MissingCertificationData,
+    MalformedCertificate(String),
+    CertificateNotYetValid { subject: String },
+    CertificateExpired { subject: String },
+    PckChainBroken { subject: String, reason: String },
+    IssuerNotCa { subject: String, reason: String },
+    UntrustedRoot { subject: String },
+    QeReportSignatureInvalid(String),
+    QeReportBindingMismatch,
+    QuoteSignatureInvalid(String),
+    NotChecked(String), // an earlier check this one depends on failed
+    CollateralInvalid(String),
+    FmspcMismatch { pck: String, collateral: String },
+    TdxModuleMismatch(String),
+    QeIdentityMismatch(String),
+    TcbLevelNotFound,
+    CrlInvalid(String),
+    CrlExpired { issuer: String },
+    CrlMissing { issuer: String },
+    Revoked { subject: String, serial: String },
+}
+
+impl fmt::Display for VerificationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerificationFailure::UnsupportedQuote(s) => write!(f, "unsupported quote: {}", s),
+            VerificationFailure::MissingCertificationData => {
+                write!(f, "quote carries no PCK certificate chain")
+            }
+            VerificationFailure::MalformedCertificate(s) => {
+                write!(f, "malformed certificate: {}", s)
+            }
+            VerificationFailure::CertificateNotYetValid { subject } => {
+                write!(f, "certificate {} is not yet valid", subject)
+            }
+            VerificationFailure::CertificateExpired { subject } => {
+                write!(f, "certificate {} has expired", subject)
+            }
+            VerificationFailure::PckChainBroken { subject, reason } => {
+                write!(f, "PCK chain broken at {}: {}", subject, reason)
+            }
+            VerificationFailure::IssuerNotCa { subject, reason } => {
+                write!(f, "issuer {} is not a CA: {}", subject, reason)
+            }
+            VerificationFailure::UntrustedRoot { subject } => {
+                write!(
+                    f,
+                    "chain does not end at the trusted root, top is {}",
+                    subject
+                )
+            }
+            VerificationFailure::QeReportSignatureInvalid(s) => {
+                write!(f, "QE report signature invalid: {}", s)
+            }
+            VerificationFailure::QeReportBindingMismatch => {
+                write!(f, "QE report data does not bind the attestation key")
+            }
+            VerificationFailure::QuoteSignatureInvalid(s) => {
+                write!(f, "quote signature invalid: {}", s)
+            }
+            VerificationFailure::NotChecked(s) => write!(f, "not checked: {}", s),
+            VerificationFailure::CollateralInvalid(s) => write!(f, "collateral invalid: {}", s),
+            VerificationFailure::FmspcMismatch { pck, collateral } => write!(
+                f,
+                "PCK certificate FMSPC {} does not match collateral FMSPC {}",
+                pck, collateral
+            ),
+            VerificationFailure::TdxModuleMismatch(s) => {
+                write!(f, "TDX module does not match collateral: {}", s)
+            }
+            VerificationFailure::QeIdentityMismatch(s) => {
+                write!(f, "QE does not match QE identity: {}", s)
+            }
+            VerificationFailure::TcbLevelNotFound => {
+                write!(f, "no TCB level in the collateral matches the platform")
+            }
+            VerificationFailure::CrlInvalid(s) => write!(f, "CRL invalid: {}", s),
+            VerificationFailure::CrlExpired { issuer } => {
+                write!(f, "CRL of {} has passed its next update", issuer)
+            }
+            VerificationFailure::CrlMissing { issuer } => {
+                write!(f, "no CRL provided for {}", issuer)
+            }
+            VerificationFailure::Revoked { subject, serial } => {
+                write!(f, "certificate {} (serial {}) is revoked", subject, serial)
+            }
+        }
+    }
+}
+
+pub type CheckResult = Result<(), VerificationFailure>;
+
+/// Outcome of each step of the offline ECDSA quote verification.
+#[derive(Clone, Debug)]
+pub struct QuoteVerdict {
+    pub quote_format: CheckResult, // attestation key type and QE certification data
+    pub pck_chain: CheckResult,
+    pub qe_report_signature: CheckResult,
+    pub qe_report_binding: CheckResult,
+    pub quote_signature: CheckResult,
+    pub revocation: Option<CheckResult>, // only checked when CRLs are supplied
+    pub pck_chain_certs: Vec<Certificate>, // leaf first, as embedded in the quote
+}
+
+impl QuoteVerdict {
+    // the quote cannot be checked further, failure belongs to quote_format
+    fn rejected(failure: VerificationFailure) -> Self {
+        let not_checked = Err(VerificationFailure::NotChecked(failure.to_string()));
+        QuoteVerdict {
+            quote_format: Err(failure),
+            pck_chain: not_checked.clone(),
+            qe_report_signature: not_checked.clone(),
+            qe_report_binding: not_checked.clone(),
+            quote_signature: not_checked,
+            revocation: None,
+            pck_chain_certs: Vec::new(),
+        }
+    }
+
+    // the PCK chain embedded in the quote is missing or unreadable
+    fn chain_rejected(failure: VerificationFailure) -> Self {
+        let not_checked = Err(VerificationFailure::NotChecked(failure.to_string()));
+        QuoteVerdict {
+            quote_format: Ok(()),
+            pck_chain: Err(failure),
+            qe_report_signature: not_checked.clone(),
+            qe_report_binding: not_checked.clone(),
+            quote_signature: not_checked,
+            revocation: None,
+            pck_chain_certs: Vec::new(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.failures().is_empty()
+    }
+
+    pub fn failures(&self) -> Vec<&VerificationFailure> {
+        [
+            &self.quote_format,
+            &self.pck_chain,
+            &self.qe_report_signature,
+            &self.qe_report_binding,
+            &self.quote_signature,
+        ]
+        .into_iter()
+        .chain(self.revocation.as_ref())
+        .filter_map(|r| r.as_ref().err())
+        .collect()
+    }
+
+    pub fn pck_leaf(&self) -> Option<&Certificate> {
+        self.pck_chain_certs.first()
+    }
+}
+
+// accept a single PEM or DER encoded certificate
+pub fn load_certificate(bytes: &[u8]) -> Result<Certificate, anyhow::Error> {
+    let parsed = if bytes.starts_with(b"-----BEGIN") {
+        Certificate::from_pem(bytes)
+    } else {
+        Certificate::from_der(bytes)
+    };
+    match parsed {
+        Err(e) => Err(anyhow!(
+            "[load_certificate] Fail to parse certificate: {:?}",
+            e
+        )),
+        Ok(cert) => Ok(cert),
+    }
+}
+
+// Intel appends a NUL terminator to the PEM chain in certification data type 5
+pub fn load_pem_chain(bytes: &[u8]) -> Result<Vec<Certificate>, anyhow::Error> {
+    let end = bytes
+        .iter()
+        .rposition(|b| !matches!(b, 0 | b'\r' | b'\n' | b' '))
+        .map_or(0, |p| p + 1);
+    if end == 0 {
+        return Err(anyhow!("[load_pem_chain] Empty certificate chain"));
+    }
+    match Certificate::load_pem_chain(&bytes[0..end]) {
+        Err(e) => Err(anyhow!(
+            "[load_pem_chain] Fail to parse certificate chain: {:?}",
+            e
+        )),
+        Ok(chain) => Ok(chain),
+    }
+}
+
+pub fn subject_name(cert: &Certificate) -> String {
+    cert.tbs_certificate.subject.to_string()
+}
+
+pub fn verifying_key(cert: &Certificate) -> Result<VerifyingKey, anyhow::Error> {
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    match VerifyingKey::from_sec1_bytes(spki.subject_public_key.raw_bytes()) {
+        Err(e) => Err(anyhow!(
+            "[verifying_key] {} does not carry a P-256 key: {:?}",
+            subject_name(cert),
+            e
+        )),
+        Ok(key) => Ok(key),
+    }
+}
+
+// check an ecdsa-with-SHA256 signature over DER encoded data, as used by certificates and CRLs
+pub fn verify_der_signature(
+    issuer: &Certificate,
+    algorithm: &str,
+    signed_der: &[u8],
+    signature_der: &[u8],
+) -> Result<(), anyhow::Error> {
+    if algorithm != ECDSA_WITH_SHA256_OID {
+        return Err(anyhow!(
+            "[verify_der_signature] Unsupported signature algorithm {}",
+            algorithm
+        ));
+    }
+    let signature = match Signature::from_der(signature_der) {
+        Err(e) => return Err(anyhow!("[verify_der_signature] Bad signature: {:?}", e)),
+        Ok(s) => s,
+    };
+    match verifying_key(issuer)?.verify(signed_der, &signature) {
+        Err(e) => Err(anyhow!(
+            "[verify_der_signature] Signature does not verify with {}: {:?}",
+            subject_name(issuer),
+            e
+        )),
+        Ok(_) => Ok(()),
+    }
+}
+
+pub fn verify_cert_signature(
+    cert: &Certificate,
+    issuer: &Certificate,
+) -> Result<(), anyhow::Error> {
+    if cert.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+        return Err(anyhow!(
+            "[verify_cert_signature] Issuer {} does not match {}",
+            cert.tbs_certificate.issuer,
+            subject_name(issuer)
+        ));
+    }
+    let tbs = match cert.tbs_certificate.to_der() {
+        Err(e) => return Err(anyhow!("[verify_cert_signature] {:?}", e)),
+        Ok(t) => t,
+    };
+    verify_der_signature(
+        issuer,
+        &cert.signature_algorithm.oid.to_string(),
+        &tbs,
+        cert.signature.raw_bytes(),
+    )
+}
+
+fn check_validity(cert: &Certificate, now: SystemTime) -> CheckResult {
+    let validity = &cert.tbs_certificate.validity;
+    if now < validity.not_before.to_system_time() {
+        return Err(VerificationFailure::CertificateNotYetValid {
+            subject: subject_name(cert),
+        });
+    }
+    if now > validity.not_after.to_system_time() {
+        return Err(VerificationFailure::CertificateExpired {
+            subject: subject_name(cert),
+        });
+    }
+    Ok(())
+}
+
+// RFC 5280 6.1.4: an issuer must be a CA with keyCertSign, and its pathLenConstraint must
+// allow the non-self-issued intermediates below it
+fn check_issuer(issuer: &Certificate, intermediates_below: usize) -> CheckResult {
+    let not_ca = |reason: &str| {
+        Err(VerificationFailure::IssuerNotCa {
+            subject: subject_name(issuer),
+            reason: reason.to_string(),
+        })
+    };
+    match issuer.tbs_certificate.get::<BasicConstraints>() {
+        Err(_) => return not_ca("malformed basicConstraints"),
+        Ok(None) => return not_ca("no basicConstraints"),
+        Ok(Some((_, constraints))) if !constraints.ca => return not_ca("cA is false"),
+        Ok(Some((_, constraints))) => {
+            if let Some(path_len) = constraints.path_len_constraint {
+                if intermediates_below > path_len as usize {
+                    return not_ca(&format!(
+                        "pathLenConstraint {} but {} intermediate certificates below",
+                        path_len, intermediates_below
+                    ));
+                }
+            }
+        }
+    }
+    match issuer.tbs_certificate.get::<KeyUsage>() {
+        Err(_) => not_ca("malformed keyUsage"),
+        Ok(Some((_, usage))) if !usage.key_cert_sign() => not_ca("keyUsage lacks keyCertSign"),
+        _ => Ok(()),
+    }
+}
+
+fn is_self_issued(cert: &Certificate) -> bool {
+    cert.tbs_certificate.issuer == cert.tbs_certificate.subject
+}
+
+// walk leaf -> intermediate -> root (PCK or TCB signing chain) and require the top
+// to be, or be issued by, the trusted root
+pub fn verify_cert_chain(
+    chain: &[Certificate],
+    trusted_root: &Certificate,
+    now: SystemTime,
+) -> CheckResult {
+    if chain.is_empty() {
+        return Err(VerificationFailure::MissingCertificationData);
+    }
+    for (i, cert) in chain.iter().enumerate() {
+        check_validity(cert, now)?;
+        let issuer = match chain.get(i + 1) {
+            Some(issuer) => issuer,
+            None if cert == trusted_root => trusted_root,
+            None => {
+                if cert.tbs_certificate.issuer == cert.tbs_certificate.subject {
+                    return Err(VerificationFailure::UntrustedRoot {
+                        subject: subject_name(cert),
+                    });
+                }
+                trusted_root
+            }
+        };
+        if let Err(e) = verify_cert_signature(cert, issuer) {
+            return Err(VerificationFailure::PckChainBroken {
+                subject: subject_name(cert),
+                reason: e.to_string(),
+            });
+        }
+        //certificates between the leaf and this issuer, a self-signed root not counting
+        let intermediates_below = chain[1..=i].iter().filter(|c| !is_self_issued(c)).count();
+        check_issuer(issuer, intermediates_below)?;
+    }
+    check_validity(trusted_root, now)
+}
+
+fn raw_p256_key(key: &[u8; 64]) -> Result<VerifyingKey, anyhow::Error> {
+    let mut sec1 = [0u8; 65];
+    sec1[0] = 0x04;
+    sec1[1..].copy_from_slice(key);
+    match VerifyingKey::from_sec1_bytes(&sec1) {
+        Err(e) => Err(anyhow!("[raw_p256_key] Bad attestation key: {:?}", e)),
+        Ok(k) => Ok(k),
+    }
+}
+
+pub fn verify_raw_signature(
+    key: &VerifyingKey,
+    msg: &[u8],
+    signature: &[u8; 64],
+) -> Result<(), anyhow::Error> {
+    let signature = match Signature::from_slice(signature) {
+        Err(e) => return Err(anyhow!("[verify_raw_signature] Bad signature: {:?}", e)),
+        Ok(s) => s,
+    };
+    match key.verify(msg, &signature) {
+        Err(e) => Err(anyhow!("[verify_raw_signature] {:?}", e)),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Verify a quote against a caller provided Intel SGX Root CA, using the current time.
+pub fn verify_quote(quote: &Quote, trusted_root: &Certificate) -> QuoteVerdict {
+    verify_quote_at(quote, trusted_root, SystemTime::now())
+}
+
+pub fn verify_quote_at(quote: &Quote, trusted_root: &Certificate, now: SystemTime) -> QuoteVerdict {
+    if quote.header.att_key_type != ATT_KEY_TYPE_ECDSA_P256 {
+        return QuoteVerdict::rejected(VerificationFailure::UnsupportedQuote(format!(
+            "attestation key type {}",
+            quote.header.att_key_type
+        )));
+    }
+    let qe_cert_data = match quote.signature_data.qe_report_data() {
+        None => return QuoteVerdict::rejected(VerificationFailure::MissingCertificationData),
+        Some(q) => q,
+    };
+    let chain_pem = match qe_cert_data.certification_data.pck_cert_chain() {
+        None => return QuoteVerdict::chain_rejected(VerificationFailure::MissingCertificationData),
+        Some(c) => c,
+    };
+    let chain = match load_pem_chain(chain_pem) {
+        Err(e) => {
+            return QuoteVerdict::chain_rejected(VerificationFailure::MalformedCertificate(
+                e.to_string(),
+            ))
+        }
+        Ok(c) => c,
+    };
+
+    let pck_chain = verify_cert_chain(&chain, trusted_root, now);
+
+    //the QE report is signed by the PCK leaf
+    let qe_report_signature = match verifying_key(&chain[0]) {
+        Err(e) => Err(VerificationFailure::MalformedCertificate(e.to_string())),
+        Ok(pck_key) => verify_raw_signature(
+            &pck_key,
+            &qe_cert_data.qe_report.raw,
+            &qe_cert_data.qe_report_signature,
+        )
+        .map_err(|e| VerificationFailure::QeReportSignatureInvalid(e.to_string())),
+    };
+
+    //QE REPORTDATA = SHA256(attestation key || QE auth data) || 32 zero bytes
+    let mut hasher = Sha256::new();
+    hasher.update(quote.signature_data.attestation_key);
+    hasher.update(&qe_cert_data.qe_auth_data);
+    let expected = hasher.finalize();
+    let report_data = &qe_cert_data.qe_report.report_data;
+    let qe_report_binding =
+        if report_data[0..32] == expected[..] && report_data[32..].iter().all(|b| *b == 0) {
+            Ok(())
+        } else {
+            Err(VerificationFailure::QeReportBindingMismatch)
+        };
+
+    //the attestation key signs the header and TD body
+    let quote_signature = match raw_p256_key(&quote.signature_data.attestation_key) {
+        Err(e) => Err(VerificationFailure::QuoteSignatureInvalid(e.to_string())),
+        Ok(key) => verify_raw_signature(&key, quote.signed_data(), &quote.signature_data.signature)
+            .map_err(|e| VerificationFailure::QuoteSignatureInvalid(e.to_string())),
+    };
+
+    QuoteVerdict {
+        quote_format: Ok(()),
+        pck_chain,
+        qe_report_signature,
+        qe_report_binding,
+        quote_signature,
+        revocation: None,
+        pck_chain_certs: chain,
+    }
+}
+
+/// Same as `verify_quote_at`, additionally rejecting chains with a revoked PCK or intermediate
+/// certificate according to the supplied Root CA and PCK Platform/Processor CRLs.
+pub fn verify_quote_with_crls(
+    quote: &Quote,
+    trusted_root: &Certificate,
+    crls: &[Crl],
+    now: SystemTime,
+) -> QuoteVerdict {
+    let mut verdict = verify_quote_at(quote, trusted_root, now);
+    verdict.revocation = Some(if verdict.pck_chain_certs.is_empty() {
+        Err(VerificationFailure::NotChecked(
+            "no PCK certificate chain".to_string(),
+        ))
+    } else {
+        check_revocation(&verdict.pck_chain_certs, trusted_root, crls, now)
+    });
+    verdict
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::*;
+    use crate::tee_tdx_lib::ReportData;
+    use p256::ecdsa::{DerSignature, SigningKey};
+    use rand_core::OsRng;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use x509_cert::builder::{Builder, CertificateBuilder, Profile};
+    use x509_cert::name::Name;
+    use x509_cert::serial_number::SerialNumber;
+    use x509_cert::spki::SubjectPublicKeyInfoOwned;
+    use x509_cert::time::Validity;
+
+    const MRTD_OFFSET: usize = 48 + 136; // quote header, then the TD body up to MRTD
+
+    fn simulated_quote(pki: &SimulatedPki) -> Vec<u8> {
+        let backend = SimulatedBackend::with_keys(
+            SimulatedTdConfig::default(),
+            SigningKey::random(&mut OsRng),
+            pki.pck_key.clone(),
+            pki.chain.clone(),
+        )
+        .unwrap();
+        let report = backend.build_report(&ReportData([7; 64]));
+        backend.quote_for_report(&report).unwrap()
+    }
+
+    fn issue(
+        profile: Profile,
+        serial: u32,
+        subject: &str,
+        key: &SigningKey,
+        issuer_key: &SigningKey,
+    ) -> Certificate {
+        CertificateBuilder::new(
+            profile,
+            SerialNumber::from(serial),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            Name::from_str(subject).unwrap(),
+            SubjectPublicKeyInfoOwned::from_key(*key.verifying_key()).unwrap(),
+            issuer_key,
+        )
+        .unwrap()
+        .build::<DerSignature>()
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_quote() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let quote = Quote::from_bytes(&simulated_quote(&pki)).unwrap();
+        let verdict = verify_quote_at(&quote, pki.root_certificate(), SystemTime::now());
+        assert!(verdict.is_valid(), "{:?}", verdict.failures());
+        assert_eq!(verdict.pck_chain_certs, pki.chain);
+    }
+
+    #[test]
+    fn tampered_body() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let mut raw = simulated_quote(&pki);
+        raw[MRTD_OFFSET] ^= 1;
+        let quote = Quote::from_bytes(&raw).unwrap();
+        let verdict = verify_quote_at(&quote, pki.root_certificate(), SystemTime::now());
+        assert!(matches!(
+            verdict.quote_signature,
+            Err(VerificationFailure::QuoteSignatureInvalid(_))
+        ));
+        assert_eq!(verdict.failures().len(), 1);
+    }
+
+    #[test]
+    fn wrong_qe_report_binding() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let mut quote = Quote::from_bytes(&simulated_quote(&pki)).unwrap();
+        let qe_cert_data = quote
+            .signature_data
+            .certification_data
+            .qe_report_data
+            .as_mut()
+            .unwrap();
+        qe_cert_data.qe_auth_data[0] ^= 1;
+        let verdict = verify_quote_at(&quote, pki.root_certificate(), SystemTime::now());
+        assert!(matches!(
+            verdict.qe_report_binding,
+            Err(VerificationFailure::QeReportBindingMismatch)
+        ));
+        assert_eq!(verdict.failures().len(), 1);
+    }
+
+    #[test]
+    fn untrusted_root() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let other = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let quote = Quote::from_bytes(&simulated_quote(&pki)).unwrap();
+        let verdict = verify_quote_at(&quote, other.root_certificate(), SystemTime::now());
+        assert!(matches!(
+            verdict.pck_chain,
+            Err(VerificationFailure::UntrustedRoot { .. })
+        ));
+    }
+
+    #[test]
+    fn expired_chain() {
+        let pki = SimulatedPki::new(&SimulatedTdConfig::default()).unwrap();
+        let quote = Quote::from_bytes(&simulated_quote(&pki)).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(2 * 365 * 24 * 3600);
+        let verdict = verify_quote_at(&quote, pki.root_certificate(), later);
+        assert!(matches!(
+            verdict.pck_chain,
+            Err(VerificationFailure::CertificateExpired { .. })
+        ));
+    }
+
+    #[test]
+    fn issuer_not_ca() {
+        let root_key = SigningKey::random(&mut OsRng);
+        let issuer_key = SigningKey::random(&mut OsRng);
+        let leaf_key = SigningKey::random(&mut OsRng);
+        let root = issue(Profile::Root, 1, "CN=Test Root", &root_key, &root_key);
+        let issuer = issue(
+            Profile::Leaf {
+                issuer: root.tbs_certificate.subject.clone(),
+                enable_key_agreement: false,
+                enable_key_encipherment: false,
+            },
+            2,
+            "CN=Test Leaf",
+            &issuer_key,
+            &root_key,
+        );
+        let leaf = issue(
+            Profile::Leaf {
+                issuer: issuer.tbs_certificate.subject.clone(),
+                enable_key_agreement: false,
+                enable_key_encipherment: false,
+            },
+            3,
+            "CN=Test PCK",
+            &leaf_key,
+            &issuer_key,
+        );
+        let result = verify_cert_chain(&[leaf, issuer, root.clone()], &root, SystemTime::now());
+        assert!(matches!(
+            result,
+            Err(VerificationFailure::IssuerNotCa { subject, .. }) if subject == "CN=Test Leaf"
+        ));
+    }
+
+    #[test]
+    fn path_len_exceeded() {
+        let root_key = SigningKey::random(&mut OsRng);
+        let ca_key = SigningKey::random(&mut OsRng);
+        let sub_ca_key = SigningKey::random(&mut OsRng);
+        let leaf_key = SigningKey::random(&mut OsRng);
+        let root = issue(Profile::Root, 1, "CN=Test Root", &root_key, &root_key);
+        let ca = issue(
+            Profile::SubCA {
+                issuer: root.tbs_certificate.subject.clone(),
+                path_len_constraint: Some(0),
+            },
+            2,
+            "CN=Test CA",
+            &ca_key,
+            &root_key,
+        );
+        let sub_ca = issue(
+            Profile::SubCA {
+                issuer: ca.tbs_certificate.subject.clone(),
+                path_len_constraint: Some(0),
+            },
+            3,
+            "CN=Test Sub CA",
+            &sub_ca_key,
+            &ca_key,
+        );
+        let leaf = issue(
+            Profile::Leaf {
+                issuer: sub_ca.tbs_certificate.subject.clone(),
+                enable_key_agreement: false,
+                enable_key_encipherment: false,
+            },
+            4,
+            "CN=Test PCK",
+            &leaf_key,
+            &sub_ca_key,
+        );
+        let result = verify_cert_chain(&[leaf, sub_ca, ca, root.clone()], &root, SystemTime::now());
+        assert!(matches!(
+            result,
+            Err(VerificationFailure::IssuerNotCa { subject, .. }) if subject == "CN=Test CA"
+        ));
+    }
+}